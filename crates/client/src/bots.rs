@@ -0,0 +1,175 @@
+use crate::{Args, HEADLESS_FRAME_TIME, NetState, Prediction};
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use shared::{LocalPlayer, NetworkError, Pong, ToggleReady};
+use std::collections::BTreeMap;
+use std::f32::consts::TAU;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time the last frame of a stopping bot gets to send its disconnect before the app is dropped
+const CLOSE_GRACE: Duration = Duration::from_millis(100);
+
+/// Chance of a bot standing still for an interval instead of walking somewhere
+const IDLE_CHANCE: f32 = 0.2;
+
+#[derive(Resource)]
+/// A bot's random walk and what it saw of the server, present only in `--bots` apps
+struct Bot {
+    intent: Timer,
+    joined: bool,
+    /// Round-trip times in milliseconds, in the order the pongs arrived
+    rtts: Vec<f32>,
+    /// Network errors in the order they happened
+    errors: Vec<String>,
+}
+
+/// Runs `--bots` headless clients on their own threads until `--bot-secs` pass or Ctrl-C, then
+/// disconnects them all and prints how they fared
+pub fn run_bots(args: Args) -> AppExit {
+    let count = args.bots.map_or(1, usize::from);
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed)) {
+        eprintln!("Failed to set Ctrl-C handler: {e:?}");
+    }
+    let duration = args.bot_secs.map(Duration::from_secs_f32);
+
+    println!("Starting {count} bots");
+    let started = Instant::now();
+    let bots: Vec<_> = (0..count)
+        .map(|index| {
+            let mut bot_args = args.clone();
+            bot_args.name = Some(format!("Bot-{}", index + 1));
+            let stop = stop.clone();
+            thread::spawn(move || run_bot(bot_args, &stop))
+        })
+        .collect();
+
+    while !stop.load(Ordering::Relaxed) {
+        if duration.is_some_and(|duration| started.elapsed() >= duration) {
+            stop.store(true, Ordering::Relaxed);
+        }
+        thread::sleep(HEADLESS_FRAME_TIME);
+    }
+
+    // A bot that panicked is reported as one that never joined.
+    let reports: Vec<Option<Bot>> = bots.into_iter().map(|bot| bot.join().ok()).collect();
+    println!("{}", summary(&reports));
+    if reports.iter().flatten().filter(|bot| bot.joined).count() == count {
+        AppExit::Success
+    } else {
+        AppExit::error()
+    }
+}
+
+fn run_bot(args: Args, stop: &AtomicBool) -> Bot {
+    let mut app = build_bot_app(args);
+    while !stop.load(Ordering::Relaxed) {
+        app.update();
+        thread::sleep(HEADLESS_FRAME_TIME);
+    }
+
+    // The regular exit handling tells the server and closes the connection.
+    app.world_mut().write_message(AppExit::Success);
+    app.update();
+    thread::sleep(CLOSE_GRACE);
+    app.world_mut()
+        .remove_resource::<Bot>()
+        .expect("bot apps keep their Bot")
+}
+
+fn build_bot_app(args: Args) -> App {
+    let rate = args.bot_rate;
+    let mut app = crate::build_app(args, crate::configure_headless_plugins);
+    app.insert_resource(Bot {
+        intent: Timer::from_seconds(1.0 / rate, TimerMode::Repeating),
+        joined: false,
+        rtts: Vec::new(),
+        errors: Vec::new(),
+    });
+    app.add_systems(OnEnter(NetState::InGame), join_as_bot);
+    app.add_systems(
+        Update,
+        (
+            walk_randomly.run_if(in_state(NetState::InGame)),
+            record_network_errors.run_if(resource_exists_and_changed::<NetworkError>),
+        ),
+    );
+    app.add_observer(record_bot_pong);
+    // Steps are driven by `run_bot`, which is what `App::run` would do otherwise.
+    app.finish();
+    app.cleanup();
+    app
+}
+
+/// Readies up right away, so bots never hold a lobby back from starting
+fn join_as_bot(mut bot: ResMut<Bot>, mut commands: Commands) {
+    bot.joined = true;
+    commands.client_trigger(ToggleReady);
+}
+
+fn walk_randomly(
+    mut bot: ResMut<Bot>,
+    mut players: Query<&mut Prediction, With<LocalPlayer>>,
+    time: Res<Time<Real>>,
+    mut commands: Commands,
+) {
+    if !bot.intent.tick(time.delta()).just_finished() {
+        return;
+    }
+    let Ok(mut prediction) = players.single_mut() else {
+        return;
+    };
+    let direction = if rand::random::<f32>() < IDLE_CHANCE {
+        Vec2::ZERO
+    } else {
+        Vec2::from_angle(rand::random::<f32>() * TAU)
+    };
+    crate::send_movement_intent(&mut prediction, direction, &mut commands);
+}
+
+fn record_network_errors(error: Res<NetworkError>, mut bot: ResMut<Bot>) {
+    bot.errors.push(error.0.clone());
+}
+
+fn record_bot_pong(pong: On<Pong>, mut bot: ResMut<Bot>, time: Res<Time<Real>>) {
+    let now_ms = time.elapsed().as_millis() as u64;
+    bot.rtts
+        .push(now_ms.saturating_sub(pong.client_time_ms) as f32);
+}
+
+fn summary(reports: &[Option<Bot>]) -> String {
+    let joined = reports.iter().flatten().filter(|bot| bot.joined).count();
+    let mut summary = format!("{joined} of {} bots joined", reports.len());
+
+    let mut rtts: Vec<f32> = reports
+        .iter()
+        .flatten()
+        .flat_map(|bot| bot.rtts.iter().copied())
+        .collect();
+    rtts.sort_by(f32::total_cmp);
+    if let (Some(min), Some(max)) = (rtts.first(), rtts.last()) {
+        let average = rtts.iter().sum::<f32>() / rtts.len() as f32;
+        let p95 = rtts[(rtts.len() - 1) * 95 / 100];
+        summary += &format!(
+            "\nRTT over {} pongs: min {min:.0} ms, average {average:.1} ms, p95 {p95:.0} ms, max {max:.0} ms",
+            rtts.len()
+        );
+    }
+
+    let panicked = reports.iter().filter(|bot| bot.is_none()).count();
+    if panicked > 0 {
+        summary += &format!("\n{panicked} bots panicked");
+    }
+    let mut errors: BTreeMap<&str, usize> = BTreeMap::new();
+    for error in reports.iter().flatten().flat_map(|bot| &bot.errors) {
+        *errors.entry(error).or_default() += 1;
+    }
+    for (error, count) in errors {
+        summary += &format!("\n{count}x {error}");
+    }
+    summary
+}
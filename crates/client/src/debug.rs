@@ -0,0 +1,218 @@
+use bevy::prelude::*;
+use bevy_egui::{EguiContexts, EguiPrimaryContextPass, egui};
+use bevy_enhanced_input::prelude::*;
+use bevy_replicon::prelude::*;
+use shared::{LocalPlayer, NetPosition, Noclip, PerfStats, Player, PlayerName, SetNoclip};
+use std::collections::{HashMap, VecDeque};
+
+/// Corrections kept per remote player for the plot
+const HISTORY_LEN: usize = 120;
+
+/// Correction in world units that fills the height of a plot
+const PLOT_SCALE: f32 = 50.0;
+
+#[derive(Resource, Default)]
+/// How far remote players jump when authoritative positions arrive, for tuning interpolation
+struct ReplicationDebug {
+    visible: bool,
+    /// Position each remote player was drawn at last frame
+    displayed: HashMap<Entity, Vec2>,
+    /// Distance between the drawn position and each authoritative one received, oldest first
+    corrections: HashMap<Entity, VecDeque<f32>>,
+}
+
+#[derive(Component)]
+/// Input context for debugging tools
+struct DebugControls;
+
+#[derive(InputAction)]
+#[action_output(bool)]
+/// Shows or hides the replication debug overlay
+struct ToggleReplicationDebug;
+
+#[derive(InputAction)]
+#[action_output(bool)]
+/// Asks the server to turn noclip on or off for the local player, which only admins get
+struct ToggleNoclip;
+
+/// Adds the replication debug overlay, toggled with F3
+pub(crate) fn configure_replication_debug(app: &mut App) {
+    app.init_resource::<ReplicationDebug>();
+    app.add_input_context::<DebugControls>();
+
+    app.add_systems(Startup, spawn_debug_controls);
+    app.add_systems(PreUpdate, record_corrections.after(ClientSystems::Receive));
+    app.add_systems(PostUpdate, record_displayed_positions);
+    app.add_systems(EguiPrimaryContextPass, replication_debug_window);
+    app.add_observer(on_toggle_replication_debug);
+    app.add_observer(on_toggle_noclip);
+}
+
+/// Adds a frame time overlay in the bottom left corner
+pub(crate) fn configure_perf_stats(app: &mut App) {
+    app.init_resource::<PerfStats>();
+
+    app.add_systems(Last, record_frame_time);
+    app.add_systems(EguiPrimaryContextPass, perf_overlay);
+}
+
+fn record_frame_time(time: Res<Time<Real>>, mut perf: ResMut<PerfStats>) {
+    perf.record(time.delta());
+}
+
+fn perf_overlay(mut contexts: EguiContexts, perf: Res<PerfStats>) -> Result {
+    if perf.average.is_zero() {
+        return Ok(());
+    }
+
+    egui::Area::new(egui::Id::new("perf_stats"))
+        .anchor(egui::Align2::LEFT_BOTTOM, egui::vec2(8.0, -8.0))
+        .show(contexts.ctx_mut()?, |ui| {
+            ui.label(format!(
+                "{:.0} FPS | {:.2} ms (min {:.2}, max {:.2})",
+                1.0 / perf.average.as_secs_f64(),
+                perf.average.as_secs_f64() * 1000.0,
+                perf.min.as_secs_f64() * 1000.0,
+                perf.max.as_secs_f64() * 1000.0
+            ));
+        });
+    Ok(())
+}
+
+fn spawn_debug_controls(mut commands: Commands) {
+    commands.spawn((
+        DebugControls,
+        actions!(
+            DebugControls[
+                (
+                    Action::<ToggleReplicationDebug>::new(),
+                    bindings![KeyCode::F3],
+                ),
+                (Action::<ToggleNoclip>::new(), bindings![KeyCode::F4]),
+            ]
+        ),
+    ));
+}
+
+fn on_toggle_replication_debug(
+    _toggle: On<Start<ToggleReplicationDebug>>,
+    mut debug: ResMut<ReplicationDebug>,
+) {
+    debug.visible = !debug.visible;
+}
+
+fn on_toggle_noclip(
+    _toggle: On<Start<ToggleNoclip>>,
+    local_player: Query<Has<Noclip>, With<LocalPlayer>>,
+    mut commands: Commands,
+) {
+    if let Ok(noclip) = local_player.single() {
+        commands.client_trigger(SetNoclip(!noclip));
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn record_corrections(
+    players: Query<
+        (Entity, &Transform, Option<Ref<NetPosition>>),
+        (With<Player>, Without<LocalPlayer>),
+    >,
+    mut debug: ResMut<ReplicationDebug>,
+) {
+    let debug = &mut *debug;
+    // Despawned players don't come back, forget them along with their history.
+    debug
+        .corrections
+        .retain(|&entity, _| players.contains(entity));
+
+    for (entity, transform, net_position) in &players {
+        let Some(&displayed) = debug.displayed.get(&entity) else {
+            continue;
+        };
+        // Replicon writes before this runs and interpolation only after, so any change is
+        // an authoritative update.
+        let authoritative = match net_position {
+            Some(net_position) if net_position.is_changed() => net_position.get(),
+            Some(_) => continue,
+            None if transform.translation.xy() != displayed => transform.translation.xy(),
+            None => continue,
+        };
+
+        let history = debug.corrections.entry(entity).or_default();
+        if history.len() >= HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(authoritative.distance(displayed));
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn record_displayed_positions(
+    players: Query<(Entity, &Transform), (With<Player>, Without<LocalPlayer>)>,
+    mut debug: ResMut<ReplicationDebug>,
+) {
+    debug.displayed.clear();
+    debug.displayed.extend(
+        players
+            .iter()
+            .map(|(entity, transform)| (entity, transform.translation.xy())),
+    );
+}
+
+fn replication_debug_window(
+    mut contexts: EguiContexts,
+    debug: Res<ReplicationDebug>,
+    players: Query<(&Player, Option<&PlayerName>)>,
+) -> Result {
+    if !debug.visible {
+        return Ok(());
+    }
+
+    egui::Window::new("Replication debug").show(contexts.ctx_mut()?, |ui| {
+        if debug.corrections.is_empty() {
+            ui.label("No remote player updates yet");
+        }
+
+        let mut entities: Vec<_> = debug.corrections.keys().copied().collect();
+        entities.sort_by_key(|&entity| {
+            players
+                .get(entity)
+                .map(|(player, _)| player.network_id)
+                .ok()
+        });
+        for entity in entities {
+            let history = &debug.corrections[&entity];
+            let name = match players.get(entity) {
+                Ok((_, Some(name))) => name.0.clone(),
+                Ok((player, None)) => player.network_id.to_string(),
+                Err(_) => continue,
+            };
+            let last = history.back().copied().unwrap_or_default();
+            let max = history.iter().copied().fold(0.0, f32::max);
+            ui.label(format!("{name}: last {last:.2} | max {max:.2}"));
+            plot(ui, history);
+        }
+    });
+    Ok(())
+}
+
+/// Draws corrections as a line from oldest to newest, clipped at [`PLOT_SCALE`]
+fn plot(ui: &mut egui::Ui, history: &VecDeque<f32>) {
+    let (response, painter) = ui.allocate_painter(egui::vec2(240.0, 40.0), egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 2.0, egui::Color32::from_black_alpha(96));
+
+    let step = rect.width() / (HISTORY_LEN - 1) as f32;
+    let points = history
+        .iter()
+        .enumerate()
+        .map(|(i, &correction)| {
+            let height = (correction / PLOT_SCALE).min(1.0) * rect.height();
+            egui::pos2(rect.left() + i as f32 * step, rect.bottom() - height)
+        })
+        .collect();
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(1.5, egui::Color32::LIGHT_GREEN),
+    ));
+}
@@ -0,0 +1,154 @@
+use crate::NetState;
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use shared::{NetworkError, Ping, Pong};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How long to wait for the last pongs once the measurement is over
+const LATE_PONG_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Resource)]
+/// Round trips measured by `--diagnose`, present only in that mode
+pub(crate) struct Diagnosis {
+    interval: Timer,
+    /// Counts down while connected, the grace period for late pongs included
+    remaining: Timer,
+    next_id: u32,
+    /// When each ping still waiting for its pong was sent
+    outstanding: HashMap<u32, Duration>,
+    sent: u32,
+    /// Round-trip times in milliseconds, in the order the pongs arrived
+    rtts: Vec<f32>,
+    /// Whether the connection was ever attempted, so going offline afterwards means it failed
+    attempted: bool,
+}
+
+impl Diagnosis {
+    fn new(rate: f32, duration: Duration) -> Self {
+        Self {
+            interval: Timer::from_seconds(1.0 / rate, TimerMode::Repeating),
+            remaining: Timer::new(duration + LATE_PONG_GRACE, TimerMode::Once),
+            next_id: 0,
+            outstanding: HashMap::new(),
+            sent: 0,
+            rtts: Vec::new(),
+            attempted: false,
+        }
+    }
+
+    /// Whether pings are still being sent, they stop once only the grace period is left
+    fn sending(&self) -> bool {
+        self.remaining.remaining() > LATE_PONG_GRACE
+    }
+
+    fn summary(&self) -> String {
+        let received = self.rtts.len();
+        let loss = if self.sent > 0 {
+            100.0 * (self.sent as usize - received) as f32 / self.sent as f32
+        } else {
+            0.0
+        };
+        let mut summary = format!(
+            "{} pings sent, {received} answered, {loss:.1}% lost",
+            self.sent
+        );
+        if received > 0 {
+            let min = self.rtts.iter().copied().fold(f32::MAX, f32::min);
+            let max = self.rtts.iter().copied().fold(0.0, f32::max);
+            let average = self.rtts.iter().sum::<f32>() / received as f32;
+            // Mean difference between consecutive round trips, as RTP reports jitter.
+            let jitter = self
+                .rtts
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).abs())
+                .sum::<f32>()
+                / (received - 1).max(1) as f32;
+            summary += &format!(
+                "\nRTT min {min:.1} ms, average {average:.1} ms, max {max:.1} ms, jitter {jitter:.1} ms"
+            );
+        }
+        summary
+    }
+}
+
+/// Turns the client into a network diagnostic that pings the server for `--diagnose-secs`, prints
+/// what it measured and exits, without joining the game
+pub(crate) fn configure_diagnosis(app: &mut App) {
+    let args = app.world().resource::<crate::Args>();
+    let diagnosis = Diagnosis::new(
+        args.diagnose_rate,
+        Duration::from_secs_f32(args.diagnose_secs),
+    );
+    app.insert_resource(diagnosis);
+
+    app.add_systems(
+        Update,
+        (
+            send_diagnostic_pings.run_if(in_state(NetState::Connected)),
+            finish_diagnosis,
+        )
+            .chain(),
+    );
+    app.add_observer(on_diagnostic_pong);
+}
+
+fn send_diagnostic_pings(
+    mut diagnosis: ResMut<Diagnosis>,
+    time: Res<Time<Real>>,
+    mut commands: Commands,
+) {
+    diagnosis.remaining.tick(time.delta());
+    if !diagnosis.sending() || !diagnosis.interval.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let id = diagnosis.next_id;
+    diagnosis.next_id = diagnosis.next_id.wrapping_add(1);
+    diagnosis.sent += 1;
+    diagnosis.outstanding.insert(id, time.elapsed());
+    commands.client_trigger(Ping {
+        id,
+        client_time_ms: time.elapsed().as_millis() as u64,
+    });
+}
+
+fn on_diagnostic_pong(pong: On<Pong>, mut diagnosis: ResMut<Diagnosis>, time: Res<Time<Real>>) {
+    let Some(sent_at) = diagnosis.outstanding.remove(&pong.id) else {
+        return;
+    };
+    // Timed locally in full precision, the echoed milliseconds only identify the ping.
+    let rtt = time.elapsed().saturating_sub(sent_at);
+    diagnosis.rtts.push(rtt.as_secs_f32() * 1000.0);
+}
+
+fn finish_diagnosis(
+    mut diagnosis: ResMut<Diagnosis>,
+    state: Res<State<NetState>>,
+    network_error: Option<Res<NetworkError>>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    if *state.get() != NetState::Offline {
+        diagnosis.attempted = true;
+    }
+
+    let failure = match (network_error, state.get()) {
+        (Some(error), _) => Some(error.0.clone()),
+        (None, NetState::Offline) if diagnosis.attempted => Some("Connection lost".to_string()),
+        _ => None,
+    };
+    if let Some(failure) = failure {
+        eprintln!("Diagnosis failed: {failure}");
+        if diagnosis.sent > 0 {
+            eprintln!("{}", diagnosis.summary());
+        }
+        exit.write(AppExit::error());
+        return;
+    }
+
+    if diagnosis.remaining.is_finished() {
+        println!("{}", diagnosis.summary());
+        // Closing the connection is left to the regular exit handling.
+        exit.write(AppExit::Success);
+    }
+}
@@ -0,0 +1,166 @@
+use bevy::prelude::*;
+use bevy_enhanced_input::prelude::*;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// A button an action can be bound to, written as e.g. `{ "key": "KeyW" }`,
+/// `{ "mouse": "Left" }` or `{ "gamepad": "South" }`
+pub enum InputButton {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+impl From<InputButton> for Binding {
+    fn from(button: InputButton) -> Self {
+        match button {
+            InputButton::Key(key) => key.into(),
+            InputButton::Mouse(button) => button.into(),
+            InputButton::Gamepad(button) => button.into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// A gamepad stick movement can be bound to
+pub enum Stick {
+    Left,
+    Right,
+}
+
+impl Stick {
+    fn axes(self) -> (GamepadAxis, GamepadAxis) {
+        match self {
+            Stick::Left => (GamepadAxis::LeftStickX, GamepadAxis::LeftStickY),
+            Stick::Right => (GamepadAxis::RightStickX, GamepadAxis::RightStickY),
+        }
+    }
+}
+
+#[derive(Resource, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+/// Inputs the local player's actions are bound to, read from the `--keybindings` JSON file.
+///
+/// Actions the file leaves out keep their defaults, e.g. `{ "move_up": [{ "key": "KeyI" }] }`
+/// only rebinds moving up. Replacing the resource rebinds a spawned player.
+pub struct KeyBindings {
+    pub move_up: Vec<InputButton>,
+    pub move_down: Vec<InputButton>,
+    pub move_left: Vec<InputButton>,
+    pub move_right: Vec<InputButton>,
+    /// Sticks that move in every direction on top of the buttons
+    pub move_stick: Vec<Stick>,
+    pub shoot: Vec<InputButton>,
+    /// Left empty to never dash
+    pub dash: Vec<InputButton>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let keys = |keys: &[KeyCode]| keys.iter().copied().map(InputButton::Key).collect();
+        Self {
+            move_up: keys(&[KeyCode::KeyW, KeyCode::ArrowUp]),
+            move_down: keys(&[KeyCode::KeyS, KeyCode::ArrowDown]),
+            move_left: keys(&[KeyCode::KeyA, KeyCode::ArrowLeft]),
+            move_right: keys(&[KeyCode::KeyD, KeyCode::ArrowRight]),
+            move_stick: vec![Stick::Left],
+            shoot: vec![
+                InputButton::Key(KeyCode::Space),
+                InputButton::Gamepad(GamepadButton::RightTrigger2),
+            ],
+            dash: vec![
+                InputButton::Key(KeyCode::ShiftLeft),
+                InputButton::Gamepad(GamepadButton::East),
+            ],
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Reads and validates the bindings in the JSON file at `path`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read keybindings file {}: {e}", path.display()))?;
+        let bindings: Self = serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid keybindings file {}: {e}", path.display()))?;
+        bindings
+            .validate()
+            .map_err(|e| format!("Invalid keybindings file {}: {e}", path.display()))?;
+        Ok(bindings)
+    }
+
+    /// Fails if an action can't be triggered at all. Each movement direction needs a button
+    /// unless a stick covers them all.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.move_stick.is_empty() {
+            let directions = [
+                ("move_up", &self.move_up),
+                ("move_down", &self.move_down),
+                ("move_left", &self.move_left),
+                ("move_right", &self.move_right),
+            ];
+            for (action, buttons) in directions {
+                if buttons.is_empty() {
+                    return Err(format!(
+                        "{action} has no bindings and there's no move_stick"
+                    ));
+                }
+            }
+        }
+        if self.shoot.is_empty() {
+            return Err("shoot has no bindings".to_string());
+        }
+        Ok(())
+    }
+
+    /// Bindings of the movement action, laid out like [`Cardinal`] and [`Axial`] do
+    pub(crate) fn movement(&self) -> impl Bundle {
+        let bindings = self.clone();
+        Bindings::spawn(SpawnWith(move |spawner: &mut BindingSpawner| {
+            for button in bindings.move_right {
+                spawner.spawn(Binding::from(button));
+            }
+            for button in bindings.move_left {
+                spawner.spawn((Binding::from(button), Negate::all()));
+            }
+            for button in bindings.move_up {
+                spawner.spawn((Binding::from(button), SwizzleAxis::YXZ));
+            }
+            for button in bindings.move_down {
+                spawner.spawn((Binding::from(button), Negate::all(), SwizzleAxis::YXZ));
+            }
+            for stick in bindings.move_stick {
+                let (x, y) = stick.axes();
+                spawner.spawn(Binding::from(x));
+                spawner.spawn((Binding::from(y), SwizzleAxis::YXZ));
+            }
+        }))
+    }
+
+    /// Bindings of the shoot action
+    pub(crate) fn shoot(&self) -> impl Bundle {
+        button_bindings(self.shoot.clone())
+    }
+
+    /// Bindings of the dash action
+    pub(crate) fn dash(&self) -> impl Bundle {
+        button_bindings(self.dash.clone())
+    }
+}
+
+fn button_bindings(buttons: Vec<InputButton>) -> impl Bundle {
+    Bindings::spawn(SpawnWith(move |spawner: &mut BindingSpawner| {
+        for button in buttons {
+            spawner.spawn(Binding::from(button));
+        }
+    }))
+}
+
+/// Clap parser for `--keybindings`
+pub(crate) fn parse_keybindings(value: &str) -> Result<KeyBindings, String> {
+    KeyBindings::load(Path::new(value))
+}
@@ -0,0 +1,3346 @@
+mod bots;
+#[cfg(feature = "dev")]
+mod debug;
+mod diagnose;
+mod keybindings;
+
+pub use bots::run_bots;
+pub use keybindings::{InputButton, KeyBindings, Stick};
+
+use bevy::app::{PluginGroupBuilder, ScheduleRunnerPlugin};
+use bevy::input::gamepad::{GamepadRumbleIntensity, GamepadRumbleRequest};
+use bevy::input::mouse::{AccumulatedMouseScroll, MouseScrollUnit};
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy_egui::{EguiContexts, EguiGlobalSettings, EguiPlugin, EguiPrimaryContextPass, egui};
+use bevy_enhanced_input::prelude::*;
+use bevy_quinnet::client::{
+    ClientConnectionConfiguration, ClientConnectionConfigurationDefaultables, QuinnetClient,
+    certificate::{
+        CertConnectionAbortEvent, CertTrustUpdateEvent, CertVerificationStatus, CertVerifierAction,
+        CertVerifierBehaviour, CertificateVerificationMode, KnownHosts, TrustOnFirstUseConfig,
+    },
+    connection::{
+        ClientAddrConfiguration, ConnectionEvent, ConnectionFailedEvent, ConnectionLocalId,
+        ConnectionLostEvent,
+    },
+};
+use bevy_quinnet::shared::channels::DEFAULT_MAX_RELIABLE_FRAME_LEN;
+use bevy_quinnet::shared::error::AsyncChannelError;
+use bevy_replicon::client::confirm_history::EntityReplicated;
+use bevy_replicon::prelude::*;
+use bevy_replicon::shared::replicon_tick::RepliconTick;
+use bevy_replicon_quinnet::RepliconQuinnetPlugins;
+use bevy_transform_interpolation::prelude::{TransformInterpolation, TransformInterpolationPlugin};
+use clap::{Parser, ValueEnum};
+use keybindings::parse_keybindings;
+use shared::networking::{DisconnectReason, PendingClose, ServerDisconnect, close_connection};
+use shared::{
+    BALL_RADIUS, Ball, BroadcastChat, ChatMessage, ClientMovementIntent, CollisionHit,
+    ConnectIntent, ConnectionRejected, DEFAULT_TICK_RATE, DashCooldown, DashIntent, Facing,
+    FireProjectile, ForcePosition, GameConfig, GamePaused, GameSharedPlugin, GameStart, Health,
+    IdleKick, InitialSnapshot, JoinRoom, Kicked, LastProcessedInput, LocalPlayer,
+    MAX_PLAYER_NAME_LEN, NetPosition, NetworkBudget, NetworkError, PLAYER_SIZE, PROJECTILE_RADIUS,
+    Ping, Player, PlayerColor, PlayerDied, PlayerJoined, PlayerLeft, PlayerName, PlayerReady,
+    PlayerRespawned, PlayerShape, Pong, Projectile, RosterUpdate, ServerShutdown, SetPlayerName,
+    ShapeKind, ToggleReady, Velocity, Whisper, WhisperDelivery, WhisperFailed, accelerate,
+    knockback_step, sanitize_player_name,
+};
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::{PI, TAU};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Resource, Parser, Clone)]
+pub struct Args {
+    /// JSON file with values for any of the options below, the command line overrides it
+    #[arg(long)]
+    config: Option<PathBuf>,
+    #[arg(short, long, default_value_t = Ipv6Addr::LOCALHOST.into())]
+    ip: IpAddr,
+    #[arg(short, long, default_value_t = 5000)]
+    port: u16,
+    /// Server to connect to instead of `--ip` and `--port`, repeat it to hop between several with
+    /// F2, the next one is also tried after losing the connection
+    #[arg(long = "server", value_name = "IP:PORT", conflicts_with_all = ["ip", "port"])]
+    servers: Vec<SocketAddr>,
+    /// Display name shown above your player, `Player-<id>` if unset
+    #[arg(short, long, value_parser = parse_player_name)]
+    name: Option<String>,
+    /// Skip server certificate verification, for local testing only
+    #[arg(long)]
+    insecure: bool,
+    /// Watch the game without spawning a player
+    #[arg(long)]
+    spectator: bool,
+    /// Token to present to servers that only let in invited clients
+    #[arg(long)]
+    token: Option<String>,
+    /// Room to join on connecting instead of the default one, `/room <id>` in chat switches later
+    #[arg(long)]
+    room: Option<u32>,
+    /// Seconds to wait for the server to accept a connection before giving up on it
+    #[arg(long, default_value_t = 10.0, value_parser = parse_positive)]
+    connect_timeout: f32,
+    /// Replication updates to buffer remote players by, derived from the server's replication
+    /// rate if unset
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    interpolation_delay_ticks: Option<u32>,
+    /// Milliseconds remote players keep moving on their last known velocity once updates stop
+    /// arriving, 0 to only interpolate
+    #[arg(long, default_value_t = 0)]
+    extrapolation_ms: u32,
+    /// Round-trip time in milliseconds above which the connection counts as poor
+    #[arg(long, default_value_t = 200.0, value_parser = parse_positive)]
+    poor_rtt_ms: f32,
+    /// Packet loss in percent above which the connection counts as poor
+    #[arg(long, default_value_t = 5.0, value_parser = parse_positive)]
+    poor_loss: f32,
+    /// Updates the interpolation delay grows by every second the connection is poor, and shrinks
+    /// by again once it recovers. 0 to only show the warning
+    #[arg(long, default_value_t = 1)]
+    poor_delay_step: u32,
+    /// Most updates a poor connection may add to the interpolation delay
+    #[arg(long, default_value_t = 6)]
+    poor_max_extra_delay: u32,
+    /// Milliseconds the local player is eased onto server corrections over, 0 to snap
+    #[arg(long, default_value_t = CORRECTION_SMOOTHING_MS)]
+    correction_smoothing_ms: u32,
+    /// Milliseconds players take to fade in when they appear and out when they leave, 0 to pop in
+    /// and out
+    #[arg(long, default_value_t = 250)]
+    spawn_fade_ms: u32,
+    /// Stick deflection below which movement input is ignored, from 0 up to but excluding 1
+    #[arg(long, default_value_t = INPUT_DEAD_ZONE, value_parser = parse_dead_zone)]
+    dead_zone: f32,
+    /// Factor movement input is scaled by, the server still caps it at full speed
+    #[arg(long, default_value_t = 1.0, value_parser = parse_non_negative)]
+    sensitivity: f32,
+    /// Gamepad rumble strength when another player pushes you, from 0 (off) to 1
+    #[arg(long, default_value_t = RUMBLE_INTENSITY, value_parser = parse_intensity)]
+    rumble_intensity: f32,
+    /// JSON file mapping actions to the keys and buttons that trigger them, actions it leaves out
+    /// keep their default bindings
+    #[arg(long, value_name = "PATH", value_parser = parse_keybindings)]
+    keybindings: Option<KeyBindings>,
+    /// Channel movement intents are sent on, to compare dropping lost input with resending it
+    #[arg(long, value_enum, default_value_t = MovementChannel::Unreliable)]
+    movement_channel: MovementChannel,
+    /// Most movement intents sent per second, unlimited if 0
+    #[arg(long, default_value_t = 0.0, value_parser = parse_non_negative)]
+    max_intent_rate: f32,
+    /// Largest message in bytes the client sends on reliable channels
+    #[arg(long, default_value_t = DEFAULT_MAX_RELIABLE_FRAME_LEN)]
+    max_reliable_frame_len: usize,
+    /// Measure the round-trip time, jitter and loss to the server without a window, print them
+    /// and exit, with a nonzero code if the connection failed
+    #[arg(long)]
+    diagnose: bool,
+    /// Run without a window, rendering or UI, keeping only networking and the simulation. Also
+    /// chosen when there's no display to open a window on
+    #[arg(long)]
+    headless: bool,
+    /// Pings sent per second with `--diagnose`
+    #[arg(long, default_value_t = 20.0, value_parser = parse_positive)]
+    diagnose_rate: f32,
+    /// Seconds to send pings for with `--diagnose`
+    #[arg(long, default_value_t = 10.0, value_parser = parse_positive)]
+    diagnose_secs: f32,
+    /// Connect this many headless bots from one process to load-test the server, each walking
+    /// around at random. Prints how many joined, their round-trip times and errors when stopped
+    #[arg(long, conflicts_with = "diagnose", value_parser = clap::value_parser!(u16).range(1..))]
+    bots: Option<u16>,
+    /// Movement intents each bot sends per second with `--bots`
+    #[arg(long, default_value_t = 10.0, value_parser = parse_positive)]
+    bot_rate: f32,
+    /// Seconds to run `--bots` for, until Ctrl-C if unset
+    #[arg(long, value_parser = parse_positive)]
+    bot_secs: Option<f32>,
+}
+
+impl Args {
+    /// Whether to run a swarm of `--bots` instead of a single client
+    pub fn runs_bots(&self) -> bool {
+        self.bots.is_some()
+    }
+}
+
+/// Frame time of `--diagnose`, which has no window to pace it
+const DIAGNOSE_FRAME_TIME: Duration = Duration::from_millis(1);
+
+/// Frame time of `--headless`, matching a typical display refresh rate
+const HEADLESS_FRAME_TIME: Duration = Duration::from_micros(16_667);
+
+fn parse_positive(value: &str) -> Result<f32, String> {
+    let value: f32 = value.parse().map_err(|e| format!("{e}"))?;
+    if !(value > 0.0 && value.is_finite()) {
+        return Err("must be a positive number".to_string());
+    }
+    Ok(value)
+}
+
+fn parse_non_negative(value: &str) -> Result<f32, String> {
+    let value: f32 = value.parse().map_err(|e| format!("{e}"))?;
+    if !(value >= 0.0 && value.is_finite()) {
+        return Err("must be a non-negative number".to_string());
+    }
+    Ok(value)
+}
+
+fn parse_intensity(value: &str) -> Result<f32, String> {
+    let value: f32 = value.parse().map_err(|e| format!("{e}"))?;
+    if !(0.0..=1.0).contains(&value) {
+        return Err("must be from 0 to 1".to_string());
+    }
+    Ok(value)
+}
+
+fn parse_dead_zone(value: &str) -> Result<f32, String> {
+    let value: f32 = value.parse().map_err(|e| format!("{e}"))?;
+    if !(0.0..1.0).contains(&value) {
+        return Err("must be at least 0 and below 1".to_string());
+    }
+    Ok(value)
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+/// Replicon channels movement intents can be sent on
+enum MovementChannel {
+    Unreliable,
+    Ordered,
+}
+
+impl From<MovementChannel> for Channel {
+    fn from(channel: MovementChannel) -> Self {
+        match channel {
+            MovementChannel::Unreliable => Channel::Unreliable,
+            MovementChannel::Ordered => Channel::Ordered,
+        }
+    }
+}
+
+/// Buffer to aim for when deriving the interpolation delay from the server replication rate
+const INTERPOLATION_BUFFER: Duration = Duration::from_millis(30);
+
+#[derive(Resource, Debug, Clone, Copy)]
+/// How far behind the server remote players are smoothed, trading latency for smoothness
+pub struct InterpolationConfig {
+    /// Replication updates each replicated transform change is eased over
+    pub delay_ticks: u32,
+}
+
+impl InterpolationConfig {
+    /// Smallest delay that covers [`INTERPOLATION_BUFFER`] at `tick_rate`
+    pub fn for_tick_rate(tick_rate: f64) -> Self {
+        let ticks = (INTERPOLATION_BUFFER.as_secs_f64() * tick_rate).ceil();
+        Self {
+            delay_ticks: (ticks as u32).max(1),
+        }
+    }
+}
+
+impl Default for InterpolationConfig {
+    fn default() -> Self {
+        Self::for_tick_rate(DEFAULT_TICK_RATE)
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+/// How far remote players are dead-reckoned past their last update when the next one is late.
+///
+/// Updates stop both when packets are lost and when a player stands still, so a player that stops
+/// overshoots by up to its last velocity times `max_ms` before easing back to where it stopped.
+pub struct ExtrapolationConfig {
+    pub max_ms: u32,
+}
+
+/// Default [`CorrectionSmoothing`] time in milliseconds
+const CORRECTION_SMOOTHING_MS: u32 = 100;
+
+/// Corrections longer than this are drawn as they are, so a teleport or a real desync is never
+/// hidden behind smoothing
+const MAX_SMOOTHED_CORRECTION: f32 = PLAYER_SIZE * 2.0;
+
+#[derive(Resource, Debug, Clone, Copy)]
+/// How the local player is drawn after a server correction. Only the drawn position is eased, the
+/// predicted one moves to the corrected position at once.
+pub struct CorrectionSmoothing {
+    /// Time the drawn position takes to all but catch up with the predicted one
+    pub time: Duration,
+}
+
+#[derive(Component, Default)]
+/// Last authoritative position of a remote player and the velocity it arrived with
+struct Extrapolation {
+    position: Vec2,
+    velocity: Vec2,
+    /// Replicon tick of the last update
+    received: Option<RepliconTick>,
+}
+
+/// Seconds per second the estimated clock offset may grow by, letting it follow a server clock
+/// running slower than ours
+const CLOCK_DRIFT: f64 = 0.001;
+
+#[derive(Resource, Debug, Default)]
+/// Server time, estimated from the ticks replication updates are stamped with.
+///
+/// The server advances its replicon tick once per replication update, so ticks over the
+/// replication rate are seconds on its clock.
+pub struct ServerClock {
+    /// Newest tick received, older ones arriving later were reordered on the way
+    latest: Option<RepliconTick>,
+    /// Ticks since the first update, which keeps counting when `RepliconTick` wraps around
+    elapsed_ticks: u64,
+    replication_rate: f64,
+    /// Local time minus server time of the least delayed update, in seconds
+    offset: f64,
+    /// Local time the offset was last estimated at
+    updated_at: Duration,
+}
+
+impl ServerClock {
+    /// Newest tick received from the server
+    pub fn latest(&self) -> Option<RepliconTick> {
+        self.latest
+    }
+
+    /// Server time in seconds `tick` was sent at, counted from the first update received
+    pub fn server_time(&self, tick: RepliconTick) -> Option<f64> {
+        let latest = self.latest?;
+        // Wrapping differences, so a tick from just before a wraparound is still in the past.
+        let ticks = if tick <= latest {
+            self.elapsed_ticks.checked_sub((latest - tick).into())?
+        } else {
+            self.elapsed_ticks + u64::from(tick - latest)
+        };
+        Some(ticks as f64 / self.replication_rate)
+    }
+
+    /// Server time in seconds it is now, given the local time
+    pub fn now(&self, local: Duration) -> Option<f64> {
+        self.latest?;
+        Some(local.as_secs_f64() - self.offset)
+    }
+
+    fn record(&mut self, tick: RepliconTick, replication_rate: f64, local: Duration) {
+        match self.latest {
+            None => {
+                self.replication_rate = replication_rate;
+                self.latest = Some(tick);
+            }
+            Some(latest) if tick > latest => {
+                self.elapsed_ticks += u64::from(tick - latest);
+                self.latest = Some(tick);
+            }
+            Some(_) => {}
+        }
+        let Some(server_time) = self.server_time(tick) else {
+            return;
+        };
+
+        // Delays only ever add to the offset, so the smallest one seen is closest to the clock
+        // difference. It's allowed to creep up so drift doesn't leave it stuck on an old minimum.
+        let sample = local.as_secs_f64() - server_time;
+        let drifted = self.offset + (local - self.updated_at).as_secs_f64() * CLOCK_DRIFT;
+        self.offset = if self.updated_at.is_zero() {
+            sample
+        } else {
+            sample.min(drifted)
+        };
+        self.updated_at = local;
+    }
+}
+
+/// Rejects names the server would truncate or reduce to nothing, it still validates them itself
+fn parse_player_name(value: &str) -> Result<String, String> {
+    if value.trim().chars().count() > MAX_PLAYER_NAME_LEN {
+        return Err(format!("must be at most {MAX_PLAYER_NAME_LEN} characters"));
+    }
+    sanitize_player_name(value).ok_or_else(|| "must not be empty".to_string())
+}
+
+#[derive(InputAction)]
+#[action_output(Vec2)]
+struct PlayerMovement;
+
+#[derive(InputAction)]
+#[action_output(bool)]
+/// Fires a projectile the way the local player faces
+struct Shoot;
+
+#[derive(InputAction)]
+#[action_output(bool)]
+/// Bursts the local player forward, the way [`Shoot`] aims
+struct Dash;
+
+#[derive(Component)]
+/// Input context for actions that are available whether or not a player is spawned
+struct ClientControls;
+
+#[derive(InputAction)]
+#[action_output(bool)]
+/// Leaves the server, or joins it again after leaving
+struct ToggleConnection;
+
+#[derive(Component)]
+/// Input context on the camera, active only while there is no local player for it to follow
+struct FreeCamera;
+
+#[derive(InputAction)]
+#[action_output(Vec2)]
+/// Moves the free camera with the keyboard
+struct PanCamera;
+
+#[derive(InputAction)]
+#[action_output(bool)]
+/// Held to drag the free camera with the mouse
+struct GrabCamera;
+
+#[derive(InputAction)]
+#[action_output(Vec2)]
+/// Mouse motion while [`GrabCamera`] is held
+struct DragCamera;
+
+/// Free camera keyboard speed in world units per second at a zoom scale of 1
+const FREE_CAMERA_SPEED: f32 = 600.0;
+
+#[derive(Event)]
+/// Closes the connection on purpose and returns to the connect menu
+struct LeaveServer;
+
+#[derive(Event)]
+/// Opens a fresh connection from the connect menu
+struct JoinServer;
+
+#[derive(InputAction)]
+#[action_output(bool)]
+/// Leaves the current server and joins the next one in [`ServerList`]
+struct NextServer;
+
+#[derive(Resource, Debug, Clone)]
+/// Servers the client can connect to, one at a time
+struct ServerList {
+    servers: Vec<SocketAddr>,
+    active: usize,
+}
+
+impl ServerList {
+    /// The `--server` list, or `--ip` and `--port` when it's empty
+    fn from_args(args: &Args) -> Self {
+        let servers = if args.servers.is_empty() {
+            vec![SocketAddr::new(args.ip, args.port)]
+        } else {
+            args.servers.clone()
+        };
+        Self { servers, active: 0 }
+    }
+
+    fn active(&self) -> SocketAddr {
+        self.servers[self.active]
+    }
+
+    /// Makes the next server active, wrapping around to the first one, and returns it
+    fn advance(&mut self) -> SocketAddr {
+        self.active = (self.active + 1) % self.servers.len();
+        self.active()
+    }
+}
+
+#[derive(Resource)]
+/// Present while leaving one server to join the next, until the old connection is closed
+struct SwitchingServer;
+
+#[derive(Resource)]
+/// Present after leaving the server on purpose, which stops automatic reconnection
+struct LeftServer;
+
+/// Number of unacknowledged predicted moves kept for replay, older ones are dropped
+const INPUT_HISTORY_LEN: usize = 256;
+
+#[derive(Message, Debug, Clone)]
+/// Raised when a server presents a certificate that doesn't match its known fingerprint
+struct CertificateWarning {
+    server_name: String,
+    fingerprint: String,
+    known_fingerprint: Option<String>,
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+/// Network quality of the active connection, absent while not connected
+struct ConnectionStats {
+    rtt_ms: f32,
+    /// Percentage of sent packets that were lost
+    packet_loss: f32,
+    /// Packets sent over the connection so far
+    sent_packets: u64,
+    /// Packets of `sent_packets` that were lost
+    lost_packets: u64,
+}
+
+/// How often the connection quality is judged, from the packets sent in that time
+const QUALITY_WINDOW: Duration = Duration::from_secs(1);
+
+/// Fraction of the poor connection thresholds a connection has to get back under to count as
+/// good again, so one hovering around a threshold doesn't flip every window
+const QUALITY_RECOVERY: f32 = 0.75;
+
+/// Good windows in a row it takes to relax the interpolation delay by a step
+const QUALITY_RELAX_WINDOWS: u32 = 5;
+
+#[derive(Resource, Debug, Clone, Copy)]
+/// When the connection counts as poor, and how far the interpolation delay follows it
+pub struct ConnectionQualityConfig {
+    pub poor_rtt_ms: f32,
+    /// Packet loss in percent
+    pub poor_loss: f32,
+    /// Updates the delay grows or shrinks by per window, 0 to leave it alone
+    pub delay_step: u32,
+    /// Most updates added on top of the [`InterpolationConfig`] delay
+    pub max_extra_delay: u32,
+}
+
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// How the connection fared lately and what was done about it, reset for every connection
+pub struct ConnectionQuality {
+    /// Whether the connection is poor, shown as a warning until it clearly recovers
+    pub poor: bool,
+    /// Updates added to the [`InterpolationConfig`] delay, keeping remote players smooth at the
+    /// cost of showing them further behind
+    pub extra_delay_ticks: u32,
+}
+
+#[derive(Resource, Default)]
+/// The [`QUALITY_WINDOW`] being measured
+struct QualityWindow {
+    elapsed: Duration,
+    /// Sent and lost packets when the window started
+    start: Option<(u64, u64)>,
+    /// Good windows in a row, up to [`QUALITY_RELAX_WINDOWS`]
+    good: u32,
+}
+
+/// Default [`InputSettings::dead_zone`]
+const INPUT_DEAD_ZONE: f32 = 0.2;
+
+#[derive(Resource, Reflect, Debug, Clone, Copy)]
+#[reflect(Resource)]
+/// Tuning of the movement input, editable at runtime from the inspector
+pub struct InputSettings {
+    /// Stick deflection below which movement input is ignored, from 0 to 1
+    pub dead_zone: f32,
+    /// Factor the movement direction is scaled by before it's predicted and sent
+    pub sensitivity: f32,
+}
+
+// The clamps only guard values edited in the inspector, `Args` refuses them when parsing
+impl InputSettings {
+    fn dead_zone(&self) -> DeadZone {
+        DeadZone {
+            lower_threshold: self.dead_zone.clamp(0.0, 0.99),
+            ..default()
+        }
+    }
+
+    fn scale(&self) -> Scale {
+        Scale::splat(self.sensitivity.max(0.0))
+    }
+}
+
+impl Default for InputSettings {
+    fn default() -> Self {
+        Self {
+            dead_zone: INPUT_DEAD_ZONE,
+            sensitivity: 1.0,
+        }
+    }
+}
+
+/// Default [`CollisionRumble::intensity`]
+const RUMBLE_INTENSITY: f32 = 0.4;
+
+#[derive(Resource, Reflect, Debug, Clone, Copy)]
+#[reflect(Resource)]
+/// Gamepad feedback for the local player being pushed, editable at runtime from the inspector
+pub struct CollisionRumble {
+    /// Strength of both motors from 0 to 1, where 0 turns rumble off
+    pub intensity: f32,
+    /// How long a single rumble lasts, pushes while it runs don't start another
+    pub duration: Duration,
+}
+
+impl Default for CollisionRumble {
+    fn default() -> Self {
+        Self {
+            intensity: RUMBLE_INTENSITY,
+            duration: Duration::from_millis(150),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+/// Time left on the running collision rumble, and whether a push arrived since the last frame
+struct RumbleState {
+    remaining: Duration,
+    pushed: bool,
+}
+
+/// Default [`CameraSmoothing`] decay rate
+const CAMERA_SMOOTHING: f32 = 8.0;
+
+#[derive(Resource, Debug, Clone, Copy)]
+/// How quickly the camera catches up with the local player, as an exponential decay rate per second
+pub struct CameraSmoothing(pub f32);
+
+impl Default for CameraSmoothing {
+    fn default() -> Self {
+        Self(CAMERA_SMOOTHING)
+    }
+}
+
+/// Smallest and largest orthographic scale the mouse wheel can zoom to
+const ZOOM_RANGE: RangeInclusive<f32> = 0.5..=3.0;
+
+/// Fraction the scale changes by per mouse wheel line
+const ZOOM_STEP: f32 = 0.1;
+
+/// How often a [`Ping`] is sent while connected
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of round trips averaged into [`PingStats::average_ms`]
+const PING_SAMPLES: usize = 10;
+
+#[derive(Resource)]
+/// Application-level round-trip time, measured with [`Ping`] and [`Pong`] events
+struct PingStats {
+    timer: Timer,
+    next_id: u32,
+    /// Ids of pings still waiting for their pong
+    outstanding: VecDeque<u32>,
+    samples: VecDeque<f32>,
+}
+
+impl Default for PingStats {
+    fn default() -> Self {
+        Self {
+            timer: Timer::new(PING_INTERVAL, TimerMode::Repeating),
+            next_id: 0,
+            outstanding: VecDeque::new(),
+            samples: VecDeque::new(),
+        }
+    }
+}
+
+impl PingStats {
+    /// Rolling average over the last [`PING_SAMPLES`] round trips, if any completed yet
+    fn average_ms(&self) -> Option<f32> {
+        (!self.samples.is_empty())
+            .then(|| self.samples.iter().sum::<f32>() / self.samples.len() as f32)
+    }
+}
+
+#[derive(States, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+/// Status of the connection to the server, mirrored from `QuinnetClient`
+enum NetState {
+    #[default]
+    Offline,
+    Connecting,
+    /// Connected, but the initial world snapshot hasn't arrived yet
+    Connected,
+    /// Connected with the world replicated, so it can be shown and played
+    InGame,
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+/// How the client retries after losing its connection to the server
+struct ReconnectPolicy {
+    max_attempts: u32,
+    /// Delay before the first retry, doubled after every failed attempt
+    backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+/// Progress of the current reconnection, reset once connected again
+struct ReconnectState {
+    attempts: u32,
+    timer: Option<Timer>,
+    exhausted: bool,
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+/// How long a connection attempt may take before it is aborted
+struct ConnectTimeout(Duration);
+
+#[derive(Resource)]
+/// Time spent on the current connection attempt
+struct ConnectTimer(Timer);
+
+#[derive(Message, Debug, Clone)]
+/// Raised when a connection attempt is aborted without the server ever answering
+struct ConnectFailed {
+    reason: String,
+}
+
+#[derive(Message, Debug, Clone, Copy)]
+/// Raised once every reconnection attempt allowed by the `ReconnectPolicy` has failed
+struct ReconnectFailed {
+    attempts: u32,
+}
+
+#[derive(Resource)]
+/// Present once the server left the lobby and movement is enabled
+struct GameStarted;
+
+#[derive(Resource)]
+/// Present while the server has the game paused, which stops local prediction
+struct Paused;
+
+#[derive(Resource)]
+/// Explanation shown to the player after the server ended the session
+struct DisconnectNotice(String);
+
+/// Number of chat lines kept in the chat window
+const CHAT_HISTORY_LEN: usize = 50;
+
+#[derive(Resource, Default)]
+/// Recent chat lines and the message currently being typed
+struct ChatLog {
+    lines: VecDeque<String>,
+    draft: String,
+}
+
+impl ChatLog {
+    fn push(&mut self, line: String) {
+        self.lines.push_back(line);
+        while self.lines.len() > CHAT_HISTORY_LEN {
+            self.lines.pop_front();
+        }
+    }
+}
+
+/// Splits `/w <name> <text>` or `/whisper <name> <text>` into the name and the text, `None` if
+/// `text` isn't a whisper. Names containing spaces can't be whispered to.
+fn parse_whisper(text: &str) -> Option<Result<(&str, &str), &'static str>> {
+    let (command, rest) = text.split_once(' ').unwrap_or((text, ""));
+    if command != "/w" && command != "/whisper" {
+        return None;
+    }
+    let whisper = rest
+        .trim_start()
+        .split_once(' ')
+        .map(|(name, text)| (name, text.trim()))
+        .filter(|(_, text)| !text.is_empty());
+    Some(whisper.ok_or("Usage: /w <name> <message>"))
+}
+
+/// Parses `/room <id>` into the room id, `None` if `text` isn't a room command
+fn parse_room(text: &str) -> Option<Result<u32, &'static str>> {
+    let (command, rest) = text.split_once(' ').unwrap_or((text, ""));
+    if command != "/room" {
+        return None;
+    }
+    Some(rest.trim().parse().map_err(|_| "Usage: /room <id>"))
+}
+
+/// Number of entries kept in the connection log
+const CONNECTION_LOG_LEN: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectionLogKind {
+    Connected,
+    Disconnected,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+struct ConnectionLogEntry {
+    /// Seconds since the client started
+    time: f32,
+    kind: ConnectionLogKind,
+    client_id: Option<u64>,
+    detail: String,
+}
+
+#[derive(Resource, Default)]
+/// History of connection changes shown in the connection log window
+struct ConnectionLog {
+    entries: VecDeque<ConnectionLogEntry>,
+    /// Client id of the current or last session, kept for entries logged after disconnecting
+    client_id: Option<u64>,
+}
+
+impl ConnectionLog {
+    fn push(&mut self, time: f32, kind: ConnectionLogKind, detail: impl Into<String>) {
+        self.entries.push_back(ConnectionLogEntry {
+            time,
+            kind,
+            client_id: self.client_id,
+            detail: detail.into(),
+        });
+        while self.entries.len() > CONNECTION_LOG_LEN {
+            self.entries.pop_front();
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+/// Players on the server by network id and name, as last sent by the server
+struct Roster(Vec<(u64, String)>);
+
+/// Length of the longer side of the minimap, in points
+const MINIMAP_SIZE: f32 = 180.0;
+
+#[derive(Resource)]
+/// Whether the minimap is shown
+struct MinimapVisible(bool);
+
+#[derive(InputAction)]
+#[action_output(bool)]
+/// Shows or hides the minimap
+struct ToggleMinimap;
+
+#[derive(Component)]
+/// Marker for the text label showing a player's name
+struct NameLabel;
+
+/// Size of the health bar drawn above each player
+const HEALTH_BAR_SIZE: Vec2 = Vec2::new(PLAYER_SIZE, 6.0);
+
+#[derive(Component)]
+/// Marker for the filled part of a player's health bar
+struct HealthBar;
+
+/// Exponential decay rate per second of the gap between a remote player's drawn and replicated
+/// facing
+const FACING_SMOOTHING: f32 = 15.0;
+
+#[derive(Component)]
+/// Child drawing a player's shape, turned to its facing without turning the labels above it
+struct PlayerBody;
+
+/// Scale a player's body grows from as it fades in, and shrinks to as it fades out
+const SPAWN_FADE_SCALE: f32 = 0.2;
+
+#[derive(Resource)]
+/// How long players take to fade in and out, absent when they pop in and out instead
+struct SpawnFade(Duration);
+
+#[derive(Component)]
+/// Fades in the body of a newly replicated player, removed once it's fully shown.
+///
+/// Only the body child is scaled and faded, the player's own transform stays with replication
+/// and prediction.
+struct SpawnAnim(Timer);
+
+#[derive(Component)]
+/// Fades out a stand-in for the body of a player that's gone, despawning it at the end
+struct DespawnAnim(Timer);
+
+#[derive(Component, Default)]
+/// Locally predicted movement state for the local player
+struct Prediction {
+    input: Vec2,
+    /// Sequence number of the last movement intent sent to the server
+    seq: u32,
+    position: Vec2,
+    /// Moves predicted since the last acknowledged intent, replayed on every server update
+    pending: VecDeque<PredictedMove>,
+    /// Frame time not yet simulated, in seconds, carried over until it adds up to a server tick
+    unsimulated: f64,
+    /// When the last held-input intent was sent, see [`NetworkBudget::max_intent_rate`]
+    last_sent: Duration,
+    /// Knockback from the last authoritative update, faded out locally until the next one
+    knockback: Vec2,
+    /// Velocity of the player's own movement, accelerating towards `input` like the server's
+    velocity: Vec2,
+    /// Offset of the drawn position from `position`, left by the last correction and eased out
+    smoothing: Vec2,
+}
+
+/// Movement predicted locally for one server tick while a given intent was active
+struct PredictedMove {
+    seq: u32,
+    delta: Vec2,
+}
+
+/// Builds the windowed client app
+pub fn build_client_app(args: Args) -> App {
+    if args.diagnose {
+        return build_diagnose_app(args);
+    }
+    if args.headless {
+        return build_app(args, configure_windowless_plugins);
+    }
+    if !display_available() {
+        // Logging is only set up once the plugins are added.
+        let app = build_app(args, configure_windowless_plugins);
+        warn!("No display to open a window on, running headless");
+        return app;
+    }
+    build_app(args, configure_plugins)
+}
+
+/// Whether a window could be opened. Only Unix desktops reveal it up front, through the
+/// variables pointing at their display server.
+fn display_available() -> bool {
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        ["DISPLAY", "WAYLAND_DISPLAY"]
+            .iter()
+            .any(|var| std::env::var_os(var).is_some_and(|value| !value.is_empty()))
+    } else {
+        true
+    }
+}
+
+/// Builds the `--diagnose` client, which measures the connection to the server and exits
+pub fn build_diagnose_app(args: Args) -> App {
+    build_app(args, configure_diagnose_plugins)
+}
+
+/// Builds the client without windowing, rendering or UI, so it can be stepped in tests
+pub fn build_headless_client_app(args: Args) -> App {
+    build_app(args, configure_headless_plugins)
+}
+
+fn build_app(args: Args, configure_plugins: fn(&mut App)) -> App {
+    let mut app = App::new();
+    app.insert_resource(ConnectTimeout(Duration::from_secs_f32(
+        args.connect_timeout,
+    )));
+    app.insert_resource(
+        args.interpolation_delay_ticks
+            .map(|delay_ticks| InterpolationConfig { delay_ticks })
+            .unwrap_or_default(),
+    );
+    if args.extrapolation_ms > 0 {
+        app.insert_resource(ExtrapolationConfig {
+            max_ms: args.extrapolation_ms,
+        });
+    }
+    app.insert_resource(ConnectionQualityConfig {
+        poor_rtt_ms: args.poor_rtt_ms,
+        poor_loss: args.poor_loss,
+        delay_step: args.poor_delay_step,
+        max_extra_delay: args.poor_max_extra_delay,
+    });
+    app.insert_resource(CorrectionSmoothing {
+        time: Duration::from_millis(args.correction_smoothing_ms.into()),
+    });
+    if args.spawn_fade_ms > 0 {
+        app.insert_resource(SpawnFade(Duration::from_millis(args.spawn_fade_ms.into())));
+    }
+    app.insert_resource(InputSettings {
+        dead_zone: args.dead_zone,
+        sensitivity: args.sensitivity,
+    });
+    app.insert_resource(CollisionRumble {
+        intensity: args.rumble_intensity,
+        ..default()
+    });
+    app.insert_resource(args.keybindings.clone().unwrap_or_default());
+    // Read by `GameSharedPlugin`, so it has to be in before the plugins are added.
+    app.insert_resource(NetworkBudget {
+        movement_channel: args.movement_channel.into(),
+        max_reliable_frame_len: args.max_reliable_frame_len,
+        max_intent_rate: args.max_intent_rate,
+    });
+    app.insert_resource(ServerList::from_args(&args));
+    app.insert_resource(args);
+    app.init_resource::<RumbleState>();
+    app.init_resource::<ChatLog>();
+    app.init_resource::<ConnectionLog>();
+    app.init_resource::<Roster>();
+    app.insert_resource(MinimapVisible(true));
+    app.init_resource::<PingStats>();
+    app.init_resource::<ServerClock>();
+    app.init_resource::<ConnectionQuality>();
+    app.init_resource::<QualityWindow>();
+    app.init_resource::<CameraSmoothing>();
+    app.init_resource::<ReconnectPolicy>();
+    app.init_resource::<ReconnectState>();
+    app.add_message::<CertificateWarning>();
+    app.add_message::<ReconnectFailed>();
+    app.add_message::<ConnectFailed>();
+
+    configure_plugins(&mut app);
+    configure_systems(&mut app);
+
+    app
+}
+
+fn configure_plugins(app: &mut App) {
+    app.add_plugins(DefaultPlugins)
+        .add_plugins((
+            EnhancedInputPlugin,
+            EguiPlugin::default(),
+            TransformInterpolationPlugin::default(),
+        ))
+        .add_plugins((replicon_plugins(), RepliconQuinnetPlugins, GameSharedPlugin))
+        .add_input_context::<LocalPlayer>()
+        .add_input_context::<ClientControls>()
+        .add_input_context::<FreeCamera>()
+        // Keep typing in egui text fields from also moving the player.
+        .insert_resource(EguiGlobalSettings {
+            enable_absorb_bevy_input_system: true,
+            ..default()
+        });
+
+    #[cfg(feature = "panic-handler")]
+    app.add_plugins(bevy_panic_handler::PanicHandlerBuilder::default().build());
+    #[cfg(feature = "inspector")]
+    app.add_plugins(bevy_inspector_egui::quick::WorldInspectorPlugin::default());
+
+    #[cfg(feature = "dev")]
+    {
+        debug::configure_replication_debug(app);
+        debug::configure_perf_stats(app);
+    }
+}
+
+/// Replicon without its server half, which a client never runs.
+///
+/// After a disconnect replicon replays the events sent in the last frames as if this app were a
+/// listen server, and the server half would take a replayed protocol hash for the host's own and
+/// panic. A client refused right after connecting hits exactly that.
+fn replicon_plugins() -> PluginGroupBuilder {
+    RepliconPlugins
+        .build()
+        .disable::<ServerPlugin>()
+        .disable::<ServerMessagePlugin>()
+}
+
+fn configure_headless_plugins(app: &mut App) {
+    add_headless_plugins(app, MinimalPlugins.build());
+}
+
+fn configure_windowless_plugins(app: &mut App) {
+    app.add_plugins(LogPlugin::default());
+    add_headless_plugins(
+        app,
+        MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(HEADLESS_FRAME_TIME)),
+    );
+}
+
+fn configure_diagnose_plugins(app: &mut App) {
+    app.add_plugins(LogPlugin::default());
+    add_headless_plugins(
+        app,
+        MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(DIAGNOSE_FRAME_TIME)),
+    );
+    diagnose::configure_diagnosis(app);
+}
+
+fn add_headless_plugins(app: &mut App, minimal: impl PluginGroup) {
+    app.add_plugins((minimal, StatesPlugin, EnhancedInputPlugin))
+        .add_plugins((replicon_plugins(), RepliconQuinnetPlugins, GameSharedPlugin))
+        .add_input_context::<LocalPlayer>()
+        .add_input_context::<ClientControls>()
+        .add_input_context::<FreeCamera>();
+}
+
+fn configure_systems(app: &mut App) {
+    app.init_state::<NetState>();
+
+    app.add_systems(Startup, setup_client);
+    // Before replicon receives, so a snapshot arriving with the connection isn't overridden.
+    app.add_systems(PreUpdate, update_net_state.before(ClientSystems::Receive));
+    app.add_systems(
+        PreUpdate,
+        (
+            update_server_clock.run_if(resource_exists::<GameConfig>),
+            record_remote_updates
+                .run_if(resource_exists::<ExtrapolationConfig>.and(resource_exists::<GameConfig>)),
+        )
+            .chain()
+            .after(ClientSystems::Receive),
+    );
+    app.add_systems(OnEnter(NetState::Offline), clear_session);
+    // Replicon drops client events sent before its own connected state is entered.
+    app.add_systems(
+        OnEnter(ClientState::Connected),
+        send_connect_intent
+            .after(ClientSystems::ResetEvents)
+            .run_if(not(resource_exists::<diagnose::Diagnosis>)),
+    );
+    app.add_systems(OnEnter(NetState::Connecting), start_connect_timer);
+    app.add_systems(OnExit(NetState::Connecting), stop_connect_timer);
+    app.add_systems(
+        Update,
+        (
+            read_connected,
+            record_connection_events,
+            read_certificate_events,
+            reconnect.run_if(in_state(NetState::Offline).and(not(resource_exists::<LeftServer>))),
+            read_reconnect_failures,
+            join_next_server.run_if(
+                resource_exists::<SwitchingServer>
+                    .and(in_state(NetState::Offline))
+                    .and(not(resource_exists::<PendingClose>)),
+            ),
+            check_connect_timeout.run_if(in_state(NetState::Connecting)),
+            read_connect_failures,
+            (
+                update_connection_stats,
+                evaluate_connection_quality.run_if(resource_exists::<ConnectionStats>),
+            )
+                .chain(),
+            send_pings.run_if(in_state(NetState::InGame)),
+            apply_net_positions
+                .before(handle_new_players)
+                .before(predict_local_movement),
+            handle_new_players.run_if(in_state(NetState::InGame)),
+            (handle_new_balls, handle_new_projectiles)
+                .run_if(in_state(NetState::InGame).and(resource_exists::<Assets<ColorMaterial>>)),
+            predict_local_movement.run_if(
+                in_state(NetState::InGame)
+                    .and(resource_exists::<GameStarted>)
+                    .and(resource_exists::<GameConfig>)
+                    .and(not(resource_exists::<Paused>)),
+            ),
+            (
+                toggle_free_camera,
+                camera_follow,
+                zoom_camera,
+                clamp_camera.run_if(resource_exists::<GameConfig>),
+            )
+                .chain()
+                .after(predict_local_movement),
+            // Their children need the `Visibility` a player's visuals bring along.
+            (update_name_labels, update_health_bars).after(handle_new_players),
+            apply_input_settings.run_if(resource_changed::<InputSettings>),
+            rumble_on_collision.run_if(resource_exists::<Messages<GamepadRumbleRequest>>),
+            apply_interpolation_config.run_if(
+                resource_changed::<InterpolationConfig>
+                    .or(resource_changed::<ConnectionQuality>)
+                    .or(resource_exists_and_changed::<GameConfig>),
+            ),
+        ),
+    );
+    app.add_systems(
+        Update,
+        extrapolate_remote_players
+            .after(apply_net_positions)
+            .run_if(resource_exists::<ExtrapolationConfig>.and(resource_exists::<GameConfig>)),
+    );
+    app.add_systems(
+        Update,
+        tick_dash_cooldowns.run_if(not(resource_exists::<Paused>)),
+    );
+    app.add_systems(
+        Update,
+        apply_key_bindings
+            .after(handle_new_players)
+            .run_if(resource_changed::<KeyBindings>),
+    );
+    app.add_systems(
+        Update,
+        turn_player_bodies
+            .after(predict_local_movement)
+            .after(handle_new_players),
+    );
+    app.add_systems(
+        Update,
+        (fade_in_players.after(handle_new_players), fade_out_players)
+            .run_if(resource_exists::<SpawnFade>),
+    );
+    app.add_systems(
+        EguiPrimaryContextPass,
+        (
+            chat_window,
+            lobby_window
+                .run_if(in_state(NetState::InGame).and(not(resource_exists::<GameStarted>))),
+            certificate_warning_window,
+            connection_stats_overlay,
+            connection_log_window,
+            scoreboard_window.run_if(in_state(NetState::InGame)),
+            minimap_window.run_if(in_state(NetState::InGame)),
+            disconnect_notice_window,
+            paused_overlay.run_if(in_state(NetState::InGame).and(resource_exists::<Paused>)),
+            dash_cooldown_overlay.run_if(in_state(NetState::InGame)),
+            connect_menu.run_if(in_state(NetState::Offline)),
+        ),
+    );
+    app.add_systems(
+        PostUpdate,
+        announce_exit
+            .before(ClientSystems::Send)
+            .run_if(in_state(ClientState::Connected)),
+    );
+    app.add_systems(Last, close_connections);
+
+    app.add_observer(on_input);
+    app.add_observer(on_input_ended);
+    app.add_observer(on_shoot);
+    app.add_observer(on_dash);
+    app.add_observer(on_toggle_connection);
+    app.add_observer(on_pan_camera);
+    app.add_observer(on_drag_camera);
+    app.add_observer(on_leave_server);
+    app.add_observer(on_join_server);
+    app.add_observer(on_next_server);
+    app.add_observer(on_toggle_minimap);
+    app.add_observer(on_game_config);
+    app.add_observer(on_broadcast_chat);
+    app.add_observer(on_whisper_delivery);
+    app.add_observer(on_whisper_failed);
+    app.add_observer(on_connection_rejected);
+    app.add_observer(on_server_shutdown);
+    app.add_observer(on_server_disconnect);
+    app.add_observer(on_kicked);
+    app.add_observer(on_protocol_mismatch);
+    app.add_observer(on_force_position);
+    app.add_observer(on_initial_snapshot);
+    app.add_observer(on_idle_kick);
+    app.add_observer(on_game_start);
+    app.add_observer(on_game_paused);
+    app.add_observer(on_player_died);
+    app.add_observer(on_player_respawned);
+    app.add_observer(on_player_joined);
+    app.add_observer(on_player_removed);
+    app.add_observer(on_player_left);
+    app.add_observer(on_roster_update);
+    app.add_observer(on_pong);
+    app.add_observer(on_collision_hit);
+}
+
+#[allow(clippy::type_complexity)]
+fn read_connected(
+    mut reader: MessageReader<ConnectionEvent>,
+    mut reconnect: ResMut<ReconnectState>,
+    stale_entities: Query<Entity, Or<(With<Player>, With<Ball>, With<Projectile>)>>,
+    mut commands: Commands,
+) {
+    for message in reader.read() {
+        if reconnect.attempts > 0 {
+            info!("Reconnected after {} attempt(s)", reconnect.attempts);
+        }
+        *reconnect = ReconnectState::default();
+
+        // Replicon keeps entities from a previous session around, so drop them along with the
+        // local prediction before the server replicates the world again.
+        for entity in &stale_entities {
+            commands.entity(entity).despawn();
+        }
+        commands.remove_resource::<DisconnectNotice>();
+        commands.remove_resource::<NetworkError>();
+
+        let Some(client_id) = message.client_id else {
+            warn!("Connected without receiving a client id");
+            continue;
+        };
+        info!("Client Id is: {}", client_id);
+
+        commands.insert_resource(MyClientId(client_id));
+    }
+}
+
+#[derive(Resource)]
+struct MyClientId(u64);
+
+fn update_net_state(
+    client: Res<QuinnetClient>,
+    state: Res<State<NetState>>,
+    mut next_state: ResMut<NextState<NetState>>,
+) {
+    let current = if client.is_connected() {
+        // The connection alone doesn't tell whether the world is in, `on_initial_snapshot` does.
+        match state.get() {
+            NetState::InGame => NetState::InGame,
+            _ => NetState::Connected,
+        }
+    } else if client.is_connecting() {
+        NetState::Connecting
+    } else {
+        NetState::Offline
+    };
+
+    if *state.get() != current {
+        info!("Network state: {:?} -> {:?}", state.get(), current);
+        next_state.set(current);
+    }
+}
+
+fn record_connection_events(
+    mut connected: MessageReader<ConnectionEvent>,
+    mut lost: MessageReader<ConnectionLostEvent>,
+    mut failed: MessageReader<ConnectionFailedEvent>,
+    mut log: ResMut<ConnectionLog>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed_secs();
+
+    for event in connected.read() {
+        log.client_id = event.client_id;
+        log.push(now, ConnectionLogKind::Connected, "Connected to the server");
+    }
+    for _event in lost.read() {
+        log.push(now, ConnectionLogKind::Disconnected, "Connection lost");
+    }
+    for event in failed.read() {
+        log.push(
+            now,
+            ConnectionLogKind::Error,
+            format!("Connection failed: {}", event.err),
+        );
+    }
+}
+
+fn clear_session(mut commands: Commands) {
+    commands.remove_resource::<MyClientId>();
+    commands.remove_resource::<ConnectionStats>();
+    commands.remove_resource::<GameStarted>();
+    commands.remove_resource::<Paused>();
+    commands.remove_resource::<GameConfig>();
+    // Another server, or the same one restarted, counts its ticks anew.
+    commands.insert_resource(ServerClock::default());
+    commands.insert_resource(Roster::default());
+    commands.insert_resource(PingStats::default());
+    commands.insert_resource(ConnectionQuality::default());
+    commands.insert_resource(QualityWindow::default());
+}
+
+fn send_connect_intent(args: Res<Args>, mut commands: Commands) {
+    commands.client_trigger(ConnectIntent {
+        spectator: args.spectator,
+        token: args.token.clone(),
+    });
+    if let Some(room_id) = args.room {
+        commands.client_trigger(JoinRoom { room_id });
+    }
+}
+
+fn setup_client(
+    args: Res<Args>,
+    servers: Res<ServerList>,
+    channels: Res<RepliconChannels>,
+    budget: Res<NetworkBudget>,
+    mut client: ResMut<QuinnetClient>,
+    mut commands: Commands,
+) {
+    // A failed attempt leaves no connection behind, so `reconnect` picks it up from here.
+    let server = servers.active();
+    if let Err(e) = open_server_connection(&mut client, &args, server, &channels, &budget) {
+        error!("Failed to open connection: {:?}", e);
+        commands.insert_resource(NetworkError(format!("Failed to open connection: {e}")));
+    }
+
+    commands.spawn((
+        Camera2d,
+        FreeCamera,
+        Actions::<FreeCamera>::spawn(SpawnWith(|context: &mut ActionSpawner<_>| {
+            context.spawn((
+                Action::<PanCamera>::new(),
+                Bindings::spawn((Cardinal::wasd_keys(), Cardinal::arrows())),
+            ));
+            let grab = context
+                .spawn((Action::<GrabCamera>::new(), bindings![MouseButton::Left]))
+                .id();
+            context.spawn((
+                Action::<DragCamera>::new(),
+                Chord::single(grab),
+                bindings![Binding::mouse_motion()],
+            ));
+        })),
+    ));
+    commands.spawn((
+        ClientControls,
+        actions!(
+            ClientControls[
+                (
+                    Action::<ToggleConnection>::new(),
+                    bindings![KeyCode::Escape],
+                ),
+                (Action::<NextServer>::new(), bindings![KeyCode::F2]),
+                (Action::<ToggleMinimap>::new(), bindings![KeyCode::KeyM]),
+            ]
+        ),
+    ));
+}
+
+fn on_toggle_connection(
+    _toggle: On<Start<ToggleConnection>>,
+    left: Option<Res<LeftServer>>,
+    mut commands: Commands,
+) {
+    if left.is_some() {
+        commands.trigger(JoinServer);
+    } else {
+        commands.trigger(LeaveServer);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn on_leave_server(
+    _leave: On<LeaveServer>,
+    replicated: Query<Entity, Or<(With<Player>, With<Ball>, With<Projectile>)>>,
+    mut commands: Commands,
+) {
+    info!("Leaving the server");
+    commands.insert_resource(LeftServer);
+    close_connection(&mut commands, DisconnectReason::UserRequested);
+
+    // The local player carries the prediction buffers, so this also resets them.
+    for entity in &replicated {
+        commands.entity(entity).despawn();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn on_join_server(
+    _join: On<JoinServer>,
+    args: Res<Args>,
+    servers: Res<ServerList>,
+    channels: Res<RepliconChannels>,
+    budget: Res<NetworkBudget>,
+    mut client: ResMut<QuinnetClient>,
+    mut reconnect: ResMut<ReconnectState>,
+    mut commands: Commands,
+) {
+    commands.remove_resource::<LeftServer>();
+    commands.remove_resource::<DisconnectNotice>();
+    *reconnect = ReconnectState::default();
+
+    client.close_all_connections();
+    let server = servers.active();
+    if let Err(e) = open_server_connection(&mut client, &args, server, &channels, &budget) {
+        error!("Failed to open connection: {:?}", e);
+        commands.insert_resource(NetworkError(format!("Failed to open connection: {e}")));
+    }
+}
+
+fn on_next_server(
+    _next: On<Start<NextServer>>,
+    mut servers: ResMut<ServerList>,
+    mut commands: Commands,
+) {
+    if servers.servers.len() < 2 {
+        info!("No other server to switch to, pass several with --server");
+        return;
+    }
+    let server = servers.advance();
+    info!(
+        "Switching to server {}/{}: {server}",
+        servers.active + 1,
+        servers.servers.len()
+    );
+    // Leaving drops the replicated world, and going offline clears the rest of the session.
+    commands.trigger(LeaveServer);
+    commands.insert_resource(SwitchingServer);
+}
+
+/// Joins the newly active server once the connection to the previous one is closed
+fn join_next_server(mut commands: Commands) {
+    commands.remove_resource::<SwitchingServer>();
+    commands.trigger(JoinServer);
+}
+
+fn open_server_connection(
+    client: &mut QuinnetClient,
+    args: &Args,
+    server: SocketAddr,
+    channels: &RepliconChannels,
+    budget: &NetworkBudget,
+) -> Result<ConnectionLocalId, AsyncChannelError> {
+    let (ip, port) = (server.ip(), server.port());
+
+    let connection_id = client.open_connection(ClientConnectionConfiguration {
+        addr_config: ClientAddrConfiguration::from_ips(ip, port, Ipv6Addr::UNSPECIFIED, 0),
+        cert_mode: certificate_verification_mode(args),
+        defaultables: ClientConnectionConfigurationDefaultables {
+            send_channels_cfg: budget.client_configs(channels),
+        },
+    })?;
+
+    info!("Client connecting to [{ip}]:{port}");
+
+    Ok(connection_id)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn reconnect(
+    mut client: ResMut<QuinnetClient>,
+    mut state: ResMut<ReconnectState>,
+    policy: Res<ReconnectPolicy>,
+    args: Res<Args>,
+    mut servers: ResMut<ServerList>,
+    channels: Res<RepliconChannels>,
+    budget: Res<NetworkBudget>,
+    time: Res<Time>,
+    mut failed: MessageWriter<ReconnectFailed>,
+    mut commands: Commands,
+) {
+    if state.exhausted {
+        return;
+    }
+
+    let Some(timer) = state.timer.as_mut() else {
+        warn!(
+            "Lost connection to the server, reconnecting in {:?}",
+            policy.backoff
+        );
+        state.timer = Some(Timer::new(policy.backoff, TimerMode::Once));
+        return;
+    };
+    if !timer.tick(time.delta()).is_finished() {
+        return;
+    }
+
+    if state.attempts >= policy.max_attempts {
+        error!("Giving up after {} reconnection attempts", state.attempts);
+        state.exhausted = true;
+        failed.write(ReconnectFailed {
+            attempts: state.attempts,
+        });
+        return;
+    }
+
+    state.attempts += 1;
+    info!(
+        "Reconnection attempt {}/{}",
+        state.attempts, policy.max_attempts
+    );
+
+    // With several servers, hop to the next one in case this one went down for good.
+    let server = if servers.servers.len() > 1 {
+        let server = servers.advance();
+        info!("Trying the next server: {server}");
+        server
+    } else {
+        servers.active()
+    };
+    // The dead connection would otherwise stay the default one.
+    client.close_all_connections();
+    if let Err(e) = open_server_connection(&mut client, &args, server, &channels, &budget) {
+        warn!("Failed to reopen connection: {:?}", e);
+        commands.insert_resource(NetworkError(format!("Failed to reopen connection: {e}")));
+    }
+
+    let delay = policy
+        .backoff
+        .saturating_mul(2u32.saturating_pow(state.attempts));
+    state.timer = Some(Timer::new(delay, TimerMode::Once));
+}
+
+fn start_connect_timer(timeout: Res<ConnectTimeout>, mut commands: Commands) {
+    commands.insert_resource(ConnectTimer(Timer::new(timeout.0, TimerMode::Once)));
+}
+
+fn stop_connect_timer(mut commands: Commands) {
+    commands.remove_resource::<ConnectTimer>();
+}
+
+fn check_connect_timeout(
+    timer: Option<ResMut<ConnectTimer>>,
+    servers: Res<ServerList>,
+    time: Res<Time<Real>>,
+    mut failed: MessageWriter<ConnectFailed>,
+    mut commands: Commands,
+) {
+    let Some(mut timer) = timer else {
+        return;
+    };
+    if !timer.0.tick(time.delta()).is_finished() {
+        return;
+    }
+
+    let reason = format!(
+        "No answer from {} after {:.0} seconds",
+        servers.active(),
+        timer.0.duration().as_secs_f32()
+    );
+    warn!("{reason}, aborting the connection attempt");
+    // Once the connection is gone `NetState` goes back to offline, where reconnection takes over.
+    // Nothing to tell a server that never answered.
+    commands.insert_resource(PendingClose(DisconnectReason::Timeout));
+    failed.write(ConnectFailed { reason });
+}
+
+fn read_connect_failures(
+    mut reader: MessageReader<ConnectFailed>,
+    mut log: ResMut<ConnectionLog>,
+    time: Res<Time<Real>>,
+    mut commands: Commands,
+) {
+    for failure in reader.read() {
+        log.push(
+            time.elapsed_secs(),
+            ConnectionLogKind::Error,
+            failure.reason.clone(),
+        );
+        commands.insert_resource(NetworkError(failure.reason.clone()));
+    }
+}
+
+fn read_reconnect_failures(mut reader: MessageReader<ReconnectFailed>, mut commands: Commands) {
+    for failure in reader.read() {
+        commands.insert_resource(DisconnectNotice(format!(
+            "Could not reconnect to the server after {} attempts.",
+            failure.attempts
+        )));
+    }
+}
+
+fn certificate_verification_mode(args: &Args) -> CertificateVerificationMode {
+    if args.insecure {
+        warn!("Server certificate verification is disabled");
+        return CertificateVerificationMode::SkipVerification;
+    }
+
+    // Unlike quinnet's default, a changed fingerprint aborts the connection instead of waiting
+    // for an interactive decision, and is surfaced through `CertificateWarning`.
+    // Hosts are keyed by the server IP, which is also the name the server issues its generated
+    // certificate for unless it was started with `--hostname`.
+    CertificateVerificationMode::TrustOnFirstUse(TrustOnFirstUseConfig {
+        known_hosts: KnownHosts::HostsFile(known_hosts_path().to_string_lossy().into_owned()),
+        verifier_behaviour: HashMap::from([
+            (
+                CertVerificationStatus::UnknownCertificate,
+                CertVerifierBehaviour::ImmediateAction(CertVerifierAction::TrustAndStore),
+            ),
+            (
+                CertVerificationStatus::UntrustedCertificate,
+                CertVerifierBehaviour::ImmediateAction(CertVerifierAction::AbortConnection),
+            ),
+            (
+                CertVerificationStatus::TrustedCertificate,
+                CertVerifierBehaviour::ImmediateAction(CertVerifierAction::TrustOnce),
+            ),
+        ]),
+    })
+}
+
+/// Location of the trust-on-first-use fingerprint store inside the user's config directory
+fn known_hosts_path() -> PathBuf {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| std::env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_default();
+
+    config_dir.join("quinnet-testing").join("known_hosts")
+}
+
+fn read_certificate_events(
+    mut trusted: MessageReader<CertTrustUpdateEvent>,
+    mut aborted: MessageReader<CertConnectionAbortEvent>,
+    mut warnings: MessageWriter<CertificateWarning>,
+) {
+    for event in trusted.read() {
+        info!(
+            "Trusting new certificate for {}: {}",
+            event.cert_info.server_name, event.cert_info.fingerprint
+        );
+    }
+
+    for event in aborted.read() {
+        error!(
+            "Connection aborted, certificate for {} is {:?}",
+            event.cert_info.server_name, event.status
+        );
+        warnings.write(CertificateWarning {
+            server_name: event.cert_info.server_name.to_string(),
+            fingerprint: event.cert_info.fingerprint.to_string(),
+            known_fingerprint: event
+                .cert_info
+                .known_fingerprint
+                .as_ref()
+                .map(ToString::to_string),
+        });
+    }
+}
+
+fn update_connection_stats(client: Res<QuinnetClient>, mut commands: Commands) {
+    let Some(quinn_stats) = client
+        .get_connection()
+        .and_then(|connection| connection.quinn_connection_stats())
+    else {
+        commands.remove_resource::<ConnectionStats>();
+        return;
+    };
+
+    let path = quinn_stats.path;
+    let packet_loss = if path.sent_packets == 0 {
+        0.0
+    } else {
+        100.0 * path.lost_packets as f32 / path.sent_packets as f32
+    };
+
+    commands.insert_resource(ConnectionStats {
+        rtt_ms: path.rtt.as_secs_f32() * 1000.0,
+        packet_loss,
+        sent_packets: path.sent_packets,
+        lost_packets: path.lost_packets,
+    });
+}
+
+/// Judges the connection once per [`QUALITY_WINDOW`]. Every poor window grows the interpolation
+/// delay by a step, and [`QUALITY_RELAX_WINDOWS`] good ones in a row shrink it by one again.
+fn evaluate_connection_quality(
+    stats: Res<ConnectionStats>,
+    config: Res<ConnectionQualityConfig>,
+    mut quality: ResMut<ConnectionQuality>,
+    mut window: ResMut<QualityWindow>,
+    time: Res<Time<Real>>,
+) {
+    let counts = (stats.sent_packets, stats.lost_packets);
+    let Some((sent_before, lost_before)) = window.start else {
+        window.start = Some(counts);
+        return;
+    };
+    window.elapsed += time.delta();
+    if window.elapsed < QUALITY_WINDOW {
+        return;
+    }
+    window.elapsed = Duration::ZERO;
+    window.start = Some(counts);
+
+    // The totals in `ConnectionStats` cover the whole connection and would hide a recent change.
+    let sent = stats.sent_packets.saturating_sub(sent_before);
+    let lost = stats.lost_packets.saturating_sub(lost_before);
+    let loss = if sent == 0 {
+        0.0
+    } else {
+        100.0 * lost as f32 / sent as f32
+    };
+
+    let mut next = *quality;
+    if stats.rtt_ms > config.poor_rtt_ms || loss > config.poor_loss {
+        window.good = 0;
+        next.poor = true;
+        next.extra_delay_ticks =
+            (next.extra_delay_ticks + config.delay_step).min(config.max_extra_delay);
+    } else if stats.rtt_ms < config.poor_rtt_ms * QUALITY_RECOVERY
+        && loss < config.poor_loss * QUALITY_RECOVERY
+    {
+        window.good += 1;
+        if window.good >= QUALITY_RELAX_WINDOWS {
+            window.good = 0;
+            next.poor = false;
+            next.extra_delay_ticks = next.extra_delay_ticks.saturating_sub(config.delay_step);
+        }
+    } else {
+        // Between the thresholds the connection hasn't recovered yet, but isn't worse either.
+        window.good = 0;
+    }
+
+    if next.poor && !quality.poor {
+        warn!(
+            "Poor connection: RTT {:.0} ms, loss {:.1}%",
+            stats.rtt_ms, loss
+        );
+    } else if !next.poor && quality.poor {
+        info!("Connection recovered");
+    }
+    quality.set_if_neq(next);
+}
+
+fn send_pings(mut stats: ResMut<PingStats>, time: Res<Time<Real>>, mut commands: Commands) {
+    if !stats.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let id = stats.next_id;
+    stats.next_id = stats.next_id.wrapping_add(1);
+    // Pongs that never came back were lost, so don't let them pile up.
+    if stats.outstanding.len() >= PING_SAMPLES {
+        stats.outstanding.pop_front();
+    }
+    stats.outstanding.push_back(id);
+    commands.client_trigger(Ping {
+        id,
+        client_time_ms: time.elapsed().as_millis() as u64,
+    });
+}
+
+fn on_pong(pong: On<Pong>, mut stats: ResMut<PingStats>, time: Res<Time<Real>>) {
+    let Some(index) = stats.outstanding.iter().position(|&id| id == pong.id) else {
+        // Duplicated, or sent before the session was reset.
+        return;
+    };
+    stats.outstanding.remove(index);
+
+    let now_ms = time.elapsed().as_millis() as u64;
+    let rtt_ms = now_ms.saturating_sub(pong.client_time_ms);
+    if stats.samples.len() >= PING_SAMPLES {
+        stats.samples.pop_front();
+    }
+    stats.samples.push_back(rtt_ms as f32);
+}
+
+fn on_collision_hit(_hit: On<CollisionHit>, mut rumble: ResMut<RumbleState>) {
+    rumble.pushed = true;
+}
+
+fn rumble_on_collision(
+    settings: Res<CollisionRumble>,
+    mut state: ResMut<RumbleState>,
+    time: Res<Time>,
+    gamepads: Query<Entity, With<Gamepad>>,
+    mut requests: MessageWriter<GamepadRumbleRequest>,
+) {
+    state.remaining = state.remaining.saturating_sub(time.delta());
+    if !std::mem::take(&mut state.pushed) || !state.remaining.is_zero() {
+        return;
+    }
+    // Only clamped for values edited in the inspector, `Args` refuses them when parsing
+    let intensity = settings.intensity.clamp(0.0, 1.0);
+    if intensity == 0.0 {
+        return;
+    }
+
+    state.remaining = settings.duration;
+    // Without a gamepad there is nothing to send, and nothing else to fall back to.
+    for gamepad in &gamepads {
+        requests.write(GamepadRumbleRequest::Add {
+            duration: settings.duration,
+            intensity: GamepadRumbleIntensity {
+                strong_motor: intensity,
+                weak_motor: intensity,
+            },
+            gamepad,
+        });
+    }
+}
+
+/// Rebuilds transforms for players the server replicates as compact `NetPosition`s
+fn apply_net_positions(mut query: Query<(&NetPosition, &mut Transform), Changed<NetPosition>>) {
+    for (net_position, mut transform) in query.iter_mut() {
+        transform.translation = net_position.get().extend(transform.translation.z);
+    }
+}
+
+fn update_server_clock(
+    mut replicated: MessageReader<EntityReplicated>,
+    mut clock: ResMut<ServerClock>,
+    config: Res<GameConfig>,
+    time: Res<Time<Real>>,
+) {
+    for update in replicated.read() {
+        clock.record(update.tick, config.replication_rate, time.elapsed());
+    }
+}
+
+/// Derives the velocity of remote players from their last two updates
+fn record_remote_updates(
+    mut replicated: MessageReader<EntityReplicated>,
+    mut players: Query<(&Transform, Option<&NetPosition>, &mut Extrapolation)>,
+    config: Res<GameConfig>,
+) {
+    for update in replicated.read() {
+        let Ok((transform, net_position, mut extrapolation)) = players.get_mut(update.entity)
+        else {
+            continue;
+        };
+        if extrapolation
+            .received
+            .is_some_and(|tick| update.tick <= tick)
+        {
+            continue;
+        }
+        // Replicon writes before this runs, so these hold the update's position.
+        let position = net_position.map_or(transform.translation.xy(), |net_position| {
+            net_position.get()
+        });
+        if let Some(tick) = extrapolation.received {
+            // Ticks rather than arrival times, which jitter with the network.
+            let elapsed = (update.tick - tick) as f32 / config.replication_rate as f32;
+            if elapsed > 0.0 {
+                extrapolation.velocity = (position - extrapolation.position) / elapsed;
+            }
+        }
+        extrapolation.position = position;
+        extrapolation.received = Some(update.tick);
+    }
+}
+
+/// Keeps remote players moving while their next update is overdue, for at most
+/// [`ExtrapolationConfig::max_ms`]. The next update overwrites the transform, snapping them back.
+///
+/// The server only sends players that move, so one whose updates stay away longer most likely
+/// stopped at its last position. It's eased back there over another `max_ms` and left alone.
+fn extrapolate_remote_players(
+    mut players: Query<(&mut Transform, &Extrapolation)>,
+    extrapolation_config: Res<ExtrapolationConfig>,
+    config: Res<GameConfig>,
+    clock: Res<ServerClock>,
+    time: Res<Time<Real>>,
+) {
+    let Some(now) = clock.now(time.elapsed()) else {
+        return;
+    };
+    let interval = 1.0 / config.replication_rate;
+    let max = f64::from(extrapolation_config.max_ms) / 1000.0;
+    for (mut transform, extrapolation) in &mut players {
+        let Some(sent_at) = extrapolation
+            .received
+            .and_then(|tick| clock.server_time(tick))
+        else {
+            continue;
+        };
+        // Measured on the server's clock, so jitter in when the last update arrived doesn't shift
+        // when the next one is due.
+        let overdue = now - sent_at - interval;
+        if overdue <= 0.0 || extrapolation.velocity == Vec2::ZERO {
+            continue;
+        }
+        let ahead = if overdue <= max {
+            overdue
+        } else {
+            (max * 2.0 - overdue).max(0.0)
+        };
+        let position = extrapolation.position + extrapolation.velocity * ahead as f32;
+        transform.translation = config
+            .bounds
+            .clamp(position)
+            .extend(transform.translation.z);
+    }
+}
+
+/// Name used without `--name`, the server may still rename the player
+fn default_player_name(client_id: u64) -> String {
+    format!("Player-{:04x}", client_id & 0xffff)
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn handle_new_players(
+    mut query: Query<
+        (
+            Entity,
+            &Player,
+            &Transform,
+            Option<&PlayerColor>,
+            Option<&PlayerShape>,
+            Option<&Facing>,
+        ),
+        Added<Player>,
+    >,
+    local_players: Query<Entity, With<LocalPlayer>>,
+    client_id: Option<Res<MyClientId>>,
+    args: Res<Args>,
+    input_settings: Res<InputSettings>,
+    key_bindings: Res<KeyBindings>,
+    fade: Option<Res<SpawnFade>>,
+    mut meshes: Option<ResMut<Assets<Mesh>>>,
+    mut materials: Option<ResMut<Assets<ColorMaterial>>>,
+    mut commands: Commands,
+) {
+    let Some(client_id) = client_id else {
+        return;
+    };
+    let mut local_player = local_players.iter().next();
+
+    for (entity, player, transform, color, shape, facing) in query.iter_mut() {
+        let color = color.map_or(Color::WHITE, |color| color.0);
+        let shape = shape.copied().unwrap_or_default();
+        let facing = facing.map_or(0.0, |facing| facing.0);
+        commands.entity(entity).insert(Visibility::default());
+        let mut body = commands.spawn((
+            PlayerBody,
+            Transform::from_rotation(Quat::from_rotation_z(facing)),
+            ChildOf(entity),
+        ));
+        match (shape.kind, meshes.as_mut(), materials.as_mut()) {
+            (ShapeKind::Circle, Some(meshes), Some(materials)) => {
+                let radii = shape.size / 2.0;
+                body.insert((
+                    Mesh2d(meshes.add(Ellipse::new(radii.x, radii.y))),
+                    MeshMaterial2d(materials.add(color)),
+                ));
+            }
+            // Without 2D meshes, as when running headless, circles make do with a square.
+            _ => {
+                body.insert(Sprite::from_color(color, shape.size));
+            }
+        }
+        if let Some(fade) = &fade {
+            // `fade_in_players` shrinks and hides it before it's first drawn.
+            body.insert(SpawnAnim(Timer::new(fade.0, TimerMode::Once)));
+        }
+        // A notch on the front edge, so even a circle shows which way it faces.
+        let notch = shape.size.min_element() / 5.0;
+        body.with_child((
+            Sprite::from_color(Color::srgba(1.0, 1.0, 1.0, 0.8), Vec2::splat(notch)),
+            Transform::from_xyz((shape.size.x - notch) / 2.0, 0.0, 0.5),
+        ));
+
+        let is_ours = player.network_id == client_id.0;
+        // A reconnect race could replicate two entities with our id, only one may take input.
+        if is_ours && let Some(existing) = local_player {
+            warn!(
+                "Entity {:?} also has our network id {}, keeping {:?} as the local player",
+                entity, player.network_id, existing
+            );
+        }
+        if is_ours && local_player.is_none() {
+            local_player = Some(entity);
+            info!("Adding local player controls to entity {:?}", entity);
+            // Sent once the server has spawned us, so we know the client is authorized by now.
+            let name = args
+                .name
+                .clone()
+                .unwrap_or_else(|| default_player_name(client_id.0));
+            commands.client_trigger(SetPlayerName(name));
+            commands.entity(entity).insert((
+                LocalPlayer,
+                Prediction {
+                    position: transform.translation.xy(),
+                    ..default()
+                },
+                actions!(
+                    LocalPlayer[
+                        (
+                            Action::<PlayerMovement>::new(),
+                            input_settings.dead_zone(),
+                            input_settings.scale(),
+                            key_bindings.movement(),
+                        ),
+                        (Action::<Shoot>::new(), key_bindings.shoot()),
+                        (Action::<Dash>::new(), key_bindings.dash()),
+                    ]
+                ),
+            ));
+        } else {
+            info!("Adding remote player visuals to entity {:?}", entity);
+            commands.entity(entity).insert((
+                TransformInterpolation,
+                Extrapolation {
+                    position: transform.translation.xy(),
+                    ..default()
+                },
+            ));
+        }
+    }
+}
+
+/// Cleans up after players the server removed, which replicon despawns or strips of `Player`
+#[allow(clippy::type_complexity)]
+fn on_player_removed(
+    remove: On<Remove, Player>,
+    players: Query<(&Player, Has<LocalPlayer>, Option<&Children>)>,
+    bodies: Query<
+        (
+            &GlobalTransform,
+            Option<&Sprite>,
+            Option<&Mesh2d>,
+            Option<&MeshMaterial2d<ColorMaterial>>,
+        ),
+        With<PlayerBody>,
+    >,
+    fade: Option<Res<SpawnFade>>,
+    mut rumble: ResMut<RumbleState>,
+    mut commands: Commands,
+) {
+    let Ok((player, is_local, children)) = players.get(remove.entity) else {
+        return;
+    };
+    debug!("Player {} was removed", player.network_id);
+
+    // Despawning takes the visuals along, but an entity that only lost `Player` would keep them.
+    commands.entity(remove.entity).try_remove::<(
+        Visibility,
+        TransformInterpolation,
+        Extrapolation,
+        LocalPlayer,
+        Prediction,
+    )>();
+    for child in children.into_iter().flatten() {
+        commands.entity(*child).try_despawn();
+    }
+
+    // The body goes along with the player, so a stand-in left where it was fades out instead.
+    if let Some(fade) = fade
+        && let Some((global_transform, sprite, mesh, material)) = children
+            .into_iter()
+            .flatten()
+            .find_map(|child| bodies.get(*child).ok())
+    {
+        let mut stand_in = commands.spawn((
+            DespawnAnim(Timer::new(fade.0, TimerMode::Once)),
+            global_transform.compute_transform(),
+        ));
+        if let Some(sprite) = sprite {
+            stand_in.insert(sprite.clone());
+        }
+        if let (Some(mesh), Some(material)) = (mesh, material) {
+            stand_in.insert((mesh.clone(), material.clone()));
+        }
+    }
+
+    if is_local {
+        // `MyClientId` stays, the connection is still up and `handle_new_players` needs it to
+        // recognize our player if the server spawns it again. Going offline clears it.
+        info!("Our player was removed by the server");
+        *rumble = RumbleState::default();
+    }
+}
+
+fn apply_input_settings(
+    settings: Res<InputSettings>,
+    mut movement: Query<(&mut DeadZone, &mut Scale), With<Action<PlayerMovement>>>,
+) {
+    for (mut dead_zone, mut scale) in &mut movement {
+        *dead_zone = settings.dead_zone();
+        *scale = settings.scale();
+    }
+}
+
+/// Swaps the bindings of a spawned local player for the current [`KeyBindings`]
+fn apply_key_bindings(
+    key_bindings: Res<KeyBindings>,
+    movement: Query<Entity, With<Action<PlayerMovement>>>,
+    shoot: Query<Entity, With<Action<Shoot>>>,
+    dash: Query<Entity, With<Action<Dash>>>,
+    mut commands: Commands,
+) {
+    for action in &movement {
+        commands
+            .entity(action)
+            .despawn_related::<Bindings>()
+            .insert(key_bindings.movement());
+    }
+    for action in &shoot {
+        commands
+            .entity(action)
+            .despawn_related::<Bindings>()
+            .insert(key_bindings.shoot());
+    }
+    for action in &dash {
+        commands
+            .entity(action)
+            .despawn_related::<Bindings>()
+            .insert(key_bindings.dash());
+    }
+}
+
+fn handle_new_balls(
+    balls: Query<Entity, Added<Ball>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut commands: Commands,
+) {
+    for entity in &balls {
+        commands.entity(entity).insert((
+            Mesh2d(meshes.add(Circle::new(BALL_RADIUS))),
+            MeshMaterial2d(materials.add(Color::WHITE)),
+            TransformInterpolation,
+        ));
+    }
+}
+
+fn handle_new_projectiles(
+    projectiles: Query<Entity, Added<Projectile>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut commands: Commands,
+) {
+    for entity in &projectiles {
+        commands.entity(entity).insert((
+            Mesh2d(meshes.add(Circle::new(PROJECTILE_RADIUS))),
+            MeshMaterial2d(materials.add(Color::srgb(1.0, 0.8, 0.2))),
+            TransformInterpolation,
+        ));
+    }
+}
+
+fn update_name_labels(
+    players: Query<(Entity, &PlayerName, Option<&Children>), Changed<PlayerName>>,
+    mut labels: Query<&mut Text2d, With<NameLabel>>,
+    mut commands: Commands,
+) {
+    for (entity, name, children) in &players {
+        let label =
+            children.and_then(|children| children.iter().find(|&child| labels.contains(child)));
+
+        match label {
+            Some(label) => {
+                if let Ok(mut text) = labels.get_mut(label) {
+                    text.0.clone_from(&name.0);
+                }
+            }
+            None => {
+                commands.entity(entity).with_child((
+                    NameLabel,
+                    Text2d::new(name.0.clone()),
+                    TextFont::from_font_size(16.0),
+                    Transform::from_xyz(0.0, 40.0, 1.0),
+                ));
+            }
+        }
+    }
+}
+
+fn update_health_bars(
+    players: Query<(Entity, &Health, Option<&Children>), Changed<Health>>,
+    mut bars: Query<(&mut Sprite, &mut Transform), With<HealthBar>>,
+    mut commands: Commands,
+) {
+    for (entity, health, children) in &players {
+        commands.entity(entity).insert(if health.is_dead() {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        });
+
+        let fraction = if health.max > 0.0 {
+            (health.current / health.max).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let width = HEALTH_BAR_SIZE.x * fraction;
+        // Keeps the bar anchored on its left edge as it shrinks.
+        let x = (width - HEALTH_BAR_SIZE.x) / 2.0;
+
+        let bar = children.and_then(|children| children.iter().find(|&child| bars.contains(child)));
+        match bar {
+            Some(bar) => {
+                if let Ok((mut sprite, mut transform)) = bars.get_mut(bar) {
+                    sprite.custom_size = Some(Vec2::new(width, HEALTH_BAR_SIZE.y));
+                    transform.translation.x = x;
+                }
+            }
+            None => {
+                commands.entity(entity).with_children(|parent| {
+                    parent.spawn((
+                        Sprite::from_color(Color::linear_rgb(0.2, 0.0, 0.0), HEALTH_BAR_SIZE),
+                        Transform::from_xyz(0.0, 30.0, 1.0),
+                    ));
+                    parent.spawn((
+                        HealthBar,
+                        Sprite::from_color(
+                            Color::linear_rgb(0.0, 0.8, 0.0),
+                            Vec2::new(width, HEALTH_BAR_SIZE.y),
+                        ),
+                        Transform::from_xyz(x, 30.0, 2.0),
+                    ));
+                });
+            }
+        }
+    }
+}
+
+fn on_input(
+    movement: On<Fire<PlayerMovement>>,
+    state: Res<State<NetState>>,
+    budget: Res<NetworkBudget>,
+    time: Res<Time<Real>>,
+    mut predictions: Query<&mut Prediction>,
+    mut commands: Commands,
+) {
+    if *state.get() != NetState::InGame {
+        return;
+    }
+    let Ok(mut prediction) = predictions.get_mut(movement.context) else {
+        return;
+    };
+    // Held input fires every frame, the budget thins it out. The latest value goes out next time.
+    let since_last = time.elapsed().saturating_sub(prediction.last_sent);
+    if budget.max_intent_rate > 0.0 && since_last.as_secs_f32() < 1.0 / budget.max_intent_rate {
+        return;
+    }
+    prediction.last_sent = time.elapsed();
+    send_movement_intent(&mut prediction, movement.value, &mut commands);
+}
+
+fn on_input_ended(
+    movement: On<Complete<PlayerMovement>>,
+    state: Res<State<NetState>>,
+    mut predictions: Query<&mut Prediction>,
+    mut commands: Commands,
+) {
+    if *state.get() != NetState::InGame {
+        return;
+    }
+    if let Ok(mut prediction) = predictions.get_mut(movement.context) {
+        send_movement_intent(&mut prediction, movement.value, &mut commands);
+    }
+}
+
+/// Fires where the local player is predicted to walk, or where it last faced when standing still
+fn on_shoot(
+    shoot: On<Start<Shoot>>,
+    state: Res<State<NetState>>,
+    players: Query<(&Prediction, &Facing)>,
+    mut commands: Commands,
+) {
+    if *state.get() != NetState::InGame {
+        return;
+    }
+    let Ok((prediction, facing)) = players.get(shoot.context) else {
+        return;
+    };
+    let direction = prediction
+        .velocity
+        .try_normalize()
+        .unwrap_or_else(|| Vec2::from_angle(facing.0));
+    commands.client_trigger(FireProjectile { direction });
+}
+
+/// Dashes the way the local player would shoot, unless its last dash is still cooling down
+fn on_dash(
+    dash: On<Start<Dash>>,
+    state: Res<State<NetState>>,
+    players: Query<(&Prediction, &Facing, Option<&DashCooldown>)>,
+    mut commands: Commands,
+) {
+    if *state.get() != NetState::InGame {
+        return;
+    }
+    let Ok((prediction, facing, cooldown)) = players.get(dash.context) else {
+        return;
+    };
+    // The server would ignore it anyway.
+    if cooldown.is_some_and(|cooldown| !cooldown.0.is_finished()) {
+        return;
+    }
+    let direction = prediction
+        .velocity
+        .try_normalize()
+        .unwrap_or_else(|| Vec2::from_angle(facing.0));
+    commands.client_trigger(DashIntent { direction });
+}
+
+/// Counts replicated cooldowns down, the server only sends them again on the next dash
+fn tick_dash_cooldowns(mut cooldowns: Query<&mut DashCooldown>, time: Res<Time>) {
+    for mut cooldown in &mut cooldowns {
+        cooldown.0.tick(time.delta());
+    }
+}
+
+fn send_movement_intent(prediction: &mut Prediction, direction: Vec2, commands: &mut Commands) {
+    prediction.input = direction;
+    prediction.seq += 1;
+    commands.client_trigger(ClientMovementIntent {
+        seq: prediction.seq,
+        direction,
+    });
+}
+
+#[derive(Component)]
+/// Backdrop showing the area the server confines players to
+struct WorldBorder;
+
+fn on_game_config(
+    config: On<GameConfig>,
+    args: Res<Args>,
+    borders: Query<Entity, With<WorldBorder>>,
+    mut commands: Commands,
+) {
+    info!("Received game config: {:?}", *config);
+    commands.insert_resource(*config);
+    if args.interpolation_delay_ticks.is_none() {
+        commands.insert_resource(InterpolationConfig::for_tick_rate(config.replication_rate));
+    }
+
+    for entity in &borders {
+        commands.entity(entity).despawn();
+    }
+    let bounds = config.bounds;
+    commands.spawn((
+        WorldBorder,
+        Sprite::from_color(Color::srgb(0.1, 0.1, 0.12), bounds.max - bounds.min),
+        Transform::from_translation(((bounds.min + bounds.max) / 2.0).extend(-1.0)),
+    ));
+}
+
+/// Transform interpolation eases over one fixed timestep, so stretch it to the configured delay
+fn apply_interpolation_config(
+    interpolation: Res<InterpolationConfig>,
+    quality: Res<ConnectionQuality>,
+    game_config: Option<Res<GameConfig>>,
+    mut time: ResMut<Time<Fixed>>,
+) {
+    // Updates only arrive at the replication rate, which may be below the tick rate.
+    let rate = game_config.map_or(DEFAULT_TICK_RATE, |config| config.replication_rate);
+    let delay_ticks = interpolation.delay_ticks + quality.extra_delay_ticks;
+    let timestep = delay_ticks as f64 / rate;
+    info!(
+        "Interpolating remote players over {} update(s) ({:.1} ms)",
+        delay_ticks,
+        timestep * 1000.0
+    );
+    time.set_timestep_seconds(timestep);
+}
+
+fn on_connection_rejected(
+    rejection: On<ConnectionRejected>,
+    mut log: ResMut<ConnectionLog>,
+    time: Res<Time<Real>>,
+    mut commands: Commands,
+) {
+    error!("Server refused the connection: {}", rejection.reason);
+    log.push(
+        time.elapsed_secs(),
+        ConnectionLogKind::Error,
+        format!("Rejected: {}", rejection.reason),
+    );
+    commands.insert_resource(DisconnectNotice(rejection.reason.clone()));
+}
+
+fn on_initial_snapshot(
+    _snapshot: On<InitialSnapshot>,
+    state: Res<State<NetState>>,
+    mut next_state: ResMut<NextState<NetState>>,
+) {
+    // Replicon may see the connection a frame before `update_net_state` does.
+    if matches!(state.get(), NetState::Connecting | NetState::Connected) {
+        info!("Network state: {:?} -> {:?}", state.get(), NetState::InGame);
+        next_state.set(NetState::InGame);
+    }
+}
+
+/// Snaps the local player to where the server put it, dropping the moves it already accounted for
+fn on_force_position(
+    force: On<ForcePosition>,
+    mut query: Query<(&mut Transform, &mut Prediction), With<LocalPlayer>>,
+) {
+    let Ok((mut transform, mut prediction)) = query.single_mut() else {
+        return;
+    };
+    warn!(
+        "Server corrected our position to {} at input {}",
+        force.pos, force.seq
+    );
+    while prediction
+        .pending
+        .front()
+        .is_some_and(|predicted| predicted.seq <= force.seq)
+    {
+        prediction.pending.pop_front();
+    }
+    // Changing the transform makes the prediction treat it as authoritative and replay the moves
+    // the server hasn't seen yet on top of it.
+    transform.translation = force.pos.extend(transform.translation.z);
+}
+
+fn on_kicked(
+    kicked: On<Kicked>,
+    mut log: ResMut<ConnectionLog>,
+    time: Res<Time<Real>>,
+    mut commands: Commands,
+) {
+    warn!("Kicked from the server: {}", kicked.reason);
+    log.push(
+        time.elapsed_secs(),
+        ConnectionLogKind::Disconnected,
+        format!("Kicked: {}", kicked.reason),
+    );
+    // Rejoining is left to the player, reconnecting on our own would just undo the kick.
+    commands.insert_resource(LeftServer);
+    commands.insert_resource(DisconnectNotice(kicked.reason.clone()));
+}
+
+/// Replicon compares the hash of every registered event and replication rule on connect, and the
+/// server refuses clients built with different ones
+fn on_protocol_mismatch(
+    _mismatch: On<ProtocolMismatch>,
+    mut log: ResMut<ConnectionLog>,
+    time: Res<Time<Real>>,
+    mut commands: Commands,
+) {
+    let reason = "The server runs an incompatible version of the game, update the client to match";
+    error!("{reason}");
+    log.push(
+        time.elapsed_secs(),
+        ConnectionLogKind::Disconnected,
+        reason.to_string(),
+    );
+    // Reconnecting would only be refused again.
+    commands.insert_resource(LeftServer);
+    commands.insert_resource(DisconnectNotice(reason.to_string()));
+}
+
+fn on_idle_kick(
+    kick: On<IdleKick>,
+    mut log: ResMut<ConnectionLog>,
+    time: Res<Time<Real>>,
+    mut commands: Commands,
+) {
+    let reason = format!(
+        "Disconnected after {:.0}s without input",
+        kick.timeout.as_secs_f32()
+    );
+    warn!("{reason}");
+    log.push(
+        time.elapsed_secs(),
+        ConnectionLogKind::Disconnected,
+        reason.clone(),
+    );
+    commands.insert_resource(LeftServer);
+    commands.insert_resource(DisconnectNotice(reason));
+}
+
+fn on_server_shutdown(
+    shutdown: On<ServerShutdown>,
+    mut log: ResMut<ConnectionLog>,
+    time: Res<Time<Real>>,
+    mut commands: Commands,
+) {
+    info!("Server shut down: {}", shutdown.reason);
+    log.push(
+        time.elapsed_secs(),
+        ConnectionLogKind::Disconnected,
+        format!("Server shut down: {}", shutdown.reason),
+    );
+    // The server is going away, so there's no one to tell why.
+    commands.insert_resource(PendingClose(DisconnectReason::ServerShutdown));
+    commands.insert_resource(DisconnectNotice(shutdown.reason.clone()));
+}
+
+fn on_server_disconnect(disconnect: On<ServerDisconnect>, mut commands: Commands) {
+    info!("Server is disconnecting us: {:?}", disconnect.reason);
+    if disconnect.reason == DisconnectReason::Unauthorized {
+        // Reconnecting with the same token would only be refused again.
+        commands.insert_resource(LeftServer);
+    }
+}
+
+fn on_game_start(_start: On<GameStart>, mut commands: Commands) {
+    info!("Game started");
+    commands.insert_resource(GameStarted);
+}
+
+fn on_game_paused(paused: On<GamePaused>, mut commands: Commands) {
+    if paused.0 {
+        info!("Game paused");
+        commands.insert_resource(Paused);
+    } else {
+        info!("Game resumed");
+        commands.remove_resource::<Paused>();
+    }
+}
+
+/// Name of the player with the given network id, or a generic one if it isn't known yet
+fn display_name(players: &Query<(&Player, &PlayerName)>, network_id: u64) -> String {
+    players
+        .iter()
+        .find(|(player, _)| player.network_id == network_id)
+        .map(|(_, name)| name.0.clone())
+        .unwrap_or_else(|| format!("Player {network_id}"))
+}
+
+fn on_player_died(death: On<PlayerDied>, players: Query<(&Player, &PlayerName)>) {
+    info!("{} died", display_name(&players, death.network_id));
+}
+
+fn on_player_respawned(respawn: On<PlayerRespawned>, players: Query<(&Player, &PlayerName)>) {
+    info!("{} respawned", display_name(&players, respawn.network_id));
+}
+
+fn on_player_joined(joined: On<PlayerJoined>, mut chat: ResMut<ChatLog>) {
+    chat.push(format!("{} joined", joined.name));
+}
+
+fn on_player_left(left: On<PlayerLeft>, mut chat: ResMut<ChatLog>) {
+    chat.push(format!("{} left", left.name));
+}
+
+fn on_roster_update(update: On<RosterUpdate>, mut roster: ResMut<Roster>) {
+    roster.0.clone_from(&update.players);
+}
+
+fn on_broadcast_chat(
+    message: On<BroadcastChat>,
+    players: Query<(&Player, &PlayerName)>,
+    mut chat: ResMut<ChatLog>,
+) {
+    let sender = display_name(&players, message.sender);
+
+    chat.push(format!("{sender}: {}", message.text));
+}
+
+fn on_whisper_delivery(
+    message: On<WhisperDelivery>,
+    players: Query<(&Player, &PlayerName)>,
+    client_id: Option<Res<MyClientId>>,
+    mut chat: ResMut<ChatLog>,
+) {
+    // Whispers to yourself arrive once, as received.
+    let line = if client_id.is_some_and(|id| id.0 == message.target) {
+        format!(
+            "[from {}] {}",
+            display_name(&players, message.sender),
+            message.text
+        )
+    } else {
+        format!(
+            "[to {}] {}",
+            display_name(&players, message.target),
+            message.text
+        )
+    };
+    chat.push(line);
+}
+
+fn on_whisper_failed(message: On<WhisperFailed>, mut chat: ResMut<ChatLog>) {
+    chat.push(format!("No player named {}", message.target_name));
+}
+
+fn chat_window(
+    mut contexts: EguiContexts,
+    mut chat: ResMut<ChatLog>,
+    mut commands: Commands,
+) -> Result {
+    egui::Window::new("Chat").show(contexts.ctx_mut()?, |ui| {
+        egui::ScrollArea::vertical()
+            .max_height(200.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for line in &chat.lines {
+                    ui.label(line);
+                }
+            });
+
+        let response = ui.text_edit_singleline(&mut chat.draft);
+        if response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter)) {
+            let text = std::mem::take(&mut chat.draft);
+            if let Some(room) = parse_room(text.trim()) {
+                match room {
+                    Ok(room_id) => {
+                        chat.push(format!("Joining room {room_id}"));
+                        commands.client_trigger(JoinRoom { room_id });
+                    }
+                    Err(usage) => chat.push(usage.to_string()),
+                }
+                response.request_focus();
+                return;
+            }
+            match parse_whisper(text.trim()) {
+                Some(Ok((target_name, text))) => commands.client_trigger(Whisper {
+                    target_name: target_name.to_string(),
+                    text: text.to_string(),
+                }),
+                Some(Err(usage)) => chat.push(usage.to_string()),
+                None if !text.trim().is_empty() => commands.client_trigger(ChatMessage { text }),
+                None => {}
+            }
+            response.request_focus();
+        }
+    });
+
+    Ok(())
+}
+
+fn lobby_window(
+    mut contexts: EguiContexts,
+    players: Query<(&Player, Option<&PlayerName>, &PlayerReady, Has<LocalPlayer>)>,
+    args: Res<Args>,
+    mut commands: Commands,
+) -> Result {
+    egui::Window::new("Lobby").show(contexts.ctx_mut()?, |ui| {
+        ui.label("Waiting for every player to be ready…");
+        ui.separator();
+
+        let mut local_ready = false;
+        for (player, name, ready, is_local) in &players {
+            let name = name
+                .map(|name| name.0.clone())
+                .unwrap_or_else(|| format!("Player {}", player.network_id));
+            let status = if ready.0 { "ready" } else { "not ready" };
+            ui.label(format!("{name}: {status}"));
+            if is_local {
+                local_ready = ready.0;
+            }
+        }
+
+        ui.separator();
+        if args.spectator {
+            ui.label("Spectating");
+            return;
+        }
+        let label = if local_ready { "Unready" } else { "Ready" };
+        if ui.button(label).clicked() {
+            commands.client_trigger(ToggleReady);
+        }
+    });
+
+    Ok(())
+}
+
+fn scoreboard_window(
+    mut contexts: EguiContexts,
+    roster: Res<Roster>,
+    client_id: Option<Res<MyClientId>>,
+) -> Result {
+    egui::Window::new(format!("Players ({})", roster.0.len()))
+        .id(egui::Id::new("scoreboard"))
+        .show(contexts.ctx_mut()?, |ui| {
+            for (network_id, name) in &roster.0 {
+                if client_id.as_ref().is_some_and(|id| id.0 == *network_id) {
+                    ui.strong(format!("{name} (you)"));
+                } else {
+                    ui.label(name);
+                }
+            }
+        });
+
+    Ok(())
+}
+
+fn on_toggle_minimap(_toggle: On<Start<ToggleMinimap>>, mut minimap: ResMut<MinimapVisible>) {
+    minimap.0 = !minimap.0;
+}
+
+/// Scaled down view of the world bounds and every player in their color, the local one outlined
+#[allow(clippy::type_complexity)]
+fn minimap_window(
+    mut contexts: EguiContexts,
+    minimap: Res<MinimapVisible>,
+    config: Option<Res<GameConfig>>,
+    players: Query<
+        (
+            &Transform,
+            Option<&PlayerColor>,
+            Option<&PlayerShape>,
+            Has<LocalPlayer>,
+        ),
+        With<Player>,
+    >,
+) -> Result {
+    if !minimap.0 {
+        return Ok(());
+    }
+
+    // Until the bounds are known, fit the players with a margin so a lone one still has room.
+    let (min, max) = match config {
+        Some(config) => (config.bounds.min, config.bounds.max),
+        None => {
+            let (min, max) =
+                players
+                    .iter()
+                    .fold((Vec2::MAX, Vec2::MIN), |(min, max), (transform, ..)| {
+                        let position = transform.translation.xy();
+                        (min.min(position), max.max(position))
+                    });
+            (min - PLAYER_SIZE * 2.0, max + PLAYER_SIZE * 2.0)
+        }
+    };
+    let extent = max - min;
+    if extent.x <= 0.0 || extent.y <= 0.0 {
+        return Ok(());
+    }
+    let scale = MINIMAP_SIZE / extent.max_element();
+
+    egui::Window::new("Minimap")
+        .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-8.0, -8.0))
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut()?, |ui| {
+            let size = extent * scale;
+            let (response, painter) =
+                ui.allocate_painter(egui::vec2(size.x, size.y), egui::Sense::hover());
+            let rect = response.rect;
+            painter.rect_filled(rect, 2.0, egui::Color32::from_black_alpha(128));
+
+            // World y points up, screen y down.
+            let to_map = |position: Vec2| {
+                let offset = (position - min) * scale;
+                egui::pos2(rect.left() + offset.x, rect.bottom() - offset.y)
+            };
+            for (transform, color, shape, is_local) in &players {
+                let radius = shape.copied().unwrap_or_default().collision_radius();
+                let radius = (radius * scale).max(2.0);
+                let center = to_map(transform.translation.xy());
+                let [r, g, b, _] = color
+                    .map_or(Color::WHITE, |color| color.0)
+                    .to_srgba()
+                    .to_u8_array();
+                let color = egui::Color32::from_rgb(r, g, b);
+                if is_local {
+                    let outline = egui::Stroke::new(2.0, egui::Color32::WHITE);
+                    painter.circle(center, radius + 1.0, color, outline);
+                } else {
+                    painter.circle_filled(center, radius, color);
+                }
+            }
+        });
+
+    Ok(())
+}
+
+fn certificate_warning_window(
+    mut contexts: EguiContexts,
+    mut warnings: MessageReader<CertificateWarning>,
+    mut current: Local<Option<CertificateWarning>>,
+) -> Result {
+    if let Some(warning) = warnings.read().last() {
+        *current = Some(warning.clone());
+    }
+    let Some(warning) = current.as_ref() else {
+        return Ok(());
+    };
+
+    let mut dismissed = false;
+    egui::Window::new("Certificate warning").show(contexts.ctx_mut()?, |ui| {
+        ui.label(format!(
+            "The certificate presented by {} does not match the one trusted before.",
+            warning.server_name
+        ));
+        ui.label(format!("Received: {}", warning.fingerprint));
+        if let Some(known) = &warning.known_fingerprint {
+            ui.label(format!("Expected: {known}"));
+        }
+        ui.label(format!(
+            "If the change is expected, remove the entry from {}.",
+            known_hosts_path().display()
+        ));
+        dismissed = ui.button("Dismiss").clicked();
+    });
+
+    if dismissed {
+        *current = None;
+    }
+
+    Ok(())
+}
+
+fn paused_overlay(mut contexts: EguiContexts) -> Result {
+    egui::Area::new(egui::Id::new("paused"))
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 48.0))
+        .show(contexts.ctx_mut()?, |ui| {
+            ui.heading("Paused");
+        });
+    Ok(())
+}
+
+/// Shows how long until the local player may dash again
+fn dash_cooldown_overlay(
+    mut contexts: EguiContexts,
+    players: Query<Option<&DashCooldown>, With<LocalPlayer>>,
+) -> Result {
+    let Ok(cooldown) = players.single() else {
+        return Ok(());
+    };
+
+    egui::Area::new(egui::Id::new("dash_cooldown"))
+        .anchor(egui::Align2::CENTER_BOTTOM, egui::vec2(0.0, -16.0))
+        .show(contexts.ctx_mut()?, |ui| match cooldown {
+            Some(cooldown) if !cooldown.0.is_finished() => {
+                ui.add(
+                    egui::ProgressBar::new(cooldown.0.fraction())
+                        .desired_width(120.0)
+                        .text(format!("Dash {:.1}s", cooldown.0.remaining_secs())),
+                );
+            }
+            _ => {
+                ui.label("Dash ready");
+            }
+        });
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn connection_stats_overlay(
+    mut contexts: EguiContexts,
+    state: Res<State<NetState>>,
+    stats: Option<Res<ConnectionStats>>,
+    quality: Res<ConnectionQuality>,
+    ping: Res<PingStats>,
+    reconnect: Res<ReconnectState>,
+    policy: Res<ReconnectPolicy>,
+    network_error: Option<Res<NetworkError>>,
+) -> Result {
+    egui::Area::new(egui::Id::new("connection_stats"))
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-8.0, 8.0))
+        .show(contexts.ctx_mut()?, |ui| {
+            match state.get() {
+                NetState::Connected => {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Loading world…");
+                    });
+                }
+                NetState::InGame => match stats {
+                    Some(stats) => {
+                        ui.label(format!(
+                            "RTT: {:.0} ms | Loss: {:.1}%",
+                            stats.rtt_ms, stats.packet_loss
+                        ));
+                        if let Some(average_ms) = ping.average_ms() {
+                            ui.label(format!("Ping: {:.0} ms", average_ms));
+                        }
+                        if quality.poor {
+                            ui.colored_label(egui::Color32::LIGHT_YELLOW, "Poor connection");
+                        }
+                        if quality.extra_delay_ticks > 0 {
+                            ui.label(format!(
+                                "Smoothing over {} more update(s)",
+                                quality.extra_delay_ticks
+                            ));
+                        }
+                    }
+                    None => {
+                        ui.label("Connected");
+                    }
+                },
+                NetState::Connecting => {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Connecting…");
+                    });
+                }
+                NetState::Offline if reconnect.exhausted => {
+                    ui.label("Disconnected");
+                }
+                NetState::Offline => {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(format!(
+                            "Reconnecting… ({}/{})",
+                            reconnect.attempts, policy.max_attempts
+                        ));
+                    });
+                }
+            }
+            if let Some(network_error) = network_error {
+                ui.colored_label(egui::Color32::LIGHT_RED, &network_error.0);
+            }
+        });
+
+    Ok(())
+}
+
+fn connection_log_window(mut contexts: EguiContexts, log: Res<ConnectionLog>) -> Result {
+    egui::Window::new("Connection log")
+        .default_open(false)
+        .show(contexts.ctx_mut()?, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for entry in &log.entries {
+                        let color = match entry.kind {
+                            ConnectionLogKind::Connected => egui::Color32::LIGHT_GREEN,
+                            ConnectionLogKind::Disconnected => egui::Color32::LIGHT_YELLOW,
+                            ConnectionLogKind::Error => egui::Color32::LIGHT_RED,
+                        };
+                        let client = entry
+                            .client_id
+                            .map(|id| format!(" [client {id}]"))
+                            .unwrap_or_default();
+                        ui.colored_label(
+                            color,
+                            format!("{:>8.2}s{client} {}", entry.time, entry.detail),
+                        );
+                    }
+                });
+        });
+
+    Ok(())
+}
+
+fn connect_menu(
+    mut contexts: EguiContexts,
+    servers: Res<ServerList>,
+    left: Option<Res<LeftServer>>,
+    reconnect: Res<ReconnectState>,
+    mut commands: Commands,
+) -> Result {
+    // While reconnecting automatically the overlay already shows progress.
+    if left.is_none() && !reconnect.exhausted {
+        return Ok(());
+    }
+
+    egui::Window::new("Offline")
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut()?, |ui| {
+            ui.label(format!("Server: {}", servers.active()));
+            if ui.button("Connect").clicked() {
+                commands.trigger(JoinServer);
+            }
+            ui.label("Press Esc to leave or join the server.");
+        });
+
+    Ok(())
+}
+
+fn disconnect_notice_window(
+    mut contexts: EguiContexts,
+    notice: Option<Res<DisconnectNotice>>,
+    mut commands: Commands,
+) -> Result {
+    let Some(notice) = notice else {
+        return Ok(());
+    };
+
+    egui::Window::new("Disconnected").show(contexts.ctx_mut()?, |ui| {
+        ui.label(&notice.0);
+        if ui.button("Dismiss").clicked() {
+            commands.remove_resource::<DisconnectNotice>();
+        }
+    });
+
+    Ok(())
+}
+
+#[allow(clippy::type_complexity)]
+fn predict_local_movement(
+    mut query: Query<
+        (
+            &mut Transform,
+            &mut Prediction,
+            &LastProcessedInput,
+            &Health,
+            Option<&Velocity>,
+        ),
+        With<LocalPlayer>,
+    >,
+    config: Res<GameConfig>,
+    smoothing: Res<CorrectionSmoothing>,
+    #[cfg(feature = "dev")] noclip: Query<(), (With<LocalPlayer>, With<shared::Noclip>)>,
+    time: Res<Time>,
+) {
+    // Noclip players move at a multiple of the speed without accelerating or being bounded.
+    #[cfg(feature = "dev")]
+    let noclip_factor = (!noclip.is_empty()).then_some(shared::NOCLIP_SPEED_FACTOR);
+    #[cfg(not(feature = "dev"))]
+    let noclip_factor: Option<f32> = None;
+
+    for (mut transform, mut prediction, last_processed, health, velocity) in query.iter_mut() {
+        // Nothing else writes the local transform, so a change here is an authoritative update.
+        // Snap to it and replay the moves the server hasn't seen yet.
+        if transform.is_changed() {
+            let displayed = prediction.position + prediction.smoothing;
+            while prediction
+                .pending
+                .front()
+                .is_some_and(|predicted| predicted.seq <= last_processed.0)
+            {
+                prediction.pending.pop_front();
+            }
+            let replayed: Vec2 = prediction
+                .pending
+                .iter()
+                .map(|predicted| predicted.delta)
+                .sum();
+            prediction.position = transform.translation.xy() + replayed;
+            prediction.knockback = velocity.map_or(Vec2::ZERO, |velocity| velocity.0);
+
+            // Keep drawing the player where it was, unless that would hide a large correction.
+            let offset = displayed - prediction.position;
+            prediction.smoothing = if offset.length() <= MAX_SMOOTHED_CORRECTION {
+                offset
+            } else {
+                Vec2::ZERO
+            };
+        }
+
+        // Step by the server's fixed timestep, so replayed moves add up to what the server did.
+        let tick = 1.0 / config.tick_rate;
+        prediction.unsimulated += time.delta_secs_f64();
+        while prediction.unsimulated >= tick {
+            prediction.unsimulated -= tick;
+
+            // The server doesn't move dead players, so neither does the prediction, and they
+            // respawn at rest.
+            prediction.velocity = match (health.is_dead(), noclip_factor) {
+                (true, _) => Vec2::ZERO,
+                (false, Some(factor)) => prediction.input * config.movement.speed * factor,
+                (false, None) => accelerate(
+                    prediction.velocity,
+                    prediction.input,
+                    &config.movement,
+                    &config.accel,
+                    tick as f32,
+                ),
+            };
+            let step = prediction.velocity * tick as f32;
+            // Record the clamped move, so pushing into a wall never replays as progress past it.
+            let delta = match noclip_factor {
+                Some(_) => step,
+                None => config.bounds.clamp(prediction.position + step) - prediction.position,
+            };
+            if delta != Vec2::ZERO && !health.is_dead() {
+                let seq = prediction.seq;
+                prediction.pending.push_back(PredictedMove { seq, delta });
+                if prediction.pending.len() > INPUT_HISTORY_LEN {
+                    prediction.pending.pop_front();
+                }
+                prediction.position += delta;
+            }
+
+            // Knockback isn't input, so it's never replayed and the next update corrects it.
+            if health.is_dead() {
+                continue;
+            }
+            let (displacement, damped) = knockback_step(prediction.knockback, tick as f32);
+            prediction.knockback = damped;
+            prediction.position = config.bounds.clamp(prediction.position + displacement);
+        }
+
+        if smoothing.time.is_zero() {
+            prediction.smoothing = Vec2::ZERO;
+        } else {
+            // Decays to 5% over the smoothing time.
+            let decay_rate = 3.0 / smoothing.time.as_secs_f32();
+            prediction
+                .smoothing
+                .smooth_nudge(&Vec2::ZERO, decay_rate, time.delta_secs());
+        }
+        let drawn = prediction.position + prediction.smoothing;
+        transform.translation = drawn.extend(transform.translation.z);
+    }
+}
+
+/// Turns player bodies to their facing, the local one straight to where it's predicted to walk
+/// and remote ones eased towards their last replicated facing
+fn turn_player_bodies(
+    players: Query<(&Facing, Option<&Prediction>, &Children)>,
+    mut bodies: Query<&mut Transform, With<PlayerBody>>,
+    time: Res<Time>,
+) {
+    for (facing, prediction, children) in &players {
+        let Some(mut body) = children
+            .iter()
+            .find(|child| bodies.contains(*child))
+            .and_then(|child| bodies.get_mut(child).ok())
+        else {
+            continue;
+        };
+        let current = body.rotation.to_euler(EulerRot::ZYX).0;
+        let angle = match prediction {
+            Some(prediction) if prediction.velocity != Vec2::ZERO => prediction.velocity.to_angle(),
+            Some(_) => current,
+            None => {
+                // The short way around, so crossing from -π to π doesn't spin the body.
+                let gap = (facing.0 - current + PI).rem_euclid(TAU) - PI;
+                let eased = gap * (1.0 - (-FACING_SMOOTHING * time.delta_secs()).exp());
+                current + eased
+            }
+        };
+        if angle != current {
+            body.rotation = Quat::from_rotation_z(angle);
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn fade_in_players(
+    mut bodies: Query<(
+        Entity,
+        &mut SpawnAnim,
+        &mut Transform,
+        Option<&mut Sprite>,
+        Option<&MeshMaterial2d<ColorMaterial>>,
+    )>,
+    mut materials: Option<ResMut<Assets<ColorMaterial>>>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    for (entity, mut anim, mut transform, sprite, material) in &mut bodies {
+        anim.0.tick(time.delta());
+        let shown = anim.0.fraction();
+        show_body(shown, &mut transform, sprite, material, &mut materials);
+        if anim.0.is_finished() {
+            commands.entity(entity).remove::<SpawnAnim>();
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn fade_out_players(
+    mut stand_ins: Query<(
+        Entity,
+        &mut DespawnAnim,
+        &mut Transform,
+        Option<&mut Sprite>,
+        Option<&MeshMaterial2d<ColorMaterial>>,
+    )>,
+    mut materials: Option<ResMut<Assets<ColorMaterial>>>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    for (entity, mut anim, mut transform, sprite, material) in &mut stand_ins {
+        anim.0.tick(time.delta());
+        if anim.0.is_finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let shown = 1.0 - anim.0.fraction();
+        show_body(shown, &mut transform, sprite, material, &mut materials);
+    }
+}
+
+/// Draws a player's body `shown` of the way from gone to fully there, growing from
+/// [`SPAWN_FADE_SCALE`] as it turns opaque
+fn show_body(
+    shown: f32,
+    transform: &mut Transform,
+    sprite: Option<Mut<Sprite>>,
+    material: Option<&MeshMaterial2d<ColorMaterial>>,
+    materials: &mut Option<ResMut<Assets<ColorMaterial>>>,
+) {
+    // Eases out, so it settles gently into its full size.
+    let eased = 1.0 - (1.0 - shown).powi(2);
+    transform.scale = Vec3::splat(SPAWN_FADE_SCALE + (1.0 - SPAWN_FADE_SCALE) * eased);
+    if let Some(mut sprite) = sprite {
+        sprite.color.set_alpha(shown);
+    }
+    if let (Some(material), Some(materials)) = (material, materials.as_mut())
+        && let Some(material) = materials.get_mut(&material.0)
+    {
+        material.color.set_alpha(shown);
+    }
+}
+
+fn camera_follow(
+    player: Query<&Transform, (With<LocalPlayer>, Without<Camera2d>)>,
+    mut camera: Query<&mut Transform, With<Camera2d>>,
+    smoothing: Res<CameraSmoothing>,
+    time: Res<Time>,
+) {
+    // Interpolation eases transforms before `Update`, so this already follows the smoothed one.
+    let Ok(player) = player.single() else {
+        return;
+    };
+
+    for mut transform in &mut camera {
+        let target = player.translation.xy().extend(transform.translation.z);
+        transform
+            .translation
+            .smooth_nudge(&target, smoothing.0, time.delta_secs());
+    }
+}
+
+fn toggle_free_camera(
+    local_players: Query<(), With<LocalPlayer>>,
+    camera: Query<(Entity, &ContextActivity<FreeCamera>)>,
+    mut commands: Commands,
+) {
+    // Stays off while `camera_follow` has a player to follow, so the two never fight.
+    let active = local_players.is_empty();
+    for (entity, activity) in &camera {
+        if **activity != active {
+            commands
+                .entity(entity)
+                .insert(ContextActivity::<FreeCamera>::new(active));
+        }
+    }
+}
+
+fn on_pan_camera(
+    pan: On<Fire<PanCamera>>,
+    mut camera: Query<(&mut Transform, &Projection)>,
+    time: Res<Time>,
+) {
+    if let Ok((mut transform, projection)) = camera.get_mut(pan.context) {
+        let distance = FREE_CAMERA_SPEED * zoom_scale(projection) * time.delta_secs();
+        transform.translation += (pan.value * distance).extend(0.0);
+    }
+}
+
+fn on_drag_camera(drag: On<Fire<DragCamera>>, mut camera: Query<(&mut Transform, &Projection)>) {
+    if let Ok((mut transform, projection)) = camera.get_mut(drag.context) {
+        // Screen y points down, and the world should stick to the cursor.
+        let delta = Vec2::new(-drag.value.x, drag.value.y) * zoom_scale(projection);
+        transform.translation += delta.extend(0.0);
+    }
+}
+
+fn zoom_scale(projection: &Projection) -> f32 {
+    match projection {
+        Projection::Orthographic(orthographic) => orthographic.scale,
+        _ => 1.0,
+    }
+}
+
+fn zoom_camera(
+    scroll: Option<Res<AccumulatedMouseScroll>>,
+    mut camera: Query<&mut Projection, With<Camera2d>>,
+) {
+    let Some(scroll) = scroll else {
+        return;
+    };
+    if scroll.delta.y == 0.0 {
+        return;
+    }
+    // Pixel-based touchpads report much larger deltas than wheel lines.
+    let lines = match scroll.unit {
+        MouseScrollUnit::Line => scroll.delta.y,
+        MouseScrollUnit::Pixel => scroll.delta.y / 100.0,
+    };
+
+    for mut projection in &mut camera {
+        if let Projection::Orthographic(orthographic) = projection.as_mut() {
+            let scale = orthographic.scale * (1.0 - lines * ZOOM_STEP);
+            orthographic.scale = scale.clamp(*ZOOM_RANGE.start(), *ZOOM_RANGE.end());
+        }
+    }
+}
+
+/// Keeps the view inside the world, centering it on axes where the world is smaller than the view
+fn clamp_camera(
+    mut camera: Query<(&mut Transform, &Projection), With<Camera2d>>,
+    config: Res<GameConfig>,
+) {
+    let bounds = config.bounds;
+
+    for (mut transform, projection) in &mut camera {
+        let Projection::Orthographic(orthographic) = projection else {
+            continue;
+        };
+        // The area already accounts for the viewport size and the zoom level.
+        let half_view = orthographic.area.half_size();
+        let min = bounds.min + half_view;
+        let max = bounds.max - half_view;
+        let center = (bounds.min + bounds.max) / 2.0;
+        let position = transform.translation.xy();
+        let clamped = Vec2::new(
+            if min.x <= max.x {
+                position.x.clamp(min.x, max.x)
+            } else {
+                center.x
+            },
+            if min.y <= max.y {
+                position.y.clamp(min.y, max.y)
+            } else {
+                center.y
+            },
+        );
+        transform.translation = clamped.extend(transform.translation.z);
+    }
+}
+
+/// Tells the server the game is closing while events can still be sent this frame
+fn announce_exit(mut exit_events: MessageReader<AppExit>, mut commands: Commands) {
+    if exit_events.read().next().is_some() {
+        close_connection(&mut commands, DisconnectReason::UserRequested);
+    }
+}
+
+fn close_connections(
+    pending: Option<Res<PendingClose>>,
+    mut exit_events: MessageReader<AppExit>,
+    mut client: ResMut<QuinnetClient>,
+    mut commands: Commands,
+) {
+    // Exits requested after `PostUpdate` couldn't be announced, but still close the connection.
+    let exiting = exit_events.read().next().is_some();
+    let reason = match pending {
+        Some(pending) => pending.0,
+        None if exiting => DisconnectReason::UserRequested,
+        None => return,
+    };
+    commands.remove_resource::<PendingClose>();
+
+    info!("Closing all connections: {:?}", reason);
+    let connection_ids: Vec<u64> = client.connections().map(|(id, _)| *id).collect();
+    for connection_id in connection_ids {
+        if let Err(e) = client.close_connection(connection_id) {
+            warn!("Failed to close connection {}: {:?}", connection_id, e);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{Dead, GamePhase, Paused};
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use shared::{DashCooldown, DashIntent, Player, Velocity};
+use std::time::Duration;
+
+#[derive(Resource)]
+/// How hard and how often players may dash
+pub(crate) struct DashConfig {
+    /// Units per second added to the dashing player's velocity, fading out like knockback
+    pub(crate) speed: f32,
+    pub(crate) cooldown: Duration,
+}
+
+pub(crate) fn configure_dash(app: &mut App, config: DashConfig) {
+    app.insert_resource(config);
+    app.add_observer(on_dash);
+    app.add_observer(crate::record_activity::<DashIntent>);
+    app.add_systems(
+        FixedUpdate,
+        tick_dash_cooldowns
+            .run_if(in_state(GamePhase::Playing).and(not(resource_exists::<Paused>))),
+    );
+}
+
+#[allow(clippy::type_complexity)]
+fn on_dash(
+    dash: On<FromClient<DashIntent>>,
+    mut players: Query<(&mut Velocity, Option<&mut DashCooldown>), (With<Player>, Without<Dead>)>,
+    phase: Res<State<GamePhase>>,
+    paused: Option<Res<Paused>>,
+    config: Res<DashConfig>,
+    mut commands: Commands,
+) {
+    let Some(entity) = dash.client_id.entity() else {
+        return;
+    };
+    if *phase.get() != GamePhase::Playing || paused.is_some() {
+        return;
+    }
+    let Ok((mut velocity, cooldown)) = players.get_mut(entity) else {
+        return;
+    };
+    // Also refuses a zero or non-finite direction.
+    let Some(direction) = dash.direction.try_normalize() else {
+        return;
+    };
+
+    // However many intents arrive, only the first one after the cooldown gets through.
+    match cooldown {
+        Some(cooldown) if !cooldown.0.is_finished() => return,
+        Some(mut cooldown) => cooldown.0.reset(),
+        None => {
+            commands
+                .entity(entity)
+                .insert(DashCooldown(Timer::new(config.cooldown, TimerMode::Once)));
+        }
+    }
+    velocity.0 += direction * config.speed;
+}
+
+/// Counts cooldowns down without marking them changed, clients tick their own copy between dashes
+fn tick_dash_cooldowns(mut cooldowns: Query<&mut DashCooldown>, time: Res<Time>) {
+    for mut cooldown in &mut cooldowns {
+        cooldown.bypass_change_detection().0.tick(time.delta());
+    }
+}
@@ -0,0 +1,2664 @@
+mod dash;
+mod metrics;
+#[cfg(feature = "dev")]
+mod noclip;
+mod projectile;
+mod replay;
+
+pub use metrics::ServerMetrics;
+pub use replay::SimulationTick;
+
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::ecs::schedule::ScheduleLabel;
+use bevy::log::{Level, LogPlugin};
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy::time::common_conditions::on_timer;
+use bevy_quinnet::server::{
+    EndpointAddrConfiguration, QuinnetServer, ServerEndpointConfiguration,
+    ServerEndpointConfigurationDefaultables, certificate::CertificateRetrievalMode,
+    error::EndpointStartError,
+};
+use bevy_quinnet::shared::channels::DEFAULT_MAX_RELIABLE_FRAME_LEN;
+use bevy_replicon::prelude::*;
+use bevy_replicon::shared::backend::connected_client::NetworkId;
+use bevy_replicon_quinnet::RepliconQuinnetPlugins;
+use clap::{Parser, ValueEnum};
+use shared::networking::{ClientDisconnect, DisconnectReason, ServerDisconnect, disconnect_client};
+use shared::{
+    AccelConfig, BALL_RADIUS, Ball, BroadcastChat, ChatMessage, ClientMovementIntent, CollisionHit,
+    ConnectIntent, ConnectionRejected, DEFAULT_TICK_RATE, DashCooldown, Facing, ForcePosition,
+    GameConfig, GamePaused, GameSharedPlugin, GameStart, Health, IdleKick, InitialSnapshot,
+    JoinRoom, Kicked, LastProcessedInput, MovementConfig, NetPosition, NetworkBudget, NetworkError,
+    PLAYER_SIZE, PLAYER_SPEED, Ping, Player, PlayerColor, PlayerDied, PlayerJoined, PlayerLeft,
+    PlayerName, PlayerReady, PlayerRespawned, PlayerShape, Pong, RoomId, RosterUpdate,
+    ServerShutdown, SetPlayerName, ShapeKind, ToggleReady, Velocity, Whisper, WhisperDelivery,
+    WhisperFailed, WorldBounds, accelerate, knockback_step, sanitize_chat_message,
+    sanitize_player_name,
+};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, channel};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Resource, Parser)]
+pub struct Args {
+    /// JSON file with values for any of the options below, the command line overrides it
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Address to listen on. Repeat it on multi-homed hosts to fall back to the next address
+    /// when one can't be bound, the server listens on the first one that binds
+    #[arg(short, long = "ip", default_values_t = [IpAddr::from(Ipv6Addr::LOCALHOST)])]
+    ips: Vec<IpAddr>,
+    /// Listen on every address of one or both IP families instead of `--ip`
+    #[arg(long, value_enum, conflicts_with = "ips")]
+    bind: Option<BindMode>,
+    #[arg(short, long, default_value_t = 5000)]
+    port: u16,
+    /// Ports after `--port` to try in turn when it's already in use, 0 to only try `--port`
+    #[arg(long, default_value_t = 0)]
+    port_range: u16,
+    /// Width of the arena, centered on the origin
+    #[arg(long, default_value_t = 2000.0, value_parser = parse_positive)]
+    world_width: f32,
+    /// Height of the arena, centered on the origin
+    #[arg(long, default_value_t = 2000.0, value_parser = parse_positive)]
+    world_height: f32,
+    /// Player movement speed in units per second
+    #[arg(long, default_value_t = PLAYER_SPEED, value_parser = parse_non_negative)]
+    speed: f32,
+    /// Width and height of players, which collide as the largest circle that fits inside
+    #[arg(long, default_value_t = PLAYER_SIZE, value_parser = parse_positive)]
+    player_size: f32,
+    /// Shape players are drawn as
+    #[arg(long, value_enum, default_value_t = ShapeKind::Square)]
+    player_shape: ShapeKind,
+    /// How fast players reach their speed, in units per second squared
+    #[arg(long, default_value_t = AccelConfig::default().acceleration, value_parser = parse_non_negative)]
+    acceleration: f32,
+    /// How fast players stop after releasing their input, in units per second squared
+    #[arg(long, default_value_t = AccelConfig::default().deceleration, value_parser = parse_non_negative)]
+    deceleration: f32,
+    /// Simulation and replication rate in ticks per second
+    #[arg(long, default_value_t = DEFAULT_TICK_RATE, value_parser = parse_tick_rate)]
+    tick_rate: f64,
+    /// Replication updates sent per second, every tick if unset. Clients interpolate between
+    /// them, so a lower rate saves bandwidth at the cost of remote players lagging further behind
+    #[arg(long, value_parser = parse_replication_hz)]
+    replication_hz: Option<f64>,
+    /// Seconds a dead player waits before respawning
+    #[arg(long, default_value_t = 3.0, value_parser = parse_non_negative)]
+    respawn_delay: f32,
+    /// Projectiles a player may fire per second, faster shots are ignored
+    #[arg(long, default_value_t = 4.0, value_parser = parse_positive)]
+    fire_rate: f32,
+    /// Health a projectile takes from the player it hits
+    #[arg(long, default_value_t = 20.0)]
+    projectile_damage: f32,
+    /// Spent projectiles kept to be reused for new shots, 0 to always spawn new entities
+    #[arg(long, default_value_t = 256)]
+    projectile_pool: usize,
+    /// Speed in units per second a dash adds to a player, fading out like knockback
+    #[arg(long, default_value_t = 800.0, value_parser = parse_non_negative)]
+    dash_speed: f32,
+    /// Seconds a player waits between dashes, earlier dashes are ignored
+    #[arg(long, default_value_t = 1.5, value_parser = parse_non_negative)]
+    dash_cooldown: f32,
+    /// Only replicate other players within this distance of a client's own player, every player
+    /// in the room if unset
+    #[arg(long, value_parser = parse_positive)]
+    view_radius: Option<f32>,
+    /// Replicate player positions as quantized `NetPosition`s instead of full transforms
+    #[arg(long)]
+    compact_positions: bool,
+    /// Replicate player positions every tick, even for players standing still, instead of only
+    /// when they move. Costs bandwidth, but gives a steady stream of updates when debugging
+    #[arg(long)]
+    replicate_every_tick: bool,
+    /// Kick players after this many movement violations, never kick if unset
+    #[arg(long)]
+    kick_after_violations: Option<u32>,
+    /// Ticks a player's last movement input keeps applying without a new intent before it decays,
+    /// covering intents lost on the unreliable channel
+    #[arg(long, default_value_t = 16)]
+    input_hold_ticks: u32,
+    /// Seconds a player may go without sending input while playing before being kicked, 0 to never
+    #[arg(long, default_value_t = 300.0, value_parser = parse_non_negative)]
+    idle_timeout: f32,
+    /// Seconds to keep running after the last client leaves before shutting down, never if unset
+    #[arg(long, value_parser = parse_non_negative)]
+    empty_timeout: Option<f32>,
+    /// Maximum number of players allowed at the same time
+    #[arg(long, default_value_t = 16)]
+    max_players: usize,
+    /// Name the generated self-signed certificate is issued for, the bind address by default.
+    ///
+    /// Clients verify the certificate against the address they connect to, so this has to match
+    /// it. Ignored when loading a certificate with `--cert`.
+    #[arg(long)]
+    hostname: Option<String>,
+    /// PEM certificate file, used instead of a generated self-signed certificate
+    #[arg(long, requires = "key")]
+    cert: Option<String>,
+    /// PEM private key file matching `--cert`
+    #[arg(long, requires = "cert")]
+    key: Option<String>,
+    /// Only let in clients joining with this token
+    #[arg(long, conflicts_with = "token_file")]
+    join_secret: Option<String>,
+    /// Only let in clients joining with one of the tokens in this file, one per line
+    #[arg(long)]
+    token_file: Option<PathBuf>,
+    /// Clients joining with this token are admins, who may use noclip
+    #[cfg(feature = "dev")]
+    #[arg(long)]
+    admin_token: Option<String>,
+    /// Seconds between metrics summaries in the log
+    #[arg(long, default_value_t = 30.0, value_parser = parse_positive)]
+    metrics_interval: f32,
+    /// Port to serve metrics on over HTTP for scraping, disabled if unset
+    #[cfg(feature = "metrics-http")]
+    #[arg(long)]
+    metrics_port: Option<u16>,
+    /// Most verbose level logged, one of error, warn, info, debug or trace
+    #[arg(long, default_value_t = Level::INFO)]
+    log_level: Level,
+    /// Largest message in bytes the server sends on reliable channels
+    #[arg(long, default_value_t = DEFAULT_MAX_RELIABLE_FRAME_LEN)]
+    max_reliable_frame_len: usize,
+    /// Record every join, leave and input of the session to this file for `--replay`
+    #[arg(long)]
+    record: Option<PathBuf>,
+    /// Play a session recorded with `--record` back without opening the network, then exit
+    #[arg(long, conflicts_with = "record")]
+    replay: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+/// IP families the server accepts clients over.
+///
+/// Quinnet binds a plain std socket and can't set `IPV6_V6ONLY`, so the IPv6 modes depend on the
+/// platform default:
+/// - Linux: `[::]` also accepts IPv4 unless `net.ipv6.bindv6only` is set, so `v6` is dual-stack
+///   in practice and `dual` fails when the sysctl is set.
+/// - macOS: like Linux with the default settings.
+/// - Windows: `[::]` is IPv6 only, so `dual` is refused. Run a second server with `v4` instead.
+enum BindMode {
+    /// `0.0.0.0`, IPv4 clients only
+    V4,
+    /// `[::]`, IPv6 clients, and IPv4 ones too where the platform makes the socket dual-stack
+    V6,
+    /// `[::]` accepting both IPv4 and IPv6 clients
+    Dual,
+}
+
+impl BindMode {
+    fn address(self) -> Result<IpAddr, String> {
+        match self {
+            Self::V4 => Ok(Ipv4Addr::UNSPECIFIED.into()),
+            Self::V6 => {
+                if ipv6_socket_is_dual_stack() {
+                    warn!("IPv6 sockets are dual-stack on this platform, IPv4 clients can connect too");
+                }
+                Ok(Ipv6Addr::UNSPECIFIED.into())
+            }
+            Self::Dual if ipv6_socket_is_dual_stack() => Ok(Ipv6Addr::UNSPECIFIED.into()),
+            Self::Dual => Err(
+                "Dual-stack binding isn't available, IPv6 sockets only accept IPv6 here. Use --bind v4 or --bind v6"
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+/// Whether a socket bound to `[::]` with the platform defaults also accepts IPv4 clients
+fn ipv6_socket_is_dual_stack() -> bool {
+    if cfg!(windows) {
+        return false;
+    }
+    // Only Linux makes the default configurable, everything else we run on defaults to dual-stack.
+    fs::read_to_string("/proc/sys/net/ipv6/bindv6only").map_or(true, |value| value.trim() == "0")
+}
+
+#[derive(Component)]
+/// How a client asked to join, kept until it is authorized and joined
+struct JoinRequest {
+    spectator: bool,
+}
+
+#[derive(Component)]
+/// Authorized client watching the game without a player
+struct Spectator;
+
+#[derive(Component, Default)]
+struct MovementInput(Vec2);
+
+#[derive(Component, Default)]
+/// Last movement input a player sent and how many ticks ago, to ride out lost intents
+struct InputHistory {
+    last: Vec2,
+    age: u32,
+}
+
+#[derive(Resource)]
+/// Ticks the last movement input is held for without a new intent, see [`InputHistory`]
+struct InputHold(u32);
+
+/// Ticks a stale input takes to decay from its last value to zero once the hold ran out
+const INPUT_DECAY_TICKS: u32 = 8;
+
+#[derive(Component, Default)]
+/// Velocity of a player's own movement, accelerating towards what its [`MovementInput`] asks for
+struct MovementVelocity(Vec2);
+
+/// Movement intents accepted from a single client per tick, extra ones are dropped
+const MAX_INTENTS_PER_TICK: u32 = 8;
+
+#[derive(Component, Default)]
+/// Per-client bookkeeping for rate limiting and debugging incoming inputs
+struct InputStats {
+    received_this_tick: u32,
+    rejected: u32,
+}
+
+/// How long a client has to send its name before it gets a generated one
+const NAME_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+#[derive(Component)]
+/// Grace period for a client to send [`SetPlayerName`] after connecting
+struct PendingName(Timer);
+
+/// Distance between neighbouring spawn points
+const SPAWN_SPACING: f32 = 150.0;
+
+/// Number of overflow positions on each ring once every spawn point is taken
+const SPAWN_RING_SLOTS: usize = 12;
+
+#[derive(Resource, Debug)]
+/// Positions new players are spawned at, each held by at most one player
+struct SpawnPoints {
+    points: Vec<Vec2>,
+    taken: Vec<bool>,
+    /// Players spawned since every point was taken, used to spread them on rings
+    overflow: usize,
+}
+
+impl SpawnPoints {
+    /// Lays out `count` points on a grid centered on the origin and kept inside `bounds`
+    fn grid(count: usize, bounds: &WorldBounds) -> Self {
+        let columns = (count as f32).sqrt().ceil().max(1.0) as usize;
+        let rows = count.div_ceil(columns);
+        let offset = Vec2::new(columns as f32 - 1.0, rows as f32 - 1.0) * SPAWN_SPACING / 2.0;
+
+        let points = (0..count)
+            .map(|i| {
+                let cell = Vec2::new((i % columns) as f32, (i / columns) as f32);
+                bounds.clamp(cell * SPAWN_SPACING - offset)
+            })
+            .collect();
+
+        Self {
+            points,
+            taken: vec![false; count],
+            overflow: 0,
+        }
+    }
+
+    /// Claims a free spawn point, preferring one no player in `occupied` is within `clearance`
+    /// of, and returns its slot and position
+    fn claim(&mut self, occupied: &[Vec2], clearance: f32) -> Option<(usize, Vec2)> {
+        let is_clear = |point: Vec2| {
+            occupied
+                .iter()
+                .all(|other| other.distance(point) >= clearance)
+        };
+        let mut free = (0..self.points.len()).filter(|&slot| !self.taken[slot]);
+
+        let slot = free
+            .clone()
+            .find(|&slot| is_clear(self.points[slot]))
+            .or_else(|| free.next())?;
+        self.taken[slot] = true;
+        Some((slot, self.points[slot]))
+    }
+
+    fn release(&mut self, slot: usize) {
+        if let Some(taken) = self.taken.get_mut(slot) {
+            *taken = false;
+        }
+        if self.taken.iter().any(|taken| !taken) {
+            self.overflow = 0;
+        }
+    }
+
+    /// Position on a ring around the grid, for when every spawn point is taken
+    fn next_overflow(&mut self, bounds: &WorldBounds) -> Vec2 {
+        let ring = self.overflow / SPAWN_RING_SLOTS;
+        let angle = (self.overflow % SPAWN_RING_SLOTS) as f32 * std::f32::consts::TAU
+            / SPAWN_RING_SLOTS as f32;
+        self.overflow += 1;
+
+        let grid_radius = self
+            .points
+            .iter()
+            .map(|point| point.length())
+            .fold(0.0, f32::max);
+        let radius = grid_radius + SPAWN_SPACING * (ring + 1) as f32;
+        bounds.clamp(Vec2::from_angle(angle) * radius)
+    }
+}
+
+#[derive(Component)]
+/// Spawn point held by a player, released when the component is removed
+struct SpawnSlot(usize);
+
+/// Colors handed out to players in join order
+const PLAYER_COLORS: [Color; 8] = [
+    Color::srgb(0.90, 0.30, 0.30),
+    Color::srgb(0.30, 0.55, 0.95),
+    Color::srgb(0.35, 0.80, 0.35),
+    Color::srgb(0.95, 0.80, 0.25),
+    Color::srgb(0.70, 0.40, 0.90),
+    Color::srgb(0.25, 0.85, 0.85),
+    Color::srgb(0.95, 0.55, 0.20),
+    Color::srgb(0.95, 0.45, 0.75),
+];
+
+#[derive(Resource, Debug, Default)]
+/// Which entries of [`PLAYER_COLORS`] are in use
+struct ColorPalette {
+    taken: [bool; PLAYER_COLORS.len()],
+    /// Players that joined while every color was taken, used to spread them over the palette
+    overflow: usize,
+}
+
+impl ColorPalette {
+    /// Claims the first free color, or shares one once all are taken
+    fn claim(&mut self) -> (Option<usize>, Color) {
+        match self.taken.iter().position(|taken| !taken) {
+            Some(slot) => {
+                self.taken[slot] = true;
+                (Some(slot), PLAYER_COLORS[slot])
+            }
+            None => {
+                self.overflow += 1;
+                (None, PLAYER_COLORS[self.overflow % PLAYER_COLORS.len()])
+            }
+        }
+    }
+
+    fn release(&mut self, slot: usize) {
+        if let Some(taken) = self.taken.get_mut(slot) {
+            *taken = false;
+        }
+    }
+}
+
+#[derive(Component)]
+/// Palette entry held by a player, released when the component is removed
+struct ColorSlot(usize);
+
+/// Extra distance allowed per tick on top of `speed * delta` before movement counts as a violation
+const MOVEMENT_TOLERANCE: f32 = 1.0;
+
+#[derive(Component, Default)]
+/// Position a player ended the previous tick at, and how often it moved further than allowed
+struct MovementCheck {
+    last_position: Vec2,
+    violations: u32,
+    /// When the last [`ForcePosition`] was sent, see [`FORCE_POSITION_INTERVAL`]
+    last_forced: Option<Duration>,
+}
+
+/// Minimum time between two [`ForcePosition`] corrections to the same client, so a flapping
+/// connection isn't flooded with them. Skipped ones are still corrected by replication.
+const FORCE_POSITION_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Tells a client the server moved its player to `position`, unless it was told too recently
+fn force_position(
+    commands: &mut Commands,
+    client: Entity,
+    check: &mut MovementCheck,
+    last_processed: &LastProcessedInput,
+    position: Vec2,
+    now: Duration,
+) {
+    if check
+        .last_forced
+        .is_some_and(|last| now.saturating_sub(last) < FORCE_POSITION_INTERVAL)
+    {
+        return;
+    }
+    check.last_forced = Some(now);
+    commands.server_trigger(ToClients {
+        mode: SendMode::Direct(ClientId::Client(client)),
+        message: ForcePosition {
+            seq: last_processed.0,
+            pos: position,
+        },
+    });
+}
+
+#[derive(Resource)]
+/// How long a player may stay idle during a game before being disconnected
+struct IdleTimeout(Duration);
+
+#[derive(Resource)]
+/// How long the server keeps running without clients before it exits
+struct EmptyTimeout(Duration);
+
+#[derive(Resource)]
+/// Counts down to shutting down, present while no client is connected
+struct EmptyTimer(Timer);
+
+/// Fraction of the [`ViewRadius`] a visible player may move past it before it's hidden, so
+/// players right at the edge don't flicker in and out
+const VIEW_RADIUS_MARGIN: f32 = 0.1;
+
+#[derive(Resource)]
+/// Distance from a client's own player beyond which other players aren't replicated to it
+struct ViewRadius(f32);
+
+#[derive(Component, Default)]
+/// Time a player spent playing without sending any client event
+struct IdleTime(Duration);
+
+#[derive(Resource)]
+/// Present when player positions are replicated every tick, not only when they change
+struct ReplicateEveryTick;
+
+#[derive(Resource)]
+/// Present when players replicate a `NetPosition` instead of their `Transform`
+struct CompactPositions;
+
+#[derive(Resource)]
+/// Movement violations after which a player is kicked, if any
+struct MovementViolationLimit(Option<u32>);
+
+/// Health players spawn and respawn with
+const PLAYER_MAX_HEALTH: f32 = 100.0;
+
+#[derive(Event, Debug, Clone, Copy)]
+/// Deals damage to a player, killing it once its health reaches zero
+pub struct ApplyDamage {
+    pub target: Entity,
+    pub amount: f32,
+}
+
+#[derive(Component)]
+/// Marks a dead player, which can't move or collide until it respawns
+struct Dead;
+
+#[derive(Component)]
+/// Counts down until a dead player respawns
+struct RespawnTimer(Timer);
+
+#[derive(Resource)]
+/// Time a dead player waits before respawning
+struct RespawnDelay(Duration);
+
+/// Players the regular movement, validation and collisions apply to, noclip ones move on their own
+#[cfg(feature = "dev")]
+type Clipping = Without<shared::Noclip>;
+#[cfg(not(feature = "dev"))]
+type Clipping = ();
+
+/// Server-side components of a player, stripped again when its client goes away
+type PlayerState = (
+    Player,
+    PlayerName,
+    PendingName,
+    SpawnSlot,
+    PlayerColor,
+    ColorSlot,
+    MovementInput,
+    MovementCheck,
+    LastProcessedInput,
+    PlayerReady,
+    Health,
+    Dead,
+    RespawnTimer,
+    (
+        InputStats,
+        IdleTime,
+        Velocity,
+        MovementVelocity,
+        InputHistory,
+        PlayerShape,
+        Facing,
+        DashCooldown,
+    ),
+    Replicated,
+);
+
+/// Range of tick rates accepted by `--tick-rate`
+const TICK_RATE_RANGE: std::ops::RangeInclusive<f64> = 10.0..=240.0;
+
+#[derive(Resource, Debug, Clone, Copy)]
+/// Ticks per second the server runs at
+struct TickRate(f64);
+
+#[derive(Resource, Debug, Clone, Copy, Default)]
+/// Shape new players are given
+struct ShapeConfig(PlayerShape);
+
+/// Relaxation passes per tick when pushing overlapping players apart
+const COLLISION_ITERATIONS: usize = 4;
+
+/// How bouncy collisions between players are, from 0 (no knockback) to 1 (elastic)
+const KNOCKBACK_RESTITUTION: f32 = 0.8;
+
+/// Fraction of its speed the ball keeps per second, from rolling friction
+const BALL_FRICTION: f32 = 0.4;
+
+/// Fraction of its speed the ball keeps when bouncing off the world edge or a player
+const BALL_RESTITUTION: f32 = 0.8;
+
+/// Speed a player walking into the ball kicks it to, as a multiple of the player's speed
+const BALL_KICK: f32 = 1.5;
+
+/// Speed below which the ball stops, so it settles instead of creeping and replicating forever
+const BALL_REST_SPEED: f32 = 1.0;
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+/// Port the server is actually listening on, which may differ from `--port` with `--port-range`
+pub struct BoundPort(pub u16);
+
+#[derive(Resource)]
+/// Maximum number of players allowed at the same time
+struct MaxPlayers(usize);
+
+/// Delay that batches roster changes into a single update
+const ROSTER_DEBOUNCE: Duration = Duration::from_millis(250);
+
+#[derive(Resource, Default)]
+/// Pending roster broadcast, started by the first join, leave or rename since the last one
+struct RosterDebounce(Option<Timer>);
+
+#[derive(States, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+/// Whether players are still readying up or the game is running
+pub enum GamePhase {
+    #[default]
+    Lobby,
+    Playing,
+}
+
+#[derive(Resource)]
+/// Present while the operator has the simulation paused. Intents are still received, so players
+/// resume with their latest input.
+struct Paused;
+
+/// Players needed in the lobby before the game can start
+const MIN_PLAYERS_TO_START: usize = 2;
+
+#[derive(Resource)]
+struct ShutdownReceiver(Arc<Mutex<Receiver<()>>>);
+
+/// Time given to the shutdown broadcast to reach clients before the endpoint is stopped
+const SHUTDOWN_FLUSH_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Resource)]
+/// Counts down from the shutdown broadcast to the actual exit
+struct ShutdownTimer(Timer);
+
+/// Time given to the old endpoint's socket to close before binding a new one on restart
+const RESTART_REBIND_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Resource)]
+/// Progress of a `restart` admin command, removed once the new endpoint is up or failed to bind
+enum Restart {
+    /// Clients were told and the old endpoint stops when this finishes
+    Notifying(Timer),
+    /// The old endpoint is stopped and a new one is bound when this finishes
+    Rebinding(Timer),
+}
+
+#[derive(Resource, Clone)]
+/// Addresses the endpoint may listen on, in order of preference, reused to restart it
+struct EndpointSettings {
+    ips: Vec<IpAddr>,
+}
+
+/// Builds the server app, ready to be run or stepped manually with `App::update`
+pub fn build_server_app(args: Args) -> App {
+    let bounds = WorldBounds::from_size(Vec2::new(args.world_width, args.world_height));
+    let movement = MovementConfig { speed: args.speed };
+    let accel = AccelConfig {
+        acceleration: args.acceleration,
+        deceleration: args.deceleration,
+    };
+    let respawn_delay = Duration::from_secs_f32(args.respawn_delay);
+    let metrics_interval = Duration::from_secs_f32(args.metrics_interval);
+    let projectiles = projectile::ProjectileConfig {
+        cooldown: Duration::from_secs_f32(1.0 / args.fire_rate),
+        damage: args.projectile_damage,
+        pool_size: args.projectile_pool,
+    };
+    let dash = dash::DashConfig {
+        speed: args.dash_speed,
+        cooldown: Duration::from_secs_f32(args.dash_cooldown),
+    };
+    #[cfg(feature = "metrics-http")]
+    let metrics_port = args.metrics_port;
+    let tick_rate = args.tick_rate;
+    let replication_rate = args
+        .replication_hz
+        .map_or(tick_rate, |hz| hz.min(tick_rate));
+    let record = args.record.clone();
+    #[cfg(feature = "dev")]
+    let admin_token = args.admin_token.clone();
+    let replay = args.replay.clone();
+
+    let mut app = App::new();
+    app.insert_resource(SpawnPoints::grid(args.max_players, &bounds));
+    app.insert_resource(bounds);
+    app.insert_resource(movement);
+    app.insert_resource(accel);
+    app.insert_resource(GameConfig {
+        movement,
+        accel,
+        bounds,
+        tick_rate: args.tick_rate,
+        replication_rate,
+        respawn_delay,
+    });
+    app.insert_resource(MaxPlayers(args.max_players));
+    app.insert_resource(ShapeConfig(PlayerShape {
+        size: Vec2::splat(args.player_size),
+        kind: args.player_shape,
+    }));
+    app.init_resource::<RosterDebounce>();
+    app.init_resource::<ColorPalette>();
+    app.insert_resource(MovementViolationLimit(args.kick_after_violations));
+    app.insert_resource(InputHold(args.input_hold_ticks));
+    if args.idle_timeout > 0.0 {
+        app.insert_resource(IdleTimeout(Duration::from_secs_f32(args.idle_timeout)));
+    }
+    // Replays have no real clients to wait for and exit once they're done anyway.
+    if let Some(timeout) = args.empty_timeout
+        && args.replay.is_none()
+    {
+        app.insert_resource(EmptyTimeout(Duration::from_secs_f32(timeout)));
+    }
+    if let Some(radius) = args.view_radius {
+        app.insert_resource(ViewRadius(radius));
+    }
+    if args.compact_positions {
+        app.insert_resource(CompactPositions);
+    }
+    if args.replicate_every_tick {
+        app.insert_resource(ReplicateEveryTick);
+    }
+    app.insert_resource(TickRate(args.tick_rate));
+    app.insert_resource(NetworkBudget {
+        max_reliable_frame_len: args.max_reliable_frame_len,
+        ..default()
+    });
+    app.insert_resource(RespawnDelay(respawn_delay));
+    app.insert_resource(args);
+
+    configure_plugins(&mut app);
+    configure_systems(&mut app);
+    metrics::configure_metrics(&mut app, metrics_interval);
+    projectile::configure_projectiles(&mut app, projectiles);
+    dash::configure_dash(&mut app, dash);
+    #[cfg(feature = "dev")]
+    noclip::configure_noclip(&mut app, admin_token);
+    #[cfg(feature = "metrics-http")]
+    if let Some(port) = metrics_port {
+        metrics::serve_metrics(&mut app, port);
+    }
+    replay::configure_simulation_tick(&mut app);
+    if let Some(path) = record {
+        replay::record_replay(&mut app, &path, tick_rate);
+    }
+    if let Some(path) = replay {
+        replay::play_replay(&mut app, &path, tick_rate);
+    }
+
+    app
+}
+
+/// Makes Ctrl-C notify clients and shut the server down gracefully
+pub fn shutdown_on_ctrl_c(app: &mut App) {
+    let (tx, rx) = channel();
+    if let Err(e) = ctrlc::set_handler(move || {
+        // The receiver is only gone once the app already exited.
+        let _ = tx.send(());
+    }) {
+        warn!("Failed to set Ctrl-C handler: {:?}", e);
+    }
+
+    app.insert_resource(ShutdownReceiver(Arc::new(Mutex::new(rx))));
+}
+
+#[derive(Resource, Debug)]
+/// Tokens clients must join with, everyone gets in without it
+enum JoinTokens {
+    /// A single secret shared by every client
+    Secret(String),
+    /// An allowlist, typically one token per player
+    List(HashSet<String>),
+}
+
+impl JoinTokens {
+    /// Reads the configured source, `None` when joining is open to everyone
+    fn from_args(args: &Args) -> Result<Option<Self>, String> {
+        if let Some(secret) = &args.join_secret {
+            return Ok(Some(Self::Secret(secret.clone())));
+        }
+        let Some(path) = &args.token_file else {
+            return Ok(None);
+        };
+        let tokens: HashSet<String> = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read token file {}: {e}", path.display()))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        if tokens.is_empty() {
+            return Err(format!("Token file {} has no tokens", path.display()));
+        }
+        Ok(Some(Self::List(tokens)))
+    }
+
+    fn accepts(&self, token: Option<&str>) -> bool {
+        match (self, token) {
+            (Self::Secret(secret), Some(token)) => token == secret,
+            (Self::List(tokens), Some(token)) => tokens.contains(token),
+            (_, None) => false,
+        }
+    }
+}
+
+#[derive(Resource)]
+/// Lines typed into the server console, fed by a background thread reading stdin
+struct AdminCommandReceiver(Arc<Mutex<Receiver<String>>>);
+
+/// Lets the operator type commands such as `kick <network_id>`, `tp <network_id> <x> <y>`,
+/// `list`, `dump [path]` or `restart` into the server console
+pub fn read_admin_commands(app: &mut App) {
+    let (tx, rx) = channel();
+    let spawned = std::thread::Builder::new()
+        .name("admin-commands".to_string())
+        .spawn(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                // The receiver is only gone once the app already exited.
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        warn!("Failed to read admin commands: {:?}", e);
+        return;
+    }
+
+    app.insert_resource(AdminCommandReceiver(Arc::new(Mutex::new(rx))));
+}
+
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+/// Replicon's tick schedule when replicating slower than the simulation
+struct ReplicationTick;
+
+fn run_replication_tick(world: &mut World) {
+    world.run_schedule(ReplicationTick);
+}
+
+fn configure_plugins(app: &mut App) {
+    let tick_rate = app.world().resource::<TickRate>().0;
+    let replication_rate = app.world().resource::<GameConfig>().replication_rate;
+    // Replicon replicates whenever its tick advances. Advancing it on a timer in fixed time
+    // replicates at that rate, right after a simulation step.
+    let tick_schedule = if replication_rate < tick_rate {
+        let period = Duration::from_secs_f64(1.0 / replication_rate);
+        app.add_systems(
+            FixedPostUpdate,
+            run_replication_tick.run_if(on_timer(period)),
+        );
+        ReplicationTick.intern()
+    } else {
+        FixedPostUpdate.intern()
+    };
+    let log_level = app.world().resource::<Args>().log_level;
+    // Replays step as fast as they can, they don't simulate by the wall clock.
+    let wait = if app.world().resource::<Args>().replay.is_some() {
+        Duration::ZERO
+    } else {
+        Duration::from_secs_f64(1.0 / tick_rate)
+    };
+
+    app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(wait)))
+        .add_plugins((
+            LogPlugin {
+                level: log_level,
+                ..default()
+            },
+            StatesPlugin,
+        ))
+        .add_plugins((
+            RepliconPlugins.build().set(ServerPlugin {
+                tick_schedule,
+                ..default()
+            }),
+            RepliconQuinnetPlugins,
+            GameSharedPlugin,
+        ))
+        // The simulation and replication tick in the fixed schedule. The runner sleeps out the rest of
+        // each period, so most loops run exactly one fixed step and a late loop catches up with two
+        // instead of stretching the step.
+        .insert_resource(Time::<Fixed>::from_hz(tick_rate));
+}
+
+fn parse_replication_hz(value: &str) -> Result<f64, String> {
+    let rate: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if !(rate > 0.0 && rate.is_finite()) {
+        return Err("must be a positive number".to_string());
+    }
+    Ok(rate)
+}
+
+fn parse_positive(value: &str) -> Result<f32, String> {
+    let number: f32 = value.parse().map_err(|e| format!("{e}"))?;
+    if !(number > 0.0 && number.is_finite()) {
+        return Err("must be a positive number".to_string());
+    }
+    Ok(number)
+}
+
+fn parse_non_negative(value: &str) -> Result<f32, String> {
+    let number: f32 = value.parse().map_err(|e| format!("{e}"))?;
+    if !(number >= 0.0 && number.is_finite()) {
+        return Err("must be a non-negative number".to_string());
+    }
+    Ok(number)
+}
+
+fn parse_tick_rate(value: &str) -> Result<f64, String> {
+    let rate: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if !TICK_RATE_RANGE.contains(&rate) {
+        return Err(format!(
+            "must be between {} and {} Hz",
+            TICK_RATE_RANGE.start(),
+            TICK_RATE_RANGE.end()
+        ));
+    }
+    Ok(rate)
+}
+
+fn configure_systems(app: &mut App) {
+    app.init_state::<GamePhase>();
+
+    app.add_systems(Startup, (setup_server, spawn_ball));
+    app.add_systems(First, reset_input_stats);
+    app.add_systems(
+        Update,
+        (
+            read_connected,
+            update_visibility.after(read_connected),
+            check_shutdown,
+            process_admin_commands,
+            kick_idle_players.run_if(
+                in_state(GamePhase::Playing)
+                    .and(resource_exists::<IdleTimeout>)
+                    .and(not(resource_exists::<Paused>)),
+            ),
+            shut_down_when_empty.run_if(resource_exists::<EmptyTimer>),
+            restart_endpoint.run_if(resource_exists::<Restart>),
+            start_when_ready.run_if(in_state(GamePhase::Lobby)),
+            send_game_start_to_late_joiners,
+            send_pause_to_late_joiners.run_if(resource_exists::<Paused>),
+            assign_default_names,
+            broadcast_roster,
+        ),
+    );
+    // Movement steps by the fixed timestep, so the simulation doesn't depend on frame timing.
+    app.add_systems(
+        FixedUpdate,
+        (
+            (
+                hold_inputs,
+                apply_movement,
+                validate_movement,
+                apply_knockback,
+                resolve_collisions,
+                update_facing,
+            )
+                .chain()
+                .run_if(in_state(GamePhase::Playing).and(not(resource_exists::<Paused>))),
+            process_respawns.run_if(not(resource_exists::<Paused>)),
+            record_positions
+                .after(resolve_collisions)
+                .after(process_respawns),
+            sync_net_positions
+                .after(record_positions)
+                .run_if(resource_exists::<CompactPositions>),
+            mark_positions_changed
+                .after(sync_net_positions)
+                .run_if(resource_exists::<ReplicateEveryTick>),
+            simulate_ball
+                .after(resolve_collisions)
+                .run_if(not(resource_exists::<Paused>)),
+        ),
+    );
+    app.add_systems(Last, disconnect_observer);
+
+    app.add_observer(on_client_position);
+    app.add_observer(on_set_player_name);
+    app.add_observer(on_chat_message);
+    app.add_observer(on_whisper);
+    app.add_observer(on_toggle_ready);
+    app.add_observer(on_ping);
+    app.add_observer(on_connect_intent);
+    app.add_observer(on_join_room);
+    app.add_observer(on_client_disconnect);
+    app.add_observer(log_protocol_mismatch);
+    // Pings are sent automatically, so they don't count as activity.
+    app.add_observer(record_activity::<ClientMovementIntent>);
+    app.add_observer(record_activity::<SetPlayerName>);
+    app.add_observer(record_activity::<ChatMessage>);
+    app.add_observer(record_activity::<Whisper>);
+    app.add_observer(record_activity::<ToggleReady>);
+    app.add_observer(on_apply_damage);
+    app.add_observer(announce_join);
+    app.add_observer(cleanup_disconnected);
+    app.add_observer(start_empty_timer);
+    app.add_observer(cancel_empty_timer);
+    app.add_observer(release_spawn_slot);
+    app.add_observer(release_color_slot);
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn process_admin_commands(
+    receiver: Option<Res<AdminCommandReceiver>>,
+    players: Query<(Entity, &Player)>,
+    // Teleporting writes the transforms dumping reads.
+    mut player_queries: ParamSet<(
+        Query<(
+            &mut Transform,
+            &mut MovementCheck,
+            &mut Velocity,
+            &LastProcessedInput,
+        )>,
+        Query<(
+            Entity,
+            &Player,
+            &Transform,
+            Option<&Health>,
+            Option<&RoomId>,
+            Option<&PlayerName>,
+        )>,
+        Query<(&NetworkId, Option<&PlayerName>, Option<&Transform>), With<ConnectedClient>>,
+    )>,
+    server: Res<QuinnetServer>,
+    bounds: Res<WorldBounds>,
+    settings: Option<Res<EndpointSettings>>,
+    restart: Option<Res<Restart>>,
+    paused: Option<Res<Paused>>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    let Some(receiver) = receiver else {
+        return;
+    };
+    let Ok(rx) = receiver.0.lock() else {
+        return;
+    };
+
+    for line in rx.try_iter() {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (None, _) => {}
+            (Some("kick"), Some(id)) => {
+                let Ok(network_id) = id.parse::<u64>() else {
+                    error!("Not a network id: {id}");
+                    continue;
+                };
+                let Some((entity, _)) = players
+                    .iter()
+                    .find(|(_, player)| player.network_id == network_id)
+                else {
+                    error!("No client with network id {network_id}");
+                    continue;
+                };
+
+                info!("Kicking client {network_id}");
+                commands.server_trigger(ToClients {
+                    mode: SendMode::Direct(ClientId::Client(entity)),
+                    message: Kicked {
+                        reason: "Kicked by the server operator".to_string(),
+                    },
+                });
+                // The backend despawns the client entity once it's gone, which removes the player.
+                disconnect_client(&mut commands, entity, DisconnectReason::Kicked);
+            }
+            (Some("kick"), None) => error!("Usage: kick <network_id>"),
+            (Some("tp"), Some(id)) => {
+                let (Some(x), Some(y)) = (words.next(), words.next()) else {
+                    error!("Usage: tp <network_id> <x> <y>");
+                    continue;
+                };
+                let (Ok(network_id), Ok(x), Ok(y)) =
+                    (id.parse::<u64>(), x.parse::<f32>(), y.parse::<f32>())
+                else {
+                    error!("Usage: tp <network_id> <x> <y>");
+                    continue;
+                };
+                let Some((entity, _)) = players
+                    .iter()
+                    .find(|(_, player)| player.network_id == network_id)
+                else {
+                    error!("No client with network id {network_id}");
+                    continue;
+                };
+                let mut positions = player_queries.p0();
+                let Ok((mut transform, mut check, mut velocity, last_processed)) =
+                    positions.get_mut(entity)
+                else {
+                    continue;
+                };
+
+                let position = bounds.clamp(Vec2::new(x, y));
+                info!("Teleporting player {network_id} to {position}");
+                transform.translation = position.extend(transform.translation.z);
+                // A teleport isn't movement, so it must neither count as a violation nor carry
+                // knockback along.
+                check.last_position = position;
+                velocity.0 = Vec2::ZERO;
+                force_position(
+                    &mut commands,
+                    entity,
+                    &mut check,
+                    last_processed,
+                    position,
+                    time.elapsed(),
+                );
+            }
+            (Some("tp"), None) => error!("Usage: tp <network_id> <x> <y>"),
+            (Some("pause"), _) if paused.is_some() => error!("The game is already paused"),
+            (Some("pause"), _) => {
+                info!("Pausing the game");
+                commands.insert_resource(Paused);
+                commands.server_trigger(ToClients {
+                    mode: SendMode::Broadcast,
+                    message: GamePaused(true),
+                });
+            }
+            (Some("resume"), _) if paused.is_none() => error!("The game isn't paused"),
+            (Some("resume"), _) => {
+                info!("Resuming the game");
+                commands.remove_resource::<Paused>();
+                commands.server_trigger(ToClients {
+                    mode: SendMode::Broadcast,
+                    message: GamePaused(false),
+                });
+            }
+            (Some("restart"), _) => {
+                if settings.is_none() {
+                    error!("There is no endpoint to restart");
+                    continue;
+                }
+                if restart.is_some() {
+                    error!("A restart is already in progress");
+                    continue;
+                }
+
+                info!("Notifying clients of restart...");
+                commands.server_trigger(ToClients {
+                    mode: SendMode::Broadcast,
+                    message: ServerShutdown {
+                        reason: "Server is restarting, reconnecting shortly".to_string(),
+                    },
+                });
+                commands.server_trigger(ToClients {
+                    mode: SendMode::Broadcast,
+                    message: ServerDisconnect {
+                        reason: DisconnectReason::Restarting,
+                    },
+                });
+                commands.insert_resource(Restart::Notifying(Timer::new(
+                    SHUTDOWN_FLUSH_DELAY,
+                    TimerMode::Once,
+                )));
+            }
+            (Some("list"), _) => {
+                let connected = player_queries.p2();
+                let mut rows: Vec<_> = connected.iter().collect();
+                rows.sort_by_key(|(network_id, ..)| network_id.get());
+                let table = client_table(&rows, &server);
+                info!("{} clients:\n{table}", rows.len());
+            }
+            (Some("dump"), path) => {
+                let dumped = player_queries.p1();
+                let mut rows: Vec<_> = dumped.iter().collect();
+                rows.sort_by_key(|(entity, player, ..)| (player.network_id, *entity));
+                let table = player_table(&rows);
+                let Some(path) = path else {
+                    info!("{} players:\n{table}", rows.len());
+                    continue;
+                };
+                // Writing can take a while with many players, keep it off the tick.
+                let path = PathBuf::from(path);
+                let written =
+                    std::thread::Builder::new()
+                        .name("dump".to_string())
+                        .spawn(move || match fs::write(&path, table) {
+                            Ok(()) => info!("Dumped players to {}", path.display()),
+                            Err(e) => error!("Cannot write {}: {e}", path.display()),
+                        });
+                if let Err(e) = written {
+                    warn!("Failed to write the dump: {:?}", e);
+                }
+            }
+            (Some(command), _) => error!("Unknown command: {command}"),
+        }
+    }
+}
+
+/// Formats players as a table, one per row, flagging network ids that more than one entity has
+#[allow(clippy::type_complexity)]
+fn player_table(
+    rows: &[(
+        Entity,
+        &Player,
+        &Transform,
+        Option<&Health>,
+        Option<&RoomId>,
+        Option<&PlayerName>,
+    )],
+) -> String {
+    let mut table = format!(
+        "{:<20} {:<12} {:<24} {:>16} {:>13} {:>5}\n",
+        "network_id", "entity", "name", "position", "health", "room"
+    );
+    for (i, (entity, player, transform, health, room, name)) in rows.iter().enumerate() {
+        // Rows are sorted by network id, so duplicates are next to each other.
+        let duplicate = rows
+            .get(i.wrapping_sub(1))
+            .into_iter()
+            .chain(rows.get(i + 1))
+            .any(|(_, other, ..)| other.network_id == player.network_id);
+        let position = transform.translation.xy();
+        table += &format!(
+            "{:<20} {:<12} {:<24} {:>16} {:>13} {:>5}{}\n",
+            player.network_id,
+            entity.to_string(),
+            name.map_or("-", |name| name.0.as_str()),
+            format!("({:.1}, {:.1})", position.x, position.y),
+            health.map_or("-".to_string(), |health| format!(
+                "{:.0}/{:.0}",
+                health.current, health.max
+            )),
+            room.map_or("-".to_string(), |room| room.0.to_string()),
+            if duplicate { "  DUPLICATE ID" } else { "" },
+        );
+    }
+    table
+}
+
+/// Formats connected clients as a table, one per row, with their round trip time as quinnet
+/// measures it.
+///
+/// Clients that haven't joined yet have no name or position. Addresses are left out, quinnet
+/// doesn't expose them.
+fn client_table(
+    rows: &[(&NetworkId, Option<&PlayerName>, Option<&Transform>)],
+    server: &QuinnetServer,
+) -> String {
+    let mut table = format!(
+        "{:<20} {:<24} {:>8} {:>16}\n",
+        "network_id", "name", "rtt", "position"
+    );
+    for (network_id, name, transform) in rows {
+        // Replicon's network ids are quinnet's client ids.
+        let rtt = server
+            .get_endpoint()
+            .and_then(|endpoint| endpoint.get_connection_stats(network_id.get()))
+            .map_or("-".to_string(), |stats| {
+                format!("{}ms", stats.path.rtt.as_millis())
+            });
+        table += &format!(
+            "{:<20} {:<24} {:>8} {:>16}\n",
+            network_id.get(),
+            name.map_or("-", |name| name.0.as_str()),
+            rtt,
+            transform.map_or("-".to_string(), |transform| {
+                let position = transform.translation.xy();
+                format!("({:.1}, {:.1})", position.x, position.y)
+            }),
+        );
+    }
+    table
+}
+
+fn check_shutdown(
+    receiver: Option<Res<ShutdownReceiver>>,
+    timer: Option<ResMut<ShutdownTimer>>,
+    time: Res<Time>,
+    mut exit: MessageWriter<AppExit>,
+    mut commands: Commands,
+) {
+    if let Some(mut timer) = timer {
+        if timer.0.tick(time.delta()).is_finished() {
+            exit.write(AppExit::Success);
+        }
+        return;
+    }
+
+    if let Some(receiver) = receiver
+        && let Ok(rx) = receiver.0.lock()
+        && rx.try_recv().is_ok()
+    {
+        info!("Notifying clients of shutdown...");
+        commands.server_trigger(ToClients {
+            mode: SendMode::Broadcast,
+            message: ServerShutdown {
+                reason: "Server is shutting down".to_string(),
+            },
+        });
+        commands.server_trigger(ToClients {
+            mode: SendMode::Broadcast,
+            message: ServerDisconnect {
+                reason: DisconnectReason::ServerShutdown,
+            },
+        });
+        commands.insert_resource(ShutdownTimer(Timer::new(
+            SHUTDOWN_FLUSH_DELAY,
+            TimerMode::Once,
+        )));
+    }
+}
+
+/// Stops the endpoint once clients were told, dropping every client along with its player, then
+/// binds a new one with the same settings. Spawn points and colors are released as players go and
+/// the game goes back to the lobby.
+#[allow(clippy::too_many_arguments)]
+fn restart_endpoint(
+    mut restart: ResMut<Restart>,
+    clients: Query<Entity, With<ConnectedClient>>,
+    settings: Res<EndpointSettings>,
+    args: Res<Args>,
+    channels: Res<RepliconChannels>,
+    budget: Res<NetworkBudget>,
+    mut server: ResMut<QuinnetServer>,
+    mut next_phase: ResMut<NextState<GamePhase>>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    match &mut *restart {
+        Restart::Notifying(timer) => {
+            if !timer.tick(time.delta()).is_finished() {
+                return;
+            }
+            info!("Stopping the endpoint for restart");
+            for client in &clients {
+                commands.entity(client).despawn();
+            }
+            if let Err(e) = server.stop_endpoint() {
+                warn!("Failed to stop the endpoint: {:?}", e);
+            }
+            next_phase.set(GamePhase::Lobby);
+            commands.remove_resource::<Paused>();
+            *restart = Restart::Rebinding(Timer::new(RESTART_REBIND_DELAY, TimerMode::Once));
+        }
+        Restart::Rebinding(timer) => {
+            if !timer.tick(time.delta()).is_finished() {
+                return;
+            }
+            commands.remove_resource::<Restart>();
+            match start_listening(&mut server, &settings, &args, &channels, &budget) {
+                Ok((ip, port)) => {
+                    info!("Server restarted on [{ip}]:{port}");
+                    commands.insert_resource(BoundPort(port));
+                    commands.remove_resource::<NetworkError>();
+                }
+                Err(message) => {
+                    // Crashing would take the console with it, so stay up for another restart.
+                    error!("Restart failed, not accepting connections: {message}");
+                    commands.insert_resource(NetworkError(message));
+                }
+            }
+        }
+    }
+}
+
+/// Replicon refuses clients whose registered events and replication rules hash differently, but
+/// only says so in debug logs
+fn log_protocol_mismatch(
+    client_protocol: On<FromClient<ProtocolHash>>,
+    protocol: Res<ProtocolHash>,
+) {
+    if **client_protocol != *protocol {
+        warn!(
+            "Refusing {}: its protocol {:?} doesn't match ours {:?}, is it built from another version?",
+            client_protocol.client_id, **client_protocol, *protocol
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn read_connected(
+    query: Query<(Entity, &NetworkId, &JoinRequest), With<AuthorizedClient>>,
+    players: Query<(Entity, &Player, &Transform)>,
+    game_config: Res<GameConfig>,
+    max_players: Res<MaxPlayers>,
+    shape: Res<ShapeConfig>,
+    mut spawn_points: ResMut<SpawnPoints>,
+    mut palette: ResMut<ColorPalette>,
+    bounds: Res<WorldBounds>,
+    mut commands: Commands,
+) {
+    let mut player_count = players.iter().count();
+    let mut spawned = HashSet::new();
+
+    for (entity, network_id, join) in &query {
+        let _span = info_span!("client", network_id = network_id.get()).entered();
+        info!("Client connected: {}", network_id.get());
+        commands.entity(entity).remove::<JoinRequest>();
+
+        // Players spawned in this pass aren't in `players` yet, so the stale check can't see them.
+        if !spawned.insert(network_id.get()) {
+            error!(
+                "Network id {} was assigned to two clients at once, disconnecting {:?}",
+                network_id.get(),
+                entity
+            );
+            disconnect_client(&mut commands, entity, DisconnectReason::Error);
+            continue;
+        }
+
+        // A quick reconnect can reuse the id before the old entity is gone, so detach its player
+        // state here instead of letting it linger as a ghost.
+        for (stale, player, _) in &players {
+            if stale != entity && player.network_id == network_id.get() {
+                warn!(
+                    "Removing stale player {:?} for reconnecting client {}",
+                    stale, player.network_id
+                );
+                commands.entity(stale).try_remove::<PlayerState>();
+                player_count -= 1;
+            }
+        }
+
+        // A `JoinRoom` sent right after connecting may have placed the client already.
+        commands.entity(entity).insert_if_new(RoomId::default());
+
+        if join.spectator {
+            info!("Client {} is spectating", network_id.get());
+            commands.entity(entity).insert(Spectator);
+            commands.server_trigger(ToClients {
+                mode: SendMode::Direct(ClientId::Client(entity)),
+                message: *game_config,
+            });
+            commands.server_trigger(ToClients {
+                mode: SendMode::Direct(ClientId::Client(entity)),
+                message: InitialSnapshot,
+            });
+            continue;
+        }
+
+        if player_count >= max_players.0 {
+            info!(
+                "Refusing client {}: server is full ({} players)",
+                network_id.get(),
+                max_players.0
+            );
+            commands.server_trigger(ToClients {
+                mode: SendMode::Direct(ClientId::Client(entity)),
+                message: ConnectionRejected {
+                    reason: format!("Server is full ({} players)", max_players.0),
+                },
+            });
+            // Disconnects only after pending messages are sent, so the rejection still arrives.
+            disconnect_client(&mut commands, entity, DisconnectReason::Error);
+            continue;
+        }
+        player_count += 1;
+
+        let occupied: Vec<Vec2> = players
+            .iter()
+            .map(|(_, _, transform)| transform.translation.xy())
+            .collect();
+        let clearance = shape.0.collision_radius() * 2.0;
+        let position = match spawn_points.claim(&occupied, clearance) {
+            Some((slot, position)) => {
+                commands.entity(entity).insert(SpawnSlot(slot));
+                position
+            }
+            None => spawn_points.next_overflow(&bounds),
+        };
+
+        let (color_slot, color) = palette.claim();
+        if let Some(slot) = color_slot {
+            commands.entity(entity).insert(ColorSlot(slot));
+        }
+
+        commands.entity(entity).insert((
+            Player {
+                network_id: network_id.get(),
+            },
+            PlayerColor(color),
+            shape.0,
+            Transform::from_translation(position.extend(0.0)),
+            (MovementInput::default(), InputHistory::default()),
+            MovementVelocity::default(),
+            Facing::default(),
+            Velocity::default(),
+            MovementCheck {
+                last_position: position,
+                ..default()
+            },
+            LastProcessedInput::default(),
+            PlayerReady::default(),
+            Health::new(PLAYER_MAX_HEALTH),
+            InputStats::default(),
+            IdleTime::default(),
+            PendingName(Timer::new(NAME_GRACE_PERIOD, TimerMode::Once)),
+        ));
+
+        commands.server_trigger(ToClients {
+            mode: SendMode::Direct(ClientId::Client(entity)),
+            message: *game_config,
+        });
+        // Sent in the same tick as the player spawns, so it lands once the world has replicated.
+        commands.server_trigger(ToClients {
+            mode: SendMode::Direct(ClientId::Client(entity)),
+            message: InitialSnapshot,
+        });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn on_connect_intent(
+    intent: On<FromClient<ConnectIntent>>,
+    joined: Query<(), Or<(With<Player>, With<Spectator>)>>,
+    tokens: Option<Res<JoinTokens>>,
+    #[cfg(feature = "dev")] admin_token: Option<Res<noclip::AdminToken>>,
+    mut commands: Commands,
+) {
+    let Some(entity) = intent.client_id.entity() else {
+        return;
+    };
+    if joined.contains(entity) {
+        debug!("Ignoring repeated join request from {}", intent.client_id);
+        return;
+    }
+
+    #[cfg(feature = "dev")]
+    let is_admin = admin_token.is_some_and(|admin| intent.token.as_ref() == Some(&admin.0));
+    #[cfg(not(feature = "dev"))]
+    let is_admin = false;
+    if is_admin {
+        info!("{} joined as an admin", intent.client_id);
+        #[cfg(feature = "dev")]
+        commands.entity(entity).try_insert(noclip::AdminFlag);
+    }
+
+    // Without a `JoinRequest` no player is spawned, so nothing the client sends is applied.
+    if let Some(tokens) = tokens
+        && !is_admin
+        && !tokens.accepts(intent.token.as_deref())
+    {
+        let reason = match intent.token {
+            Some(_) => "Invalid join token",
+            None => "This server requires a join token",
+        };
+        warn!("Refusing {}: {reason}", intent.client_id);
+        commands.server_trigger(ToClients {
+            mode: SendMode::Direct(intent.client_id),
+            message: ConnectionRejected {
+                reason: reason.to_string(),
+            },
+        });
+        disconnect_client(&mut commands, entity, DisconnectReason::Unauthorized);
+        return;
+    }
+
+    // Authorization may still be pending, `read_connected` picks the request up once it's done.
+    commands.entity(entity).try_insert(JoinRequest {
+        spectator: intent.spectator,
+    });
+}
+
+fn on_join_room(
+    join: On<FromClient<JoinRoom>>,
+    clients: Query<(&NetworkId, Option<&RoomId>)>,
+    mut commands: Commands,
+) {
+    let Some(entity) = join.client_id.entity() else {
+        return;
+    };
+    let Ok((network_id, room)) = clients.get(entity) else {
+        return;
+    };
+    if room == Some(&RoomId(join.room_id)) {
+        return;
+    }
+
+    info!("Client {} joined room {}", network_id.get(), join.room_id);
+    commands.entity(entity).insert(RoomId(join.room_id));
+}
+
+/// Hides every entity with a [`RoomId`] from the clients in other rooms, whenever anything moved.
+///
+/// With a [`ViewRadius`] this runs every frame and also hides the players too far from a
+/// client's own player. Spectators have no player of their own and see the whole room.
+fn update_visibility(
+    changed: Query<(), Changed<RoomId>>,
+    view_radius: Option<Res<ViewRadius>>,
+    mut clients: Query<(Entity, &RoomId, &mut ClientVisibility)>,
+    entities: Query<(Entity, &RoomId, Option<&Transform>, Has<Player>)>,
+) {
+    if changed.is_empty() && view_radius.is_none() {
+        return;
+    }
+    for (client, client_room, mut visibility) in &mut clients {
+        let origin = view_radius.as_ref().and_then(|radius| {
+            let (_, _, transform, is_player) = entities.get(client).ok()?;
+            let transform = transform.filter(|_| is_player)?;
+            Some((transform.translation.xy(), radius.0))
+        });
+        for (entity, room, transform, is_player) in &entities {
+            let mut visible = room == client_room;
+            if let Some((origin, radius)) = origin
+                && let Some(transform) = transform
+                && is_player
+                && entity != client
+            {
+                let reach = if visibility.is_visible(entity) {
+                    radius * (1.0 + VIEW_RADIUS_MARGIN)
+                } else {
+                    radius
+                };
+                visible &= origin.distance(transform.translation.xy()) <= reach;
+            }
+            visibility.set_visibility(entity, visible);
+        }
+    }
+}
+
+fn on_client_disconnect(disconnect: On<FromClient<ClientDisconnect>>, clients: Query<&NetworkId>) {
+    let Some(Ok(network_id)) = disconnect
+        .client_id
+        .entity()
+        .map(|entity| clients.get(entity))
+    else {
+        return;
+    };
+    info!(
+        "Client {} is disconnecting: {:?}",
+        network_id.get(),
+        disconnect.reason
+    );
+}
+
+/// Announces players once they have a name, so the join line can show it
+fn announce_join(
+    add: On<Add, PlayerName>,
+    query: Query<(&Player, &PlayerName)>,
+    mut commands: Commands,
+) {
+    let Ok((player, name)) = query.get(add.entity) else {
+        return;
+    };
+    commands.server_trigger(ToClients {
+        mode: SendMode::Broadcast,
+        message: PlayerJoined {
+            network_id: player.network_id,
+            name: name.0.clone(),
+        },
+    });
+}
+
+fn cleanup_disconnected(
+    remove: On<Remove, AuthorizedClient>,
+    query: Query<(&Player, Option<&PlayerName>)>,
+    mut commands: Commands,
+) {
+    let Ok((player, name)) = query.get(remove.entity) else {
+        return;
+    };
+
+    let _span = info_span!("client", network_id = player.network_id).entered();
+    info!("Client disconnected: {}", player.network_id);
+
+    // This runs before the player state goes, graceful leave or not. Players that never got a
+    // name were never announced, so they leave silently too.
+    if let Some(name) = name {
+        commands.server_trigger(ToClients {
+            mode: SendMode::BroadcastExcept(ClientId::Client(remove.entity)),
+            message: PlayerLeft {
+                network_id: player.network_id,
+                name: name.0.clone(),
+            },
+        });
+    }
+
+    // Player state lives on the client entity, which the backend despawns on disconnect. Removing
+    // it explicitly also covers clients that lose authorization without being despawned.
+    commands.entity(remove.entity).try_remove::<PlayerState>();
+}
+
+fn start_empty_timer(
+    _remove: On<Remove, AuthorizedClient>,
+    clients: Query<(), With<AuthorizedClient>>,
+    timeout: Option<Res<EmptyTimeout>>,
+    mut commands: Commands,
+) {
+    let Some(timeout) = timeout else {
+        return;
+    };
+    // The leaving client still counts while its removal is observed.
+    if clients.iter().count() > 1 {
+        return;
+    }
+
+    info!(
+        "The last client left, shutting down in {:.0}s unless someone joins",
+        timeout.0.as_secs_f32()
+    );
+    commands.insert_resource(EmptyTimer(Timer::new(timeout.0, TimerMode::Once)));
+}
+
+fn cancel_empty_timer(
+    _add: On<Add, AuthorizedClient>,
+    timer: Option<Res<EmptyTimer>>,
+    mut commands: Commands,
+) {
+    if timer.is_some() {
+        info!("A client joined, no longer shutting down");
+        commands.remove_resource::<EmptyTimer>();
+    }
+}
+
+fn shut_down_when_empty(
+    mut timer: ResMut<EmptyTimer>,
+    time: Res<Time>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    if timer.0.tick(time.delta()).just_finished() {
+        info!("No client joined in time, shutting down");
+        exit.write(AppExit::Success);
+    }
+}
+
+fn release_spawn_slot(
+    remove: On<Remove, SpawnSlot>,
+    query: Query<&SpawnSlot>,
+    mut spawn_points: ResMut<SpawnPoints>,
+) {
+    if let Ok(slot) = query.get(remove.entity) {
+        spawn_points.release(slot.0);
+    }
+}
+
+fn release_color_slot(
+    remove: On<Remove, ColorSlot>,
+    query: Query<&ColorSlot>,
+    mut palette: ResMut<ColorPalette>,
+) {
+    if let Ok(slot) = query.get(remove.entity) {
+        palette.release(slot.0);
+    }
+}
+
+fn on_client_position(
+    message: On<FromClient<ClientMovementIntent>>,
+    mut query: Query<(
+        &Player,
+        &mut MovementInput,
+        &mut InputHistory,
+        &mut LastProcessedInput,
+        &mut InputStats,
+    )>,
+) {
+    let Some(entity) = message.client_id.entity() else {
+        return;
+    };
+    let Ok((player, mut input, mut history, mut last_processed, mut stats)) = query.get_mut(entity)
+    else {
+        return;
+    };
+    // Intents arrive every frame, so only pay for the span when debug logging is on.
+    let _span = debug_span!("client", network_id = player.network_id).entered();
+
+    // Intents travel unreliably, so late or duplicated ones must not override newer input.
+    if message.seq <= last_processed.0 {
+        debug!(
+            "Dropping out-of-order movement intent {} from {} (last applied {})",
+            message.seq, message.client_id, last_processed.0
+        );
+        return;
+    }
+
+    stats.received_this_tick += 1;
+    if stats.received_this_tick > MAX_INTENTS_PER_TICK {
+        stats.rejected += 1;
+        debug!(
+            "Dropping movement intent from {}: rate limit exceeded ({} rejected)",
+            message.client_id, stats.rejected
+        );
+        return;
+    }
+
+    if !message.direction.is_finite() {
+        stats.rejected += 1;
+        warn!(
+            "Ignoring malformed movement intent {:?} from {} ({} rejected)",
+            message.direction, message.client_id, stats.rejected
+        );
+        return;
+    }
+
+    // Clients may scale their input by a sensitivity, which must not let them go above full speed.
+    input.0 = message.direction.clamp_length_max(1.0);
+    history.last = input.0;
+    history.age = 0;
+    last_processed.0 = message.seq;
+}
+
+fn on_set_player_name(
+    message: On<FromClient<SetPlayerName>>,
+    query: Query<&Player>,
+    mut commands: Commands,
+) {
+    let Some(entity) = message.client_id.entity() else {
+        return;
+    };
+    let Ok(player) = query.get(entity) else {
+        return;
+    };
+
+    let Some(name) = sanitize_player_name(&message.0) else {
+        warn!(
+            "Ignoring invalid name {:?} from client {}",
+            message.0, player.network_id
+        );
+        return;
+    };
+
+    info!("Client {} is now known as {name}", player.network_id);
+    commands
+        .entity(entity)
+        .insert(PlayerName(name))
+        .remove::<PendingName>();
+}
+
+fn on_chat_message(
+    message: On<FromClient<ChatMessage>>,
+    query: Query<&Player>,
+    mut commands: Commands,
+) {
+    let Some(entity) = message.client_id.entity() else {
+        return;
+    };
+    let Ok(player) = query.get(entity) else {
+        return;
+    };
+    let Some(text) = sanitize_chat_message(&message.text) else {
+        return;
+    };
+
+    info!("[chat] {}: {text}", player.network_id);
+    commands.server_trigger(ToClients {
+        mode: SendMode::Broadcast,
+        message: BroadcastChat {
+            sender: player.network_id,
+            text,
+        },
+    });
+}
+
+fn on_whisper(
+    message: On<FromClient<Whisper>>,
+    players: Query<(Entity, &Player, &PlayerName)>,
+    mut commands: Commands,
+) {
+    let Some(entity) = message.client_id.entity() else {
+        return;
+    };
+    let Ok((_, sender, _)) = players.get(entity) else {
+        return;
+    };
+    let Some(text) = sanitize_chat_message(&message.text) else {
+        return;
+    };
+
+    // Names aren't unique, an exact match wins over one differing only in case.
+    let target_name = message.target_name.trim();
+    let target = players
+        .iter()
+        .find(|(_, _, name)| name.0 == target_name)
+        .or_else(|| {
+            players
+                .iter()
+                .find(|(_, _, name)| name.0.eq_ignore_ascii_case(target_name))
+        });
+    let Some((target_entity, target, _)) = target else {
+        commands.server_trigger(ToClients {
+            mode: SendMode::Direct(ClientId::Client(entity)),
+            message: WhisperFailed {
+                target_name: target_name.to_string(),
+            },
+        });
+        return;
+    };
+
+    debug!("[whisper] {} -> {}", sender.network_id, target.network_id);
+    let mut recipients = vec![target_entity];
+    if target_entity != entity {
+        recipients.push(entity);
+    }
+    for recipient in recipients {
+        commands.server_trigger(ToClients {
+            mode: SendMode::Direct(ClientId::Client(recipient)),
+            message: WhisperDelivery {
+                sender: sender.network_id,
+                target: target.network_id,
+                text: text.clone(),
+            },
+        });
+    }
+}
+
+fn on_toggle_ready(
+    message: On<FromClient<ToggleReady>>,
+    mut query: Query<(&Player, &mut PlayerReady)>,
+    phase: Res<State<GamePhase>>,
+) {
+    if *phase.get() != GamePhase::Lobby {
+        return;
+    }
+    let Some(entity) = message.client_id.entity() else {
+        return;
+    };
+    let Ok((player, mut ready)) = query.get_mut(entity) else {
+        return;
+    };
+
+    ready.0 = !ready.0;
+    info!(
+        "Client {} is {}",
+        player.network_id,
+        if ready.0 { "ready" } else { "not ready" }
+    );
+}
+
+fn on_ping(ping: On<FromClient<Ping>>, mut commands: Commands) {
+    commands.server_trigger(ToClients {
+        mode: SendMode::Direct(ping.client_id),
+        message: Pong {
+            id: ping.id,
+            client_time_ms: ping.client_time_ms,
+        },
+    });
+}
+
+fn start_when_ready(
+    players: Query<&PlayerReady, With<Player>>,
+    mut next_phase: ResMut<NextState<GamePhase>>,
+    mut commands: Commands,
+) {
+    let player_count = players.iter().count();
+    if player_count < MIN_PLAYERS_TO_START || !players.iter().all(|ready| ready.0) {
+        return;
+    }
+
+    info!("All {player_count} players are ready, starting the game");
+    next_phase.set(GamePhase::Playing);
+    commands.server_trigger(ToClients {
+        mode: SendMode::Broadcast,
+        message: GameStart,
+    });
+}
+
+#[allow(clippy::type_complexity)]
+fn send_game_start_to_late_joiners(
+    query: Query<Entity, Or<(Added<Player>, Added<Spectator>)>>,
+    phase: Res<State<GamePhase>>,
+    mut commands: Commands,
+) {
+    // Runs every frame even in the lobby, so players from before the start don't count as added.
+    if *phase.get() != GamePhase::Playing {
+        return;
+    }
+
+    for entity in &query {
+        commands.server_trigger(ToClients {
+            mode: SendMode::Direct(ClientId::Client(entity)),
+            message: GameStart,
+        });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn send_pause_to_late_joiners(
+    query: Query<Entity, Or<(Added<Player>, Added<Spectator>)>>,
+    mut commands: Commands,
+) {
+    for entity in &query {
+        commands.server_trigger(ToClients {
+            mode: SendMode::Direct(ClientId::Client(entity)),
+            message: GamePaused(true),
+        });
+    }
+}
+
+fn broadcast_roster(
+    renamed: Query<(), Changed<PlayerName>>,
+    mut removed: RemovedComponents<Player>,
+    players: Query<(&Player, &PlayerName)>,
+    mut debounce: ResMut<RosterDebounce>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    // Players only join the roster once named, so a new name also covers joins.
+    let left = removed.read().count() > 0;
+    if (left || !renamed.is_empty()) && debounce.0.is_none() {
+        debounce.0 = Some(Timer::new(ROSTER_DEBOUNCE, TimerMode::Once));
+    }
+
+    let Some(timer) = debounce.0.as_mut() else {
+        return;
+    };
+    if !timer.tick(time.delta()).is_finished() {
+        return;
+    }
+    debounce.0 = None;
+
+    let mut roster: Vec<_> = players
+        .iter()
+        .map(|(player, name)| (player.network_id, name.0.clone()))
+        .collect();
+    roster.sort_by_key(|(network_id, _)| *network_id);
+
+    debug!("Broadcasting roster of {} players", roster.len());
+    commands.server_trigger(ToClients {
+        mode: SendMode::Broadcast,
+        message: RosterUpdate { players: roster },
+    });
+}
+
+fn assign_default_names(
+    mut query: Query<(Entity, &Player, &mut PendingName)>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    for (entity, player, mut pending) in query.iter_mut() {
+        if pending.0.tick(time.delta()).is_finished() {
+            let name = format!("Player {}", player.network_id);
+            info!("Client {} sent no name, using {name}", player.network_id);
+            commands
+                .entity(entity)
+                .insert(PlayerName(name))
+                .remove::<PendingName>();
+        }
+    }
+}
+
+fn record_activity<E: Send + Sync + 'static>(
+    message: On<FromClient<E>>,
+    mut query: Query<&mut IdleTime>,
+) {
+    if let Some(entity) = message.client_id.entity()
+        && let Ok(mut idle) = query.get_mut(entity)
+    {
+        idle.0 = Duration::ZERO;
+    }
+}
+
+fn kick_idle_players(
+    mut query: Query<(Entity, &Player, &mut IdleTime)>,
+    timeout: Res<IdleTimeout>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    for (entity, player, mut idle) in query.iter_mut() {
+        // Only just crossing the timeout, so the kick is sent once while the disconnect is pending.
+        let was_idle = idle.0 >= timeout.0;
+        idle.0 += time.delta();
+        if was_idle || idle.0 < timeout.0 {
+            continue;
+        }
+
+        info!(
+            "Kicking client {} after {:.0}s without input",
+            player.network_id,
+            timeout.0.as_secs_f32()
+        );
+        commands.server_trigger(ToClients {
+            mode: SendMode::Direct(ClientId::Client(entity)),
+            message: IdleKick { timeout: timeout.0 },
+        });
+        disconnect_client(&mut commands, entity, DisconnectReason::Timeout);
+    }
+}
+
+fn reset_input_stats(mut query: Query<&mut InputStats>) {
+    for mut stats in query.iter_mut() {
+        stats.received_this_tick = 0;
+    }
+}
+
+fn on_apply_damage(
+    damage: On<ApplyDamage>,
+    mut query: Query<(&Player, &mut Health), Without<Dead>>,
+    respawn_delay: Res<RespawnDelay>,
+    mut commands: Commands,
+) {
+    if !damage.amount.is_finite() || damage.amount <= 0.0 {
+        return;
+    }
+    let Ok((player, mut health)) = query.get_mut(damage.target) else {
+        return;
+    };
+
+    health.current = (health.current - damage.amount).max(0.0);
+    if !health.is_dead() {
+        return;
+    }
+
+    info!("Player {} died", player.network_id);
+    commands.entity(damage.target).insert((
+        Dead,
+        RespawnTimer(Timer::new(respawn_delay.0, TimerMode::Once)),
+    ));
+    commands.server_trigger(ToClients {
+        mode: SendMode::Broadcast,
+        message: PlayerDied {
+            network_id: player.network_id,
+        },
+    });
+}
+
+#[allow(clippy::type_complexity)]
+fn process_respawns(
+    mut query: Query<
+        (
+            Entity,
+            &Player,
+            &mut RespawnTimer,
+            &mut Health,
+            &mut Transform,
+            &PlayerShape,
+            Option<&mut SpawnSlot>,
+        ),
+        With<Dead>,
+    >,
+    alive: Query<&Transform, (With<Player>, Without<Dead>)>,
+    mut spawn_points: ResMut<SpawnPoints>,
+    bounds: Res<WorldBounds>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    let mut occupied: Vec<Vec2> = alive
+        .iter()
+        .map(|transform| transform.translation.xy())
+        .collect();
+
+    for (entity, player, mut timer, mut health, mut transform, shape, slot) in query.iter_mut() {
+        if !timer.0.tick(time.delta()).is_finished() {
+            continue;
+        }
+
+        // The new point is claimed before the old one is released, so it's always a fresh spot.
+        let clearance = shape.collision_radius() * 2.0;
+        let position = match (spawn_points.claim(&occupied, clearance), slot) {
+            (Some((new_slot, position)), Some(mut slot)) => {
+                spawn_points.release(slot.0);
+                slot.0 = new_slot;
+                position
+            }
+            (Some((new_slot, position)), None) => {
+                commands.entity(entity).insert(SpawnSlot(new_slot));
+                position
+            }
+            (None, Some(slot)) => spawn_points.points[slot.0],
+            (None, None) => spawn_points.next_overflow(&bounds),
+        };
+        occupied.push(position);
+
+        info!("Player {} respawned", player.network_id);
+        health.current = health.max;
+        transform.translation = position.extend(transform.translation.z);
+        commands
+            .entity(entity)
+            .remove::<(Dead, RespawnTimer)>()
+            .insert((MovementVelocity::default(), Velocity::default()));
+        commands.server_trigger(ToClients {
+            mode: SendMode::Broadcast,
+            message: PlayerRespawned {
+                network_id: player.network_id,
+            },
+        });
+    }
+}
+
+/// Keeps applying a player's last input while its intents stop arriving, then fades it out.
+///
+/// Held input is resent every frame, so going quiet means intents are being lost. Holding covers
+/// short gaps, and decaying stops a player whose connection dropped without moving it forever.
+fn hold_inputs(
+    mut query: Query<(&Player, &mut MovementInput, &mut InputHistory), Without<Dead>>,
+    hold: Res<InputHold>,
+) {
+    for (player, mut input, mut history) in query.iter_mut() {
+        history.age = history.age.saturating_add(1);
+        // A release is always sent, so there's nothing to decay after one.
+        if history.last == Vec2::ZERO || history.age <= hold.0 {
+            continue;
+        }
+
+        let decayed = history.age - hold.0;
+        if decayed == 1 {
+            info!(
+                "Input from player {} went stale after {} ticks, decaying it",
+                player.network_id, hold.0
+            );
+        }
+        let remaining = 1.0 - decayed as f32 / INPUT_DECAY_TICKS as f32;
+        input.0 = history.last * remaining.max(0.0);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn apply_movement(
+    mut query: Query<
+        (&MovementInput, &mut MovementVelocity, &mut Transform),
+        (Without<Dead>, Clipping),
+    >,
+    bounds: Res<WorldBounds>,
+    config: Res<MovementConfig>,
+    accel: Res<AccelConfig>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_secs();
+    for (input, mut velocity, mut transform) in query.iter_mut() {
+        velocity.0 = accelerate(velocity.0, input.0, &config, &accel, delta);
+        let position = bounds.clamp(transform.translation.xy() + velocity.0 * delta);
+        // Only players that moved get replicated, standing still costs no bandwidth.
+        if position != transform.translation.xy() {
+            transform.translation = position.extend(transform.translation.z);
+        }
+    }
+}
+
+/// Pulls players back when they moved further in a tick than their speed allows.
+///
+/// Clamped intents and `accelerate` already keep movement within the limit, so this is defense
+/// in depth against anything else that moves a player.
+#[allow(clippy::type_complexity)]
+fn validate_movement(
+    mut query: Query<
+        (
+            Entity,
+            &Player,
+            &mut Transform,
+            &mut MovementCheck,
+            &LastProcessedInput,
+        ),
+        (Without<Dead>, Clipping),
+    >,
+    config: Res<MovementConfig>,
+    limit: Res<MovementViolationLimit>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    let max_distance = config.speed * time.delta_secs() + MOVEMENT_TOLERANCE;
+
+    for (entity, player, mut transform, mut check, last_processed) in query.iter_mut() {
+        let displacement = transform.translation.xy() - check.last_position;
+        if displacement.length() <= max_distance {
+            continue;
+        }
+
+        check.violations += 1;
+        warn!(
+            "Player {} moved {:.1} units in one tick, allowed {:.1} ({} violations)",
+            player.network_id,
+            displacement.length(),
+            max_distance,
+            check.violations
+        );
+        let position = check.last_position + displacement.clamp_length_max(max_distance);
+        transform.translation = position.extend(transform.translation.z);
+        force_position(
+            &mut commands,
+            entity,
+            &mut check,
+            last_processed,
+            position,
+            time.elapsed(),
+        );
+
+        if limit.0 == Some(check.violations) {
+            warn!("Kicking player {} for moving too fast", player.network_id);
+            commands.server_trigger(ToClients {
+                mode: SendMode::Direct(ClientId::Client(entity)),
+                message: Kicked {
+                    reason: "Kicked for moving too fast".to_string(),
+                },
+            });
+            disconnect_client(&mut commands, entity, DisconnectReason::Kicked);
+        }
+    }
+}
+
+/// Turns players towards where they're walking, standing still or being pushed keeps the facing
+fn update_facing(mut query: Query<(&MovementVelocity, &mut Facing), Without<Dead>>) {
+    for (velocity, mut facing) in query.iter_mut() {
+        if velocity.0 != Vec2::ZERO {
+            facing.set_if_neq(Facing(velocity.0.to_angle()));
+        }
+    }
+}
+
+/// Moves players by their knockback after validation, it isn't their own movement
+fn apply_knockback(
+    mut query: Query<(&mut Velocity, &mut Transform), (Without<Dead>, Clipping)>,
+    bounds: Res<WorldBounds>,
+    time: Res<Time>,
+) {
+    for (mut velocity, mut transform) in query.iter_mut() {
+        if velocity.0 == Vec2::ZERO {
+            continue;
+        }
+        let (displacement, damped) = knockback_step(velocity.0, time.delta_secs());
+        let position = bounds.clamp(transform.translation.xy() + displacement);
+        transform.translation = position.extend(transform.translation.z);
+        velocity.set_if_neq(Velocity(damped));
+    }
+}
+
+fn sync_net_positions(
+    mut query: Query<(Entity, &Transform, Option<&mut NetPosition>), With<Player>>,
+    mut commands: Commands,
+) {
+    for (entity, transform, net_position) in query.iter_mut() {
+        let position = NetPosition::new(transform.translation.xy());
+        match net_position {
+            // Only moves of at least one step mark the component changed and get replicated.
+            Some(mut net_position) => {
+                net_position.set_if_neq(position);
+            }
+            None => {
+                commands.entity(entity).insert(position);
+            }
+        }
+    }
+}
+
+/// Replicates every player's position this tick, whether it moved or not
+fn mark_positions_changed(
+    mut query: Query<(&mut Transform, Option<&mut NetPosition>), With<Player>>,
+) {
+    for (mut transform, net_position) in query.iter_mut() {
+        transform.set_changed();
+        if let Some(mut net_position) = net_position {
+            net_position.set_changed();
+        }
+    }
+}
+
+/// Remembers where each player ended the tick, after collisions and respawns
+fn record_positions(mut query: Query<(&Transform, &mut MovementCheck)>) {
+    for (transform, mut check) in query.iter_mut() {
+        check.last_position = transform.translation.xy();
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn resolve_collisions(
+    mut query: Query<
+        (
+            Entity,
+            &Player,
+            &RoomId,
+            &PlayerShape,
+            &MovementVelocity,
+            &mut Velocity,
+            &mut Transform,
+        ),
+        (Without<Dead>, Clipping),
+    >,
+    bounds: Res<WorldBounds>,
+    mut commands: Commands,
+) {
+    let mut players: Vec<_> = query.iter_mut().collect();
+    // Sorting keeps the result independent of query iteration order.
+    players.sort_by_key(|(_, player, ..)| player.network_id);
+
+    let mut positions: Vec<Vec2> = players
+        .iter()
+        .map(|(.., transform)| transform.translation.xy())
+        .collect();
+    let velocities: Vec<Vec2> = players
+        .iter()
+        .map(|(_, _, _, _, movement, velocity, _)| movement.0 + velocity.0)
+        .collect();
+    let rooms: Vec<RoomId> = players.iter().map(|(_, _, room, ..)| **room).collect();
+    let radii: Vec<f32> = players
+        .iter()
+        .map(|(_, _, _, shape, ..)| shape.collision_radius())
+        .collect();
+    let mut impulses = vec![Vec2::ZERO; players.len()];
+
+    for iteration in 0..COLLISION_ITERATIONS {
+        // Corrections are gathered first and applied together, so no pair is favoured.
+        let mut corrections = vec![Vec2::ZERO; positions.len()];
+        for i in 0..positions.len() {
+            for j in (i + 1)..positions.len() {
+                if rooms[i] != rooms[j] {
+                    continue;
+                }
+                let offset = positions[j] - positions[i];
+                let overlap = radii[i] + radii[j] - offset.length();
+                if overlap <= 0.0 {
+                    continue;
+                }
+
+                let direction = offset.try_normalize().unwrap_or(Vec2::X);
+                corrections[i] -= direction * overlap / 2.0;
+                corrections[j] += direction * overlap / 2.0;
+
+                // Players weigh the same, so an approaching pair splits the impulse evenly.
+                // Only the first pass counts, later ones just settle the same contacts.
+                let approach = (velocities[i] - velocities[j]).dot(direction);
+                if iteration == 0 && approach > 0.0 {
+                    let impulse = direction * approach * (1.0 + KNOCKBACK_RESTITUTION) / 2.0;
+                    impulses[i] -= impulse;
+                    impulses[j] += impulse;
+                }
+            }
+        }
+
+        if corrections
+            .iter()
+            .all(|correction| *correction == Vec2::ZERO)
+        {
+            break;
+        }
+        for (position, correction) in positions.iter_mut().zip(corrections) {
+            *position = bounds.clamp(*position + correction);
+        }
+    }
+
+    for ((entity, _, _, _, _, velocity, transform), (position, impulse)) in
+        players.iter_mut().zip(positions.into_iter().zip(impulses))
+    {
+        if impulse != Vec2::ZERO {
+            velocity.0 += impulse;
+        }
+        let push = position - transform.translation.xy();
+        if push != Vec2::ZERO {
+            transform.translation = position.extend(transform.translation.z);
+            commands.server_trigger(ToClients {
+                mode: SendMode::Direct(ClientId::Client(*entity)),
+                message: CollisionHit { push },
+            });
+        }
+    }
+}
+
+fn spawn_ball(mut commands: Commands) {
+    commands.spawn((Ball::default(), RoomId::default()));
+}
+
+#[allow(clippy::type_complexity)]
+fn simulate_ball(
+    mut balls: Query<(&mut Ball, &RoomId, &mut Transform), Without<Player>>,
+    players: Query<
+        (&Transform, &RoomId, &PlayerShape, &MovementVelocity),
+        (With<Player>, Without<Dead>, Clipping),
+    >,
+    bounds: Res<WorldBounds>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_secs();
+    for (mut ball, ball_room, mut transform) in balls.iter_mut() {
+        let mut velocity = ball.velocity * BALL_FRICTION.powf(delta);
+        let mut position = transform.translation.xy() + velocity * delta;
+
+        // Players shove the ball out of their way and it never pushes back. It bounces off them,
+        // and those walking into it kick it along.
+        for (player, _, shape, movement) in
+            players.iter().filter(|(_, room, ..)| *room == ball_room)
+        {
+            let offset = position - player.translation.xy();
+            let overlap = shape.collision_radius() + BALL_RADIUS - offset.length();
+            if overlap <= 0.0 {
+                continue;
+            }
+            let normal = offset.try_normalize().unwrap_or(Vec2::X);
+            position += normal * overlap;
+
+            let along = velocity.dot(normal);
+            if along < 0.0 {
+                velocity -= normal * along * (1.0 + BALL_RESTITUTION);
+            }
+            let kick_speed = movement.0.dot(normal).max(0.0) * BALL_KICK;
+            let along = velocity.dot(normal);
+            if along < kick_speed {
+                velocity += normal * (kick_speed - along);
+            }
+        }
+
+        // Bounce on the ball's edge rather than its center.
+        let (min, max) = (bounds.min + BALL_RADIUS, bounds.max - BALL_RADIUS);
+        for axis in 0..2 {
+            if position[axis] < min[axis] {
+                position[axis] = min[axis];
+                velocity[axis] = velocity[axis].abs() * BALL_RESTITUTION;
+            } else if position[axis] > max[axis] {
+                position[axis] = max[axis];
+                velocity[axis] = -velocity[axis].abs() * BALL_RESTITUTION;
+            }
+        }
+        if velocity.length() < BALL_REST_SPEED {
+            velocity = Vec2::ZERO;
+        }
+
+        // Only actual changes get replicated.
+        ball.set_if_neq(Ball { velocity });
+        if transform.translation.xy() != position {
+            transform.translation = position.extend(transform.translation.z);
+        }
+    }
+}
+
+fn setup_server(
+    args: Res<Args>,
+    game_config: Res<GameConfig>,
+    channels: Res<RepliconChannels>,
+    budget: Res<NetworkBudget>,
+    mut server: ResMut<QuinnetServer>,
+    mut exit: MessageWriter<AppExit>,
+    mut commands: Commands,
+) {
+    let ips = match args.bind.map(BindMode::address) {
+        Some(Ok(ip)) => vec![ip],
+        None => args.ips.clone(),
+        Some(Err(e)) => {
+            error!("{e}");
+            commands.insert_resource(NetworkError(e));
+            exit.write(AppExit::error());
+            return;
+        }
+    };
+
+    match JoinTokens::from_args(&args) {
+        Ok(Some(tokens)) => {
+            info!("Clients need a join token");
+            commands.insert_resource(tokens);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("{e}");
+            commands.insert_resource(NetworkError(e));
+            exit.write(AppExit::error());
+            return;
+        }
+    }
+
+    if args.replay.is_some() {
+        info!("Replaying at {} ticks per second", game_config.tick_rate);
+        return;
+    }
+
+    let settings = EndpointSettings { ips };
+    let (ip, port) = match start_listening(&mut server, &settings, &args, &channels, &budget) {
+        Ok(bound) => bound,
+        Err(message) => {
+            error!("{message}");
+            commands.insert_resource(NetworkError(message));
+            exit.write(AppExit::error());
+            return;
+        }
+    };
+    commands.insert_resource(BoundPort(port));
+    commands.insert_resource(settings);
+
+    info!(
+        "Server listening on [{ip}]:{port} at {} ticks per second, replicating at {} Hz",
+        game_config.tick_rate, game_config.replication_rate
+    );
+}
+
+/// Starts the endpoint on the first of the configured addresses that binds, returning the
+/// address and port it listens on.
+///
+/// Quinnet serves a single endpoint, so the other addresses are only fallbacks. Every address that
+/// fails is reported, and this only fails once none of them bind.
+fn start_listening(
+    server: &mut QuinnetServer,
+    settings: &EndpointSettings,
+    args: &Args,
+    channels: &RepliconChannels,
+    budget: &NetworkBudget,
+) -> Result<(IpAddr, u16), String> {
+    let mut failures = Vec::new();
+    for (i, &ip) in settings.ips.iter().enumerate() {
+        let cert_mode = certificate_mode(args, ip)?;
+        match listen_on(server, ip, cert_mode, args, channels, budget) {
+            Ok(port) => return Ok((ip, port)),
+            Err(message) if i + 1 < settings.ips.len() => {
+                warn!("{message}, trying the next address");
+                failures.push(message);
+            }
+            Err(message) => failures.push(message),
+        }
+    }
+    match failures.len() {
+        0 => Err("No address to listen on".to_string()),
+        1 => Err(failures.remove(0)),
+        n => Err(format!(
+            "None of the {n} addresses could be bound: {}",
+            failures.join("; ")
+        )),
+    }
+}
+
+/// Starts the endpoint on `ip` at `--port`, or the first free port of `--port-range` after it,
+/// returning the port it listens on
+fn listen_on(
+    server: &mut QuinnetServer,
+    ip: IpAddr,
+    cert_mode: CertificateRetrievalMode,
+    args: &Args,
+    channels: &RepliconChannels,
+    budget: &NetworkBudget,
+) -> Result<u16, String> {
+    let port = args.port;
+    let last_port = port.saturating_add(args.port_range);
+    let mut candidate = port;
+    loop {
+        match server.start_endpoint(ServerEndpointConfiguration {
+            addr_config: EndpointAddrConfiguration::from_ip(ip, candidate),
+            cert_mode: cert_mode.clone(),
+            defaultables: ServerEndpointConfigurationDefaultables {
+                send_channels_cfg: budget.server_configs(channels),
+            },
+        }) {
+            Ok(_) => return Ok(candidate),
+            Err(EndpointStartError::IoError(e))
+                if e.kind() == ErrorKind::AddrInUse && candidate < last_port =>
+            {
+                warn!(
+                    "Port {candidate} is already in use, trying {}",
+                    candidate + 1
+                );
+                candidate += 1;
+            }
+            Err(e) => {
+                let message = match e {
+                    EndpointStartError::IoError(e)
+                        if e.kind() == ErrorKind::AddrInUse && port < last_port =>
+                    {
+                        format!("Ports {port} to {last_port} on [{ip}] are all in use")
+                    }
+                    EndpointStartError::IoError(e) if e.kind() == ErrorKind::AddrInUse => {
+                        format!(
+                            "Address [{ip}]:{port} is already in use, is another server running?"
+                        )
+                    }
+                    EndpointStartError::IoError(e) if e.kind() == ErrorKind::AddrNotAvailable => {
+                        format!("Address [{ip}]:{candidate} isn't available on this machine: {e}")
+                    }
+                    e => format!("Failed to start server on [{ip}]:{candidate}: {:?}", e),
+                };
+                return Err(message);
+            }
+        }
+    }
+}
+
+fn certificate_mode(args: &Args, ip: IpAddr) -> Result<CertificateRetrievalMode, String> {
+    let (Some(cert_file), Some(key_file)) = (&args.cert, &args.key) else {
+        let server_hostname = certificate_hostname(args, ip);
+        info!("Generating a self-signed certificate for {server_hostname}");
+        return Ok(CertificateRetrievalMode::GenerateSelfSigned { server_hostname });
+    };
+
+    // Quinnet only reports a generic I/O error, so check the files up front for a clearer message.
+    for path in [cert_file, key_file] {
+        File::open(path).map_err(|e| format!("Cannot read certificate file {path}: {e}"))?;
+    }
+
+    info!("Loading certificate from {cert_file} and key from {key_file}");
+    Ok(CertificateRetrievalMode::LoadFromFile {
+        cert_file: cert_file.clone(),
+        key_file: key_file.clone(),
+    })
+}
+
+fn certificate_hostname(args: &Args, ip: IpAddr) -> String {
+    if let Some(hostname) = &args.hostname {
+        return hostname.clone();
+    }
+    // Nobody connects to the wildcard address itself, so a certificate for it would never match.
+    if ip.is_unspecified() {
+        warn!(
+            "Bound to {}, issuing the certificate for localhost. Pass --hostname for remote clients",
+            ip
+        );
+        return Ipv6Addr::LOCALHOST.to_string();
+    }
+    ip.to_string()
+}
+
+fn disconnect_observer(mut exit_events: MessageReader<AppExit>, mut server: ResMut<QuinnetServer>) {
+    for _event in exit_events.read() {
+        info!("Shutting down server...");
+        if let Err(e) = server.stop_endpoint() {
+            warn!("Failed to stop server endpoint: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use bevy::prelude::*;
+use bevy_quinnet::server::QuinnetServer;
+use bevy_replicon::prelude::*;
+use shared::{
+    ChatMessage, ClientMovementIntent, DashIntent, FireProjectile, Player, SetPlayerName,
+    ToggleReady, Whisper,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Weight of the newest tick in [`ServerMetrics::average_tick`] is `1 / TICK_SMOOTHING`
+const TICK_SMOOTHING: u32 = 64;
+
+#[derive(Resource, Default, Debug, Clone)]
+/// Load figures for server operators, updated at the end of every tick
+pub struct ServerMetrics {
+    pub players: usize,
+    /// Moving average of the time spent running a tick, not counting the wait for the next one
+    pub average_tick: Duration,
+    /// Bytes sent over the network, including to clients that already left
+    pub bytes_sent: u64,
+    /// Bytes received over the network, including from clients that already left
+    pub bytes_received: u64,
+    pub uptime: Duration,
+    /// Events by type name from clients without a player, such as input sent before the player
+    /// was spawned or after it was removed. Anything here points at an ordering bug.
+    pub orphan_events: BTreeMap<&'static str, u64>,
+    /// Projectiles fired as newly spawned entities
+    pub projectiles_spawned: u64,
+    /// Projectiles fired by reusing a spent one from the pool
+    pub projectiles_reused: u64,
+}
+
+#[derive(Resource)]
+/// When the current tick started
+struct TickStart(Instant);
+
+#[derive(Resource, Default)]
+/// Transport byte counts last seen per client, to add only what's new to the totals
+struct ConnectionTotals(HashMap<u64, (u64, u64)>);
+
+#[derive(Resource)]
+/// Interval between metrics summaries in the log
+struct MetricsLogTimer(Timer);
+
+pub(crate) fn configure_metrics(app: &mut App, log_interval: Duration) {
+    app.init_resource::<ServerMetrics>();
+    app.init_resource::<ConnectionTotals>();
+    app.insert_resource(TickStart(Instant::now()));
+    app.insert_resource(MetricsLogTimer(Timer::new(
+        log_interval,
+        TimerMode::Repeating,
+    )));
+
+    // Every client event handled per player, new ones belong here too.
+    app.add_observer(count_orphan_events::<ClientMovementIntent>);
+    app.add_observer(count_orphan_events::<SetPlayerName>);
+    app.add_observer(count_orphan_events::<ChatMessage>);
+    app.add_observer(count_orphan_events::<Whisper>);
+    app.add_observer(count_orphan_events::<ToggleReady>);
+    app.add_observer(count_orphan_events::<FireProjectile>);
+    app.add_observer(count_orphan_events::<DashIntent>);
+
+    app.add_systems(First, start_tick);
+    app.add_systems(Update, log_metrics);
+    app.add_systems(Last, update_metrics);
+
+    #[cfg(feature = "dev")]
+    {
+        app.init_resource::<shared::PerfStats>();
+        app.add_systems(Last, record_perf_stats.before(update_metrics));
+        app.add_systems(Update, log_perf_stats.after(log_metrics));
+    }
+}
+
+fn start_tick(mut start: ResMut<TickStart>) {
+    start.0 = Instant::now();
+}
+
+fn update_metrics(
+    start: Res<TickStart>,
+    players: Query<(), With<Player>>,
+    server: Res<QuinnetServer>,
+    time: Res<Time<Real>>,
+    mut totals: ResMut<ConnectionTotals>,
+    mut metrics: ResMut<ServerMetrics>,
+) {
+    let tick = start.0.elapsed();
+    metrics.average_tick = if metrics.average_tick.is_zero() {
+        tick
+    } else {
+        metrics.average_tick + tick / TICK_SMOOTHING - metrics.average_tick / TICK_SMOOTHING
+    };
+    metrics.players = players.iter().count();
+    metrics.uptime = time.elapsed();
+
+    let Some(endpoint) = server.get_endpoint() else {
+        return;
+    };
+    let clients = endpoint.clients();
+    totals.0.retain(|client_id, _| clients.contains(client_id));
+    for client_id in clients {
+        let Some(stats) = endpoint.get_connection_stats(client_id) else {
+            continue;
+        };
+        let (sent, received) = totals.0.entry(client_id).or_default();
+        metrics.bytes_sent += stats.udp_tx.bytes.saturating_sub(*sent);
+        metrics.bytes_received += stats.udp_rx.bytes.saturating_sub(*received);
+        (*sent, *received) = (stats.udp_tx.bytes, stats.udp_rx.bytes);
+    }
+}
+
+/// Counts `E` from clients that have no player, which every handler of `E` silently ignores
+fn count_orphan_events<E: Send + Sync + 'static>(
+    message: On<FromClient<E>>,
+    players: Query<(), With<Player>>,
+    mut metrics: ResMut<ServerMetrics>,
+) {
+    let Some(entity) = message.client_id.entity() else {
+        return;
+    };
+    if players.contains(entity) {
+        return;
+    }
+
+    let name = std::any::type_name::<E>()
+        .rsplit("::")
+        .next()
+        .unwrap_or_default();
+    debug!(
+        "Received {name} from {} without a player",
+        message.client_id
+    );
+    *metrics.orphan_events.entry(name).or_default() += 1;
+}
+
+fn log_metrics(
+    mut timer: ResMut<MetricsLogTimer>,
+    time: Res<Time<Real>>,
+    metrics: Res<ServerMetrics>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    info!(
+        "{} players | tick {:.2} ms | sent {} KiB | received {} KiB | up {}s",
+        metrics.players,
+        metrics.average_tick.as_secs_f64() * 1000.0,
+        metrics.bytes_sent / 1024,
+        metrics.bytes_received / 1024,
+        metrics.uptime.as_secs()
+    );
+    if !metrics.orphan_events.is_empty() {
+        info!(
+            "Events from clients without a player: {:?}",
+            metrics.orphan_events
+        );
+    }
+    if metrics.projectiles_spawned > 0 {
+        info!(
+            "Projectiles fired: {} spawned, {} reused from the pool",
+            metrics.projectiles_spawned, metrics.projectiles_reused
+        );
+    }
+}
+
+#[cfg(feature = "dev")]
+fn record_perf_stats(start: Res<TickStart>, mut perf: ResMut<shared::PerfStats>) {
+    perf.record(start.0.elapsed());
+}
+
+#[cfg(feature = "dev")]
+fn log_perf_stats(timer: Res<MetricsLogTimer>, perf: Res<shared::PerfStats>) {
+    if !timer.0.just_finished() {
+        return;
+    }
+
+    info!(
+        "tick {:.2} ms (min {:.2}, max {:.2})",
+        perf.average.as_secs_f64() * 1000.0,
+        perf.min.as_secs_f64() * 1000.0,
+        perf.max.as_secs_f64() * 1000.0
+    );
+}
+
+#[cfg(feature = "metrics-http")]
+pub(crate) use http::serve_metrics;
+
+#[cfg(feature = "metrics-http")]
+mod http {
+    use super::ServerMetrics;
+    use bevy::prelude::*;
+    use std::io::{Read, Write};
+    use std::net::{Ipv6Addr, TcpListener};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Resource)]
+    /// Latest metrics in Prometheus text format, shared with the HTTP thread
+    struct MetricsPage(Arc<Mutex<String>>);
+
+    /// Serves [`ServerMetrics`] for scraping at `http://[::]:<port>/`, any path works
+    pub(crate) fn serve_metrics(app: &mut App, port: u16) {
+        let listener = match TcpListener::bind((Ipv6Addr::UNSPECIFIED, port)) {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!("Failed to serve metrics on port {}: {:?}", port, e);
+                return;
+            }
+        };
+        info!("Serving metrics on port {port}");
+
+        let page = Arc::new(Mutex::new(String::new()));
+        let shared_page = page.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                // The request itself doesn't matter, read it so the client sees a clean close.
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+
+                let body = shared_page
+                    .lock()
+                    .map(|page| page.clone())
+                    .unwrap_or_default();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                if let Err(e) = stream.write_all(response.as_bytes()) {
+                    warn!("Failed to send metrics: {:?}", e);
+                }
+            }
+        });
+
+        app.insert_resource(MetricsPage(page));
+        app.add_systems(Last, publish_metrics.after(super::update_metrics));
+    }
+
+    fn publish_metrics(metrics: Res<ServerMetrics>, page: Res<MetricsPage>) {
+        let Ok(mut page) = page.0.lock() else {
+            return;
+        };
+        *page = format!(
+            "# TYPE game_players gauge\n\
+             game_players {}\n\
+             # TYPE game_tick_seconds gauge\n\
+             game_tick_seconds {}\n\
+             # TYPE game_sent_bytes_total counter\n\
+             game_sent_bytes_total {}\n\
+             # TYPE game_received_bytes_total counter\n\
+             game_received_bytes_total {}\n\
+             # TYPE game_uptime_seconds gauge\n\
+             game_uptime_seconds {}\n\
+             # TYPE game_projectiles_fired_total counter\n\
+             game_projectiles_fired_total{{entity=\"spawned\"}} {}\n\
+             game_projectiles_fired_total{{entity=\"reused\"}} {}\n\
+             # TYPE game_orphan_events_total counter\n",
+            metrics.players,
+            metrics.average_tick.as_secs_f64(),
+            metrics.bytes_sent,
+            metrics.bytes_received,
+            metrics.uptime.as_secs_f64(),
+            metrics.projectiles_spawned,
+            metrics.projectiles_reused
+        );
+        for (event, count) in &metrics.orphan_events {
+            page.push_str(&format!(
+                "game_orphan_events_total{{event=\"{event}\"}} {count}\n"
+            ));
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{Dead, GamePhase, MovementCheck, MovementInput, MovementVelocity, Paused};
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use shared::{MovementConfig, NOCLIP_SPEED_FACTOR, Noclip, Player, SetNoclip, WorldBounds};
+
+#[derive(Resource)]
+/// Token that makes a client joining with it an admin
+pub(crate) struct AdminToken(pub(crate) String);
+
+#[derive(Component)]
+/// Marker for clients allowed to use admin-only tools such as noclip
+pub(crate) struct AdminFlag;
+
+pub(crate) fn configure_noclip(app: &mut App, admin_token: Option<String>) {
+    if let Some(token) = admin_token {
+        app.insert_resource(AdminToken(token));
+    }
+    app.add_observer(on_set_noclip);
+    app.add_systems(
+        FixedUpdate,
+        move_noclip_players
+            .before(crate::record_positions)
+            .run_if(in_state(GamePhase::Playing).and(not(resource_exists::<Paused>))),
+    );
+}
+
+fn on_set_noclip(
+    noclip: On<FromClient<SetNoclip>>,
+    mut players: Query<(&Player, Has<AdminFlag>, &mut Transform, &mut MovementCheck)>,
+    bounds: Res<WorldBounds>,
+    mut commands: Commands,
+) {
+    let Some(entity) = noclip.client_id.entity() else {
+        return;
+    };
+    let Ok((player, is_admin, mut transform, mut check)) = players.get_mut(entity) else {
+        return;
+    };
+    if !is_admin {
+        warn!(
+            "Ignoring noclip request from player {}, who isn't an admin",
+            player.network_id
+        );
+        return;
+    }
+
+    info!(
+        "Turning noclip {} for player {}",
+        if noclip.0 { "on" } else { "off" },
+        player.network_id
+    );
+    if noclip.0 {
+        commands.entity(entity).insert(Noclip);
+        return;
+    }
+    commands.entity(entity).remove::<Noclip>();
+    // Back inside the bounds right away, so the next tick's movement check doesn't flag the jump.
+    let position = bounds.clamp(transform.translation.xy());
+    transform.translation = position.extend(transform.translation.z);
+    check.last_position = position;
+}
+
+/// Moves noclip players, which the regular movement, validation and collisions leave alone
+#[allow(clippy::type_complexity)]
+fn move_noclip_players(
+    mut query: Query<
+        (&MovementInput, &mut MovementVelocity, &mut Transform),
+        (With<Noclip>, Without<Dead>),
+    >,
+    config: Res<MovementConfig>,
+    time: Res<Time>,
+) {
+    for (input, mut velocity, mut transform) in query.iter_mut() {
+        velocity.0 = input.0 * config.speed * NOCLIP_SPEED_FACTOR;
+        transform.translation += (velocity.0 * time.delta_secs()).extend(0.0);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::metrics::ServerMetrics;
+use crate::{ApplyDamage, Clipping, Dead, GamePhase, Paused};
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use bevy_replicon::server::server_tick::ServerTick;
+use shared::{
+    FireProjectile, PROJECTILE_RADIUS, PROJECTILE_SPEED, Player, PlayerShape, Projectile, RoomId,
+    WorldBounds,
+};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Replication ticks a spent projectile waits before it's reused, so clients get its despawn
+/// before it's replicated as a new projectile
+const POOL_COOLDOWN_TICKS: u32 = 2;
+
+#[derive(Resource)]
+/// How often players may fire and how much a hit hurts
+pub(crate) struct ProjectileConfig {
+    pub(crate) cooldown: Duration,
+    pub(crate) damage: f32,
+    /// Most spent projectiles kept for reuse, the rest are despawned
+    pub(crate) pool_size: usize,
+}
+
+#[derive(Component)]
+/// Time since startup at which a player may fire again
+struct NextShot(Duration);
+
+#[derive(Component)]
+/// Player a projectile was fired by, which it flies through
+struct Shooter(Entity);
+
+#[derive(Component)]
+/// Marks a spent projectile waiting in the [`ProjectilePool`], neither simulated nor replicated
+struct Pooled;
+
+#[derive(Resource, Default)]
+/// Spent projectiles kept to be fired again instead of spawning new entities, oldest first.
+///
+/// Removing `Replicated` despawns a projectile on clients, and inserting it again replicates it
+/// like a new entity. Reusing the server entity saves allocating one and a new replicon mapping
+/// for every shot.
+struct ProjectilePool {
+    /// Each spent projectile with the tick it was put back in
+    idle: VecDeque<(Entity, RepliconTick)>,
+}
+
+impl ProjectilePool {
+    /// Takes the oldest projectile whose despawn has gone out to clients by `tick`
+    fn take(&mut self, tick: RepliconTick) -> Option<Entity> {
+        let &(entity, released) = self.idle.front()?;
+        if tick - released < POOL_COOLDOWN_TICKS {
+            return None;
+        }
+        self.idle.pop_front();
+        Some(entity)
+    }
+
+    /// Stops replicating a spent projectile and keeps it for reuse, or despawns it once the pool
+    /// is full
+    fn release(
+        &mut self,
+        entity: Entity,
+        tick: RepliconTick,
+        capacity: usize,
+        commands: &mut Commands,
+    ) {
+        if self.idle.len() >= capacity {
+            commands.entity(entity).despawn();
+            return;
+        }
+        commands
+            .entity(entity)
+            .remove::<Replicated>()
+            .insert(Pooled);
+        self.idle.push_back((entity, tick));
+    }
+}
+
+pub(crate) fn configure_projectiles(app: &mut App, config: ProjectileConfig) {
+    app.insert_resource(config);
+    app.init_resource::<ProjectilePool>();
+    app.add_observer(on_fire_projectile);
+    app.add_observer(crate::record_activity::<FireProjectile>);
+    app.add_systems(
+        FixedUpdate,
+        simulate_projectiles
+            .after(crate::resolve_collisions)
+            .run_if(in_state(GamePhase::Playing).and(not(resource_exists::<Paused>))),
+    );
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn on_fire_projectile(
+    fire: On<FromClient<FireProjectile>>,
+    mut players: Query<
+        (&Transform, &PlayerShape, &RoomId, Option<&mut NextShot>),
+        (With<Player>, Without<Dead>),
+    >,
+    phase: Res<State<GamePhase>>,
+    paused: Option<Res<Paused>>,
+    config: Res<ProjectileConfig>,
+    mut pool: ResMut<ProjectilePool>,
+    tick: Res<ServerTick>,
+    mut metrics: ResMut<ServerMetrics>,
+    time: Res<Time<Real>>,
+    mut commands: Commands,
+) {
+    let Some(entity) = fire.client_id.entity() else {
+        return;
+    };
+    if *phase.get() != GamePhase::Playing || paused.is_some() {
+        return;
+    }
+    let Ok((transform, shape, room, next_shot)) = players.get_mut(entity) else {
+        return;
+    };
+    // Also refuses a zero or non-finite direction.
+    let Some(direction) = fire.direction.try_normalize() else {
+        return;
+    };
+
+    let now = time.elapsed();
+    match next_shot {
+        Some(next_shot) if now < next_shot.0 => return,
+        Some(mut next_shot) => next_shot.0 = now + config.cooldown,
+        None => {
+            commands
+                .entity(entity)
+                .insert(NextShot(now + config.cooldown));
+        }
+    }
+
+    // Spawned just clear of the shooter, which it ignores anyway, so it starts out visible.
+    let origin =
+        transform.translation.xy() + direction * (shape.collision_radius() + PROJECTILE_RADIUS);
+    let projectile = (
+        Projectile {
+            velocity: direction * PROJECTILE_SPEED,
+        },
+        Shooter(entity),
+        *room,
+        Transform::from_translation(origin.extend(transform.translation.z)),
+    );
+    match pool.take(**tick) {
+        Some(reused) => {
+            metrics.projectiles_reused += 1;
+            commands
+                .entity(reused)
+                .remove::<Pooled>()
+                .insert((projectile, Replicated));
+        }
+        None => {
+            metrics.projectiles_spawned += 1;
+            commands.spawn(projectile);
+        }
+    }
+}
+
+/// Moves projectiles, despawning those that left the arena or hit a player in their room
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn simulate_projectiles(
+    mut projectiles: Query<
+        (Entity, &Projectile, &Shooter, &RoomId, &mut Transform),
+        (Without<Player>, Without<Pooled>),
+    >,
+    players: Query<
+        (Entity, &Transform, &RoomId, &PlayerShape),
+        (With<Player>, Without<Dead>, Clipping),
+    >,
+    config: Res<ProjectileConfig>,
+    mut pool: ResMut<ProjectilePool>,
+    tick: Res<ServerTick>,
+    bounds: Res<WorldBounds>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    for (entity, projectile, shooter, room, mut transform) in projectiles.iter_mut() {
+        let position = transform.translation.xy() + projectile.velocity * time.delta_secs();
+        if bounds.clamp(position) != position {
+            pool.release(entity, **tick, config.pool_size, &mut commands);
+            continue;
+        }
+
+        let hit = players
+            .iter()
+            .find(|(player, player_transform, player_room, shape)| {
+                *player != shooter.0
+                    && *player_room == room
+                    && position.distance(player_transform.translation.xy())
+                        < shape.collision_radius() + PROJECTILE_RADIUS
+            });
+        if let Some((target, ..)) = hit {
+            commands.trigger(ApplyDamage {
+                target,
+                amount: config.damage,
+            });
+            pool.release(entity, **tick, config.pool_size, &mut commands);
+            continue;
+        }
+
+        transform.translation = position.extend(transform.translation.z);
+    }
+}
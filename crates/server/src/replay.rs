@@ -0,0 +1,490 @@
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_replicon::prelude::*;
+use bevy_replicon::shared::backend::connected_client::{NetworkId, NetworkIdMap};
+use shared::{
+    ClientMovementIntent, DashIntent, FireProjectile, JoinRoom, Player, RoomId, ToggleReady,
+};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::{JoinRequest, Spectator};
+
+/// Identifies a replay file
+const MAGIC: &[u8; 4] = b"QTRP";
+
+/// Replay format version, bumped whenever the layout of a record changes
+const FORMAT_VERSION: u16 = 4;
+
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Fixed simulation steps run so far, the clock replay records are timed by
+pub struct SimulationTick(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Client input that affects the simulation
+enum ReplayEvent {
+    /// `room` is the room the client asked for before joining, if any
+    Join {
+        spectator: bool,
+        room: u32,
+    },
+    Leave,
+    Movement {
+        seq: u32,
+        direction: Vec2,
+    },
+    ToggleReady,
+    Fire {
+        direction: Vec2,
+    },
+    Dash {
+        direction: Vec2,
+    },
+    Room {
+        room_id: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A [`ReplayEvent`] from one client, applied before the step after `tick`
+struct ReplayRecord {
+    tick: u64,
+    network_id: u64,
+    event: ReplayEvent,
+}
+
+impl ReplayRecord {
+    /// Writes the record as a kind byte, the tick, the network id and the event fields, all
+    /// little-endian
+    fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let kind: u8 = match self.event {
+            ReplayEvent::Join { .. } => 0,
+            ReplayEvent::Leave => 1,
+            ReplayEvent::Movement { .. } => 2,
+            ReplayEvent::ToggleReady => 3,
+            ReplayEvent::Fire { .. } => 4,
+            ReplayEvent::Dash { .. } => 5,
+            ReplayEvent::Room { .. } => 6,
+        };
+        writer.write_all(&[kind])?;
+        writer.write_all(&self.tick.to_le_bytes())?;
+        writer.write_all(&self.network_id.to_le_bytes())?;
+        match self.event {
+            ReplayEvent::Join { spectator, room } => {
+                writer.write_all(&[spectator as u8])?;
+                writer.write_all(&room.to_le_bytes())
+            }
+            ReplayEvent::Movement { seq, direction } => {
+                writer.write_all(&seq.to_le_bytes())?;
+                write_vec2(writer, direction)
+            }
+            ReplayEvent::Fire { direction } | ReplayEvent::Dash { direction } => {
+                write_vec2(writer, direction)
+            }
+            ReplayEvent::Room { room_id } => writer.write_all(&room_id.to_le_bytes()),
+            ReplayEvent::Leave | ReplayEvent::ToggleReady => Ok(()),
+        }
+    }
+
+    /// Reads the next record, `None` at the end of the file
+    fn read_from(reader: &mut impl Read) -> io::Result<Option<Self>> {
+        let mut kind = [0; 1];
+        match reader.read_exact(&mut kind) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let tick = u64::from_le_bytes(read_array(reader)?);
+        let network_id = u64::from_le_bytes(read_array(reader)?);
+        let event = match kind[0] {
+            0 => ReplayEvent::Join {
+                spectator: read_array::<1>(reader)?[0] != 0,
+                room: u32::from_le_bytes(read_array(reader)?),
+            },
+            1 => ReplayEvent::Leave,
+            2 => ReplayEvent::Movement {
+                seq: u32::from_le_bytes(read_array(reader)?),
+                direction: read_vec2(reader)?,
+            },
+            3 => ReplayEvent::ToggleReady,
+            4 => ReplayEvent::Fire {
+                direction: read_vec2(reader)?,
+            },
+            5 => ReplayEvent::Dash {
+                direction: read_vec2(reader)?,
+            },
+            6 => ReplayEvent::Room {
+                room_id: u32::from_le_bytes(read_array(reader)?),
+            },
+            kind => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown record kind {kind}"),
+                ));
+            }
+        };
+        Ok(Some(Self {
+            tick,
+            network_id,
+            event,
+        }))
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn write_vec2(writer: &mut impl Write, value: Vec2) -> io::Result<()> {
+    writer.write_all(&value.x.to_le_bytes())?;
+    writer.write_all(&value.y.to_le_bytes())
+}
+
+fn read_vec2(reader: &mut impl Read) -> io::Result<Vec2> {
+    Ok(Vec2::new(
+        f32::from_le_bytes(read_array(reader)?),
+        f32::from_le_bytes(read_array(reader)?),
+    ))
+}
+
+#[derive(Resource)]
+/// Replay file the client input of this session is written to
+struct ReplayRecorder(BufWriter<File>);
+
+impl ReplayRecorder {
+    /// Creates the file and writes the header: magic, format version and tick rate
+    fn create(path: &Path, tick_rate: f64) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&tick_rate.to_le_bytes())?;
+        Ok(Self(writer))
+    }
+
+    fn record(&mut self, record: ReplayRecord) {
+        if let Err(e) = record.write_to(&mut self.0) {
+            warn!("Failed to record {:?}: {:?}", record, e);
+        }
+    }
+}
+
+#[derive(Resource)]
+/// Records of a loaded replay that haven't been applied yet, in file order
+struct ReplayPlayback {
+    records: std::vec::IntoIter<ReplayRecord>,
+    next: Option<ReplayRecord>,
+    /// Records of the current tick other than joins, applied once the joins went through
+    due: Vec<ReplayRecord>,
+}
+
+/// Loads every record of a replay file, returning them with the tick rate they were recorded at
+fn load_replay(path: &Path) -> io::Result<(f64, Vec<ReplayRecord>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if &read_array::<4>(&mut reader)? != MAGIC {
+        return Err(io::Error::new(ErrorKind::InvalidData, "not a replay file"));
+    }
+    let version = u16::from_le_bytes(read_array(&mut reader)?);
+    if version != FORMAT_VERSION {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("format version {version}, expected {FORMAT_VERSION}"),
+        ));
+    }
+    let tick_rate = f64::from_le_bytes(read_array(&mut reader)?);
+
+    let mut records = Vec::new();
+    while let Some(record) = ReplayRecord::read_from(&mut reader)? {
+        records.push(record);
+    }
+    Ok((tick_rate, records))
+}
+
+pub(crate) fn configure_simulation_tick(app: &mut App) {
+    app.init_resource::<SimulationTick>();
+    app.add_systems(FixedLast, advance_simulation_tick);
+}
+
+fn advance_simulation_tick(mut tick: ResMut<SimulationTick>) {
+    tick.0 += 1;
+}
+
+/// Writes every join, leave, movement intent, ready toggle, shot, dash and room change to `path`
+/// as they are received
+pub(crate) fn record_replay(app: &mut App, path: &Path, tick_rate: f64) {
+    let recorder = match ReplayRecorder::create(path, tick_rate) {
+        Ok(recorder) => recorder,
+        Err(e) => {
+            warn!("Failed to record replay to {}: {:?}", path.display(), e);
+            return;
+        }
+    };
+    info!("Recording replay to {}", path.display());
+
+    app.insert_resource(recorder);
+    app.add_systems(Last, flush_replay);
+    app.add_observer(record_join);
+    app.add_observer(record_leave);
+    app.add_observer(record_movement);
+    app.add_observer(record_toggle_ready);
+    app.add_observer(record_fire);
+    app.add_observer(record_dash);
+    app.add_observer(record_room);
+}
+
+/// Records clients as `read_connected` lets them in, refused ones never affect the simulation
+fn record_join(
+    add: On<Add, (Player, Spectator)>,
+    clients: Query<(&NetworkId, Has<Spectator>, Option<&RoomId>)>,
+    tick: Res<SimulationTick>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    let Ok((network_id, spectator, room)) = clients.get(add.entity) else {
+        return;
+    };
+    recorder.record(ReplayRecord {
+        tick: tick.0,
+        network_id: network_id.get(),
+        event: ReplayEvent::Join {
+            spectator,
+            room: room.map_or(0, |room| room.0),
+        },
+    });
+}
+
+fn record_leave(
+    remove: On<Remove, AuthorizedClient>,
+    clients: Query<&NetworkId>,
+    tick: Res<SimulationTick>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    let Ok(network_id) = clients.get(remove.entity) else {
+        return;
+    };
+    recorder.record(ReplayRecord {
+        tick: tick.0,
+        network_id: network_id.get(),
+        event: ReplayEvent::Leave,
+    });
+}
+
+fn record_movement(
+    intent: On<FromClient<ClientMovementIntent>>,
+    clients: Query<&NetworkId>,
+    tick: Res<SimulationTick>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    let Some(Ok(network_id)) = intent.client_id.entity().map(|entity| clients.get(entity)) else {
+        return;
+    };
+    recorder.record(ReplayRecord {
+        tick: tick.0,
+        network_id: network_id.get(),
+        event: ReplayEvent::Movement {
+            seq: intent.seq,
+            direction: intent.direction,
+        },
+    });
+}
+
+fn record_toggle_ready(
+    toggle: On<FromClient<ToggleReady>>,
+    clients: Query<&NetworkId>,
+    tick: Res<SimulationTick>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    let Some(Ok(network_id)) = toggle.client_id.entity().map(|entity| clients.get(entity)) else {
+        return;
+    };
+    recorder.record(ReplayRecord {
+        tick: tick.0,
+        network_id: network_id.get(),
+        event: ReplayEvent::ToggleReady,
+    });
+}
+
+fn record_fire(
+    fire: On<FromClient<FireProjectile>>,
+    clients: Query<&NetworkId>,
+    tick: Res<SimulationTick>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    let Some(Ok(network_id)) = fire.client_id.entity().map(|entity| clients.get(entity)) else {
+        return;
+    };
+    recorder.record(ReplayRecord {
+        tick: tick.0,
+        network_id: network_id.get(),
+        event: ReplayEvent::Fire {
+            direction: fire.direction,
+        },
+    });
+}
+
+fn record_dash(
+    dash: On<FromClient<DashIntent>>,
+    clients: Query<&NetworkId>,
+    tick: Res<SimulationTick>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    let Some(Ok(network_id)) = dash.client_id.entity().map(|entity| clients.get(entity)) else {
+        return;
+    };
+    recorder.record(ReplayRecord {
+        tick: tick.0,
+        network_id: network_id.get(),
+        event: ReplayEvent::Dash {
+            direction: dash.direction,
+        },
+    });
+}
+
+/// Records room changes of joined clients, earlier ones are part of their join
+#[allow(clippy::type_complexity)]
+fn record_room(
+    insert: On<Insert, RoomId>,
+    clients: Query<(&NetworkId, &RoomId), Or<(With<Player>, With<Spectator>)>>,
+    tick: Res<SimulationTick>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    let Ok((network_id, room)) = clients.get(insert.entity) else {
+        return;
+    };
+    recorder.record(ReplayRecord {
+        tick: tick.0,
+        network_id: network_id.get(),
+        event: ReplayEvent::Room { room_id: room.0 },
+    });
+}
+
+fn flush_replay(mut recorder: ResMut<ReplayRecorder>) {
+    if let Err(e) = recorder.0.flush() {
+        warn!("Failed to flush replay: {:?}", e);
+    }
+}
+
+/// Feeds a recorded session to the server in place of real clients, then exits.
+///
+/// Time advances by exactly one fixed step per update, so every record lands between the same
+/// two simulation steps it was received between, and the run doesn't wait on the wall clock.
+pub(crate) fn play_replay(app: &mut App, path: &Path, tick_rate: f64) {
+    let (recorded_rate, records) = match load_replay(path) {
+        Ok(replay) => replay,
+        Err(e) => {
+            error!("Failed to load replay {}: {:?}", path.display(), e);
+            app.world_mut().write_message(AppExit::error());
+            return;
+        }
+    };
+    if recorded_rate != tick_rate {
+        warn!(
+            "Replay was recorded at {} ticks per second but the server runs at {}, it will diverge",
+            recorded_rate, tick_rate
+        );
+    }
+    info!(
+        "Replaying {} records from {}",
+        records.len(),
+        path.display()
+    );
+
+    let step = app.world().resource::<Time<Fixed>>().timestep();
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(step));
+    let mut records = records.into_iter();
+    app.insert_resource(ReplayPlayback {
+        next: records.next(),
+        records,
+        due: Vec::new(),
+    });
+    // A client's input can be recorded in the same tick as its join, but it only ever arrived
+    // after `read_connected` let the client in.
+    app.add_systems(
+        Update,
+        (
+            apply_replay_joins.before(crate::read_connected),
+            apply_replay_input.after(crate::read_connected),
+        ),
+    );
+}
+
+fn apply_replay_joins(
+    mut playback: ResMut<ReplayPlayback>,
+    tick: Res<SimulationTick>,
+    mut commands: Commands,
+) {
+    while let Some(record) = playback.next.take_if(|record| record.tick <= tick.0) {
+        playback.next = playback.records.next();
+
+        if let ReplayEvent::Join { spectator, room } = record.event {
+            commands.spawn((
+                NetworkId::new(record.network_id),
+                AuthorizedClient,
+                JoinRequest { spectator },
+                RoomId(room),
+            ));
+        } else {
+            playback.due.push(record);
+        }
+    }
+}
+
+fn apply_replay_input(
+    mut playback: ResMut<ReplayPlayback>,
+    tick: Res<SimulationTick>,
+    clients: Res<NetworkIdMap>,
+    mut exit: MessageWriter<AppExit>,
+    mut commands: Commands,
+) {
+    for record in playback.due.drain(..) {
+        let client = clients.get(&NetworkId::new(record.network_id)).copied();
+        match (record.event, client) {
+            (ReplayEvent::Join { .. }, _) => unreachable!("joins are applied before input"),
+            (ReplayEvent::Leave, Some(client)) => {
+                commands.entity(client).despawn();
+            }
+            (ReplayEvent::Movement { seq, direction }, Some(client)) => {
+                commands.trigger(FromClient {
+                    client_id: ClientId::Client(client),
+                    message: ClientMovementIntent { seq, direction },
+                });
+            }
+            (ReplayEvent::ToggleReady, Some(client)) => {
+                commands.trigger(FromClient {
+                    client_id: ClientId::Client(client),
+                    message: ToggleReady,
+                });
+            }
+            (ReplayEvent::Fire { direction }, Some(client)) => {
+                commands.trigger(FromClient {
+                    client_id: ClientId::Client(client),
+                    message: FireProjectile { direction },
+                });
+            }
+            (ReplayEvent::Dash { direction }, Some(client)) => {
+                commands.trigger(FromClient {
+                    client_id: ClientId::Client(client),
+                    message: DashIntent { direction },
+                });
+            }
+            (ReplayEvent::Room { room_id }, Some(client)) => {
+                commands.trigger(FromClient {
+                    client_id: ClientId::Client(client),
+                    message: JoinRoom { room_id },
+                });
+            }
+            (event, None) => {
+                warn!(
+                    "Skipping {:?} from client {} that isn't connected",
+                    event, record.network_id
+                );
+            }
+        }
+    }
+
+    if playback.next.is_none() {
+        info!("Replay finished at tick {}", tick.0);
+        exit.write(AppExit::Success);
+    }
+}
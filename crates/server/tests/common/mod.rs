@@ -0,0 +1,145 @@
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use clap::Parser;
+use server::GamePhase;
+use shared::{LocalPlayer, ToggleReady};
+use std::net::{Ipv6Addr, UdpSocket};
+use std::thread::{JoinHandle, sleep, spawn};
+use std::time::{Duration, Instant};
+
+/// How long a test waits for a condition before failing
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause between frames so the async networking tasks can make progress
+const FRAME_DELAY: Duration = Duration::from_millis(5);
+
+/// Returns a loopback port that was free a moment ago
+pub fn free_port() -> u16 {
+    UdpSocket::bind((Ipv6Addr::LOCALHOST, 0))
+        .and_then(|socket| socket.local_addr())
+        .expect("no free UDP port on loopback")
+        .port()
+}
+
+/// Builds a server listening on `port` with extra command-line arguments
+pub fn server_app(port: u16, extra_args: &[&str]) -> App {
+    let port = port.to_string();
+    let args = ["server", "--port", &port]
+        .into_iter()
+        .chain(extra_args.iter().copied());
+    ready(server::build_server_app(server::Args::parse_from(args)))
+}
+
+/// Builds a headless client connecting to `port` with extra command-line arguments
+pub fn client_app(port: u16, extra_args: &[&str]) -> App {
+    let port = port.to_string();
+    let args = ["client", "--port", &port, "--insecure"]
+        .into_iter()
+        .chain(extra_args.iter().copied());
+    ready(client::build_headless_client_app(client::Args::parse_from(
+        args,
+    )))
+}
+
+/// Builds a client running `--diagnose` against `port` for `secs` seconds
+pub fn diagnose_app(port: u16, secs: f32) -> App {
+    let port = port.to_string();
+    let secs = secs.to_string();
+    let args = [
+        "client",
+        "--port",
+        &port,
+        "--insecure",
+        "--diagnose",
+        "--diagnose-secs",
+        &secs,
+        "--connect-timeout",
+        "1",
+    ];
+    ready(client::build_diagnose_app(client::Args::parse_from(args)))
+}
+
+/// Starts `count` client `--bots` against `port` on their own threads for `secs` seconds
+pub fn run_bots(port: u16, count: usize, secs: f32) -> JoinHandle<AppExit> {
+    let port = port.to_string();
+    let count = count.to_string();
+    let secs = secs.to_string();
+    let args = client::Args::parse_from([
+        "client",
+        "--port",
+        &port,
+        "--insecure",
+        "--bots",
+        &count,
+        "--bot-secs",
+        &secs,
+    ]);
+    spawn(move || client::run_bots(args))
+}
+
+/// `App::run` normally finishes the plugins, which manually stepped apps have to do themselves
+fn ready(mut app: App) -> App {
+    app.finish();
+    app.cleanup();
+    app
+}
+
+/// A server and its clients, all running in-process on loopback
+pub struct Harness {
+    pub server: App,
+    pub clients: Vec<App>,
+}
+
+impl Harness {
+    pub fn new(client_count: usize) -> Self {
+        let port = free_port();
+        let mut server = server_app(port, &[]);
+        // Start the endpoint before any client tries to connect.
+        server.update();
+
+        let clients = (0..client_count).map(|_| client_app(port, &[])).collect();
+        Self { server, clients }
+    }
+
+    /// Steps the server and every client once
+    pub fn update(&mut self) {
+        self.server.update();
+        for client in &mut self.clients {
+            client.update();
+        }
+        sleep(FRAME_DELAY);
+    }
+
+    /// Steps all apps until `condition` holds, panicking after [`TIMEOUT`]
+    pub fn update_until(&mut self, what: &str, mut condition: impl FnMut(&mut Self) -> bool) {
+        let start = Instant::now();
+        while !condition(self) {
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting for {what}");
+            self.update();
+        }
+    }
+
+    /// Waits for every client's player, readies them all and waits for the game to start
+    pub fn start_game(&mut self) {
+        self.update_until("every local player", |harness| {
+            harness
+                .clients
+                .iter_mut()
+                .all(|client| count::<With<LocalPlayer>>(client) == 1)
+        });
+        for client in &mut self.clients {
+            client.world_mut().client_trigger(ToggleReady);
+        }
+        self.update_until("the game to start", |harness| {
+            *harness.server.world().resource::<State<GamePhase>>().get() == GamePhase::Playing
+        });
+    }
+}
+
+/// Counts the entities matching `F` in `app`
+pub fn count<F: bevy::ecs::query::QueryFilter>(app: &mut App) -> usize {
+    app.world_mut()
+        .query_filtered::<(), F>()
+        .iter(app.world())
+        .count()
+}
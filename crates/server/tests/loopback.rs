@@ -0,0 +1,838 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_enhanced_input::prelude::*;
+use bevy_quinnet::client::QuinnetClient;
+use bevy_replicon::client::confirm_history::EntityReplicated;
+use bevy_replicon::prelude::*;
+use clap::Parser;
+use client::{ConnectionQuality, InputButton, KeyBindings};
+use common::{Harness, client_app, count, diagnose_app, free_port, run_bots, server_app};
+use server::{BoundPort, ServerMetrics};
+use shared::{
+    ClientMovementIntent, DashCooldown, DashIntent, FireProjectile, Health, JoinRoom, LocalPlayer,
+    MovementConfig, NetworkError, Player, PlayerName, Projectile, ToggleReady, Velocity, Whisper,
+    WhisperDelivery,
+};
+use std::net::{Ipv6Addr, UdpSocket};
+
+#[test]
+fn client_sees_its_replicated_player() {
+    let mut harness = Harness::new(1);
+
+    harness.update_until("the local player", |harness| {
+        count::<With<LocalPlayer>>(&mut harness.clients[0]) == 1
+    });
+
+    assert_eq!(count::<With<Player>>(&mut harness.server), 1);
+}
+
+#[test]
+fn movement_intent_moves_server_transform() {
+    let mut harness = Harness::new(2);
+
+    harness.update_until("both players on both clients", |harness| {
+        harness.clients.iter_mut().all(|client| {
+            count::<With<Player>>(client) == 2 && count::<With<LocalPlayer>>(client) == 1
+        })
+    });
+
+    let network_id = harness.clients[0]
+        .world_mut()
+        .query_filtered::<&Player, With<LocalPlayer>>()
+        .single(harness.clients[0].world())
+        .unwrap()
+        .network_id;
+    let start = server_position(&mut harness.server, network_id);
+
+    for client in &mut harness.clients {
+        client.world_mut().client_trigger(ToggleReady);
+    }
+    harness.clients[0]
+        .world_mut()
+        .client_trigger(ClientMovementIntent {
+            seq: 1,
+            direction: Vec2::NEG_Y,
+        });
+
+    harness.update_until("the server to move the player", |harness| {
+        server_position(&mut harness.server, network_id).y < start.y - 1.0
+    });
+}
+
+#[test]
+fn flooded_movement_intents_cannot_outrun_the_speed_limit() {
+    let mut harness = Harness::new(2);
+
+    harness.update_until("both local players", |harness| {
+        harness.clients.iter_mut().all(|client| {
+            count::<With<Player>>(client) == 2 && count::<With<LocalPlayer>>(client) == 1
+        })
+    });
+
+    let network_id = harness.clients[0]
+        .world_mut()
+        .query_filtered::<&Player, With<LocalPlayer>>()
+        .single(harness.clients[0].world())
+        .unwrap()
+        .network_id;
+    harness.start_game();
+
+    let speed = harness.server.world().resource::<MovementConfig>().speed;
+    let start = server_position(&mut harness.server, network_id);
+    // Movement steps on the fixed clock, which can lag the frame clock by up to a tick.
+    let start_time = harness
+        .server
+        .world()
+        .resource::<Time<Fixed>>()
+        .elapsed_secs();
+
+    let mut seq = 0;
+    for _ in 0..60 {
+        for _ in 0..20 {
+            seq += 1;
+            harness.clients[0]
+                .world_mut()
+                .client_trigger(ClientMovementIntent {
+                    seq,
+                    direction: Vec2::new(1000.0, 1000.0),
+                });
+        }
+        harness.update();
+    }
+
+    let elapsed = harness
+        .server
+        .world()
+        .resource::<Time<Fixed>>()
+        .elapsed_secs()
+        - start_time;
+    let travelled = server_position(&mut harness.server, network_id).distance(start);
+    assert!(travelled > 0.0, "the player never moved");
+    assert!(
+        travelled <= speed * elapsed + 1.0,
+        "moved {travelled} units in {elapsed}s at speed {speed}"
+    );
+}
+
+#[test]
+fn players_moved_too_far_are_pulled_back_and_kicked() {
+    let port = free_port();
+    let mut server = server_app(port, &["--kick-after-violations", "2"]);
+    server.update();
+    let mut harness = Harness {
+        server,
+        clients: vec![client_app(port, &[]), client_app(port, &[])],
+    };
+
+    harness.start_game();
+
+    let network_id = local_network_id(&mut harness.clients[0]);
+    let speed = harness.server.world().resource::<MovementConfig>().speed;
+    let timestep = harness
+        .server
+        .world()
+        .resource::<Time<Fixed>>()
+        .timestep()
+        .as_secs_f32();
+    let max_distance = speed * timestep + 1.0;
+
+    // Intents can't move a player that far, so jump it straight on the server.
+    let start = server_position(&mut harness.server, network_id);
+    let jumped = start + (-start).normalize_or(Vec2::X) * 50.0;
+    set_server_position(&mut harness.server, network_id, jumped);
+    harness.update_until("the jump to be pulled back", |harness| {
+        server_position(&mut harness.server, network_id) != jumped
+    });
+    let travelled = server_position(&mut harness.server, network_id).distance(start);
+    assert!(
+        travelled <= max_distance + 0.01,
+        "kept {travelled} units of the jump, allowed {max_distance}"
+    );
+    assert_eq!(count::<With<ConnectedClient>>(&mut harness.server), 2);
+
+    // The second violation reaches the limit.
+    set_server_position(&mut harness.server, network_id, jumped);
+    harness.update_until("the player to be kicked", |harness| {
+        count::<With<ConnectedClient>>(&mut harness.server) == 1
+            && !harness.clients[0]
+                .world()
+                .resource::<QuinnetClient>()
+                .is_connected()
+    });
+}
+
+#[derive(Resource, Default)]
+struct ReceivedWhispers(Vec<String>);
+
+#[test]
+fn whispers_only_reach_their_sender_and_target() {
+    let mut harness = Harness::new(3);
+
+    harness.update_until("every player named on the server", |harness| {
+        count::<With<PlayerName>>(&mut harness.server) == 3
+            && harness
+                .clients
+                .iter_mut()
+                .all(|client| count::<With<LocalPlayer>>(client) == 1)
+    });
+
+    for client in &mut harness.clients {
+        client.init_resource::<ReceivedWhispers>();
+        client.add_observer(
+            |whisper: On<WhisperDelivery>, mut received: ResMut<ReceivedWhispers>| {
+                received.0.push(whisper.text.clone());
+            },
+        );
+    }
+    let target_id = local_network_id(&mut harness.clients[1]);
+    let target_name = harness
+        .server
+        .world_mut()
+        .query::<(&Player, &PlayerName)>()
+        .iter(harness.server.world())
+        .find(|(player, _)| player.network_id == target_id)
+        .map(|(_, name)| name.0.clone())
+        .unwrap();
+
+    harness.clients[0].world_mut().client_trigger(Whisper {
+        target_name,
+        text: "psst".to_string(),
+    });
+    harness.update_until("the whisper and its echo", |harness| {
+        harness.clients[..2]
+            .iter()
+            .all(|client| client.world().resource::<ReceivedWhispers>().0 == ["psst"])
+    });
+    // Anything broadcast would have reached the bystander by the time the others got theirs.
+    for _ in 0..10 {
+        harness.update();
+    }
+
+    assert!(
+        harness.clients[2]
+            .world()
+            .resource::<ReceivedWhispers>()
+            .0
+            .is_empty()
+    );
+}
+
+#[test]
+fn players_that_leave_are_despawned_on_other_clients() {
+    let mut harness = Harness::new(2);
+
+    harness.update_until("both players on both clients", |harness| {
+        harness.clients.iter_mut().all(|client| {
+            count::<With<Player>>(client) == 2 && count::<With<LocalPlayer>>(client) == 1
+        })
+    });
+    let sprites = player_sprites(&mut harness.clients[0]);
+
+    harness.clients[1]
+        .world_mut()
+        .resource_mut::<QuinnetClient>()
+        .close_all_connections();
+    // Let the close go out, then drop the client before it would start reconnecting.
+    harness.update();
+    harness.clients.pop();
+
+    harness.update_until("the other player to be despawned", |harness| {
+        count::<With<Player>>(&mut harness.clients[0]) == 1
+    });
+    // Both players had the same sprites, and the ones of the player that left are gone with it.
+    assert_eq!(player_sprites(&mut harness.clients[0]), sprites / 2);
+}
+
+#[test]
+fn new_players_fade_in() {
+    let port = free_port();
+    let mut server = server_app(port, &[]);
+    server.update();
+    let mut harness = Harness {
+        server,
+        clients: vec![client_app(port, &["--spawn-fade-ms", "500"])],
+    };
+    harness.update_until("the local player", |harness| {
+        count::<With<LocalPlayer>>(&mut harness.clients[0]) == 1
+    });
+
+    harness.clients.push(client_app(port, &[]));
+    harness.update_until("the other player", |harness| {
+        count::<With<Player>>(&mut harness.clients[0]) == 2
+    });
+    assert!(remote_player_alphas(&mut harness.clients[0]).contains(&false));
+    harness.update_until("the other player to be fully shown", |harness| {
+        !remote_player_alphas(&mut harness.clients[0]).contains(&false)
+    });
+}
+
+#[test]
+fn movement_follows_the_keybindings_file() {
+    let port = free_port();
+    let path = std::env::temp_dir().join(format!("keybindings-{port}.json"));
+    std::fs::write(&path, r#"{ "move_up": [{ "key": "KeyI" }] }"#).unwrap();
+    let mut server = server_app(port, &[]);
+    server.update();
+    let mut harness = Harness {
+        server,
+        clients: vec![client_app(port, &["--keybindings", path.to_str().unwrap()])],
+    };
+    harness.update_until("the local player", |harness| {
+        count::<With<LocalPlayer>>(&mut harness.clients[0]) == 1
+    });
+    harness.update();
+    let _ = std::fs::remove_file(&path);
+
+    let keys = local_player_keys(&mut harness.clients[0]);
+    assert!(keys.contains(&KeyCode::KeyI));
+    assert!(!keys.contains(&KeyCode::KeyW));
+    assert!(keys.contains(&KeyCode::KeyS));
+
+    // Rebinding at runtime swaps the spawned player's bindings.
+    harness.clients[0].insert_resource(KeyBindings {
+        move_up: vec![InputButton::Key(KeyCode::KeyK)],
+        ..default()
+    });
+    harness.update();
+    let keys = local_player_keys(&mut harness.clients[0]);
+    assert!(keys.contains(&KeyCode::KeyK));
+    assert!(!keys.contains(&KeyCode::KeyI));
+}
+
+#[test]
+fn out_of_range_client_options_are_refused() {
+    for option in [
+        "--connect-timeout=0",
+        "--connect-timeout=inf",
+        "--interpolation-delay-ticks=0",
+        "--dead-zone=1",
+        "--dead-zone=-0.1",
+        "--sensitivity=-1",
+        "--sensitivity=NaN",
+        "--rumble-intensity=1.5",
+        "--max-intent-rate=-10",
+    ] {
+        let parsed = client::Args::try_parse_from(["client", option]);
+        assert!(parsed.is_err(), "{option} was accepted");
+    }
+}
+
+#[test]
+fn keybindings_without_a_way_to_move_are_refused() {
+    let path = std::env::temp_dir().join(format!("keybindings-{}.json", free_port()));
+    std::fs::write(&path, r#"{ "move_left": [], "move_stick": [] }"#).unwrap();
+    let parsed = client::Args::try_parse_from(["client", "--keybindings", path.to_str().unwrap()]);
+    let _ = std::fs::remove_file(&path);
+
+    let Err(error) = parsed else {
+        panic!("keybindings without a way to move left were accepted");
+    };
+    assert!(error.to_string().contains("move_left has no bindings"));
+}
+
+#[test]
+fn players_in_other_rooms_are_hidden() {
+    let mut harness = Harness::new(2);
+
+    harness.update_until("both players on both clients", |harness| {
+        harness.clients.iter_mut().all(|client| {
+            count::<With<Player>>(client) == 2 && count::<With<LocalPlayer>>(client) == 1
+        })
+    });
+
+    harness.clients[1]
+        .world_mut()
+        .client_trigger(JoinRoom { room_id: 1 });
+    harness.update_until("each client to only see its own player", |harness| {
+        harness
+            .clients
+            .iter_mut()
+            .all(|client| count::<With<Player>>(client) == 1)
+    });
+    assert_eq!(count::<With<LocalPlayer>>(&mut harness.clients[1]), 1);
+}
+
+#[test]
+fn players_out_of_view_are_hidden_until_they_come_closer() {
+    let port = free_port();
+    // Spawn points are further apart than this.
+    let mut server = server_app(port, &["--view-radius", "100"]);
+    server.update();
+    let mut harness = Harness {
+        server,
+        clients: vec![client_app(port, &[]), client_app(port, &[])],
+    };
+
+    harness.update_until("both local players", |harness| {
+        harness
+            .clients
+            .iter_mut()
+            .all(|client| count::<With<LocalPlayer>>(client) == 1)
+    });
+    for _ in 0..10 {
+        harness.update();
+    }
+    for client in &mut harness.clients {
+        assert_eq!(count::<With<Player>>(client), 1);
+    }
+
+    harness.start_game();
+    let walker = local_network_id(&mut harness.clients[0]);
+    let target = local_network_id(&mut harness.clients[1]);
+    let mut seq = 0;
+    harness.update_until("the players to see each other", |harness| {
+        seq += 1;
+        let direction = server_position(&mut harness.server, target)
+            - server_position(&mut harness.server, walker);
+        harness.clients[0]
+            .world_mut()
+            .client_trigger(ClientMovementIntent {
+                seq,
+                direction: direction.normalize_or_zero(),
+            });
+        harness
+            .clients
+            .iter_mut()
+            .all(|client| count::<With<Player>>(client) == 2)
+    });
+}
+
+#[test]
+fn idle_players_are_only_replicated_every_tick_when_asked() {
+    assert_eq!(idle_player_updates(&[]), 0);
+    assert!(idle_player_updates(&["--replicate-every-tick"]) > 0);
+}
+
+#[test]
+fn poor_connections_stretch_the_interpolation_delay() {
+    let port = free_port();
+    let mut server = server_app(port, &[]);
+    server.update();
+    // Even loopback is slower than this.
+    let client = client_app(
+        port,
+        &["--poor-rtt-ms", "0.001", "--poor-max-extra-delay", "2"],
+    );
+    let mut harness = Harness {
+        server,
+        clients: vec![client],
+    };
+    harness.update_until("the local player", |harness| {
+        count::<With<LocalPlayer>>(&mut harness.clients[0]) == 1
+    });
+    let timestep = harness.clients[0]
+        .world()
+        .resource::<Time<Fixed>>()
+        .timestep();
+
+    harness.update_until("the delay to reach its limit", |harness| {
+        let quality = harness.clients[0].world().resource::<ConnectionQuality>();
+        quality.poor && quality.extra_delay_ticks == 2
+    });
+    harness.update();
+    let stretched = harness.clients[0]
+        .world()
+        .resource::<Time<Fixed>>()
+        .timestep();
+    assert!(stretched > timestep, "{stretched:?} > {timestep:?}");
+}
+
+#[test]
+fn projectiles_damage_the_player_they_hit() {
+    let mut harness = Harness::new(2);
+
+    harness.update_until("both players on both clients", |harness| {
+        harness.clients.iter_mut().all(|client| {
+            count::<With<Player>>(client) == 2 && count::<With<LocalPlayer>>(client) == 1
+        })
+    });
+    harness.start_game();
+
+    let shooter = local_network_id(&mut harness.clients[0]);
+    let target = local_network_id(&mut harness.clients[1]);
+    let direction = server_position(&mut harness.server, target)
+        - server_position(&mut harness.server, shooter);
+    // Only the first of a burst gets past the fire rate limit.
+    for _ in 0..3 {
+        harness.clients[0]
+            .world_mut()
+            .client_trigger(FireProjectile { direction });
+    }
+
+    harness.update_until("the target to be hit", |harness| {
+        let health = server_health(&mut harness.server, target);
+        health.current < health.max
+    });
+    harness.update_until("the projectiles to be despawned", |harness| {
+        count::<(With<Projectile>, With<Replicated>)>(&mut harness.server) == 0
+            && count::<With<Projectile>>(&mut harness.clients[1]) == 0
+    });
+    let health = server_health(&mut harness.server, target);
+    assert_eq!(health.current, health.max - 20.0);
+    let health = server_health(&mut harness.server, shooter);
+    assert_eq!(health.current, health.max);
+}
+
+#[test]
+fn dashes_are_limited_by_the_cooldown() {
+    let mut harness = Harness::new(2);
+
+    harness.start_game();
+
+    let network_id = local_network_id(&mut harness.clients[0]);
+    let start = server_position(&mut harness.server, network_id);
+    // Towards the middle, so the arena bounds don't cut the dash short.
+    let direction = (-start).normalize_or(Vec2::X);
+    for _ in 0..5 {
+        harness.clients[0]
+            .world_mut()
+            .client_trigger(DashIntent { direction });
+    }
+
+    harness.update_until("the cooldown on the client", |harness| {
+        harness.clients[0]
+            .world_mut()
+            .query_filtered::<&DashCooldown, With<LocalPlayer>>()
+            .single(harness.clients[0].world())
+            .is_ok_and(|cooldown| !cooldown.0.is_finished())
+    });
+    harness.update_until("the dash to fade out", |harness| {
+        server_velocity(&mut harness.server, network_id) == Vec2::ZERO
+    });
+    // One dash at the default speed carries a player about 80 units, five would go 400.
+    let distance = (server_position(&mut harness.server, network_id) - start).dot(direction);
+    assert!((40.0..120.0).contains(&distance), "dashed {distance} units");
+}
+
+#[test]
+fn out_of_range_server_options_are_refused() {
+    for option in [
+        "--world-width=0",
+        "--world-height=NaN",
+        "--speed=-1",
+        "--speed=NaN",
+        "--acceleration=-1",
+        "--deceleration=inf",
+        "--player-size=0",
+        "--view-radius=-5",
+        "--metrics-interval=0",
+        "--dash-speed=-1",
+        "--dash-cooldown=inf",
+        "--dash-cooldown=NaN",
+        "--idle-timeout=inf",
+        "--respawn-delay=-1",
+        "--empty-timeout=inf",
+    ] {
+        let parsed = server::Args::try_parse_from(["server", option]);
+        assert!(parsed.is_err(), "{option} was accepted");
+    }
+}
+
+#[test]
+fn spent_projectiles_are_reused_without_being_replicated() {
+    let port = free_port();
+    let mut server = server_app(port, &["--fire-rate", "100", "--projectile-damage", "0"]);
+    server.update();
+    let mut harness = Harness {
+        server,
+        clients: vec![client_app(port, &[]), client_app(port, &[])],
+    };
+
+    harness.update_until("both players on both clients", |harness| {
+        harness.clients.iter_mut().all(|client| {
+            count::<With<Player>>(client) == 2 && count::<With<LocalPlayer>>(client) == 1
+        })
+    });
+    harness.start_game();
+
+    let shooter = local_network_id(&mut harness.clients[0]);
+    let target = local_network_id(&mut harness.clients[1]);
+    let direction = server_position(&mut harness.server, target)
+        - server_position(&mut harness.server, shooter);
+    harness.update_until("a spent projectile to be fired again", |harness| {
+        harness.clients[0]
+            .world_mut()
+            .client_trigger(FireProjectile { direction });
+        harness
+            .server
+            .world()
+            .resource::<ServerMetrics>()
+            .projectiles_reused
+            > 0
+    });
+    harness.update_until("every projectile to land", |harness| {
+        count::<(With<Projectile>, With<Replicated>)>(&mut harness.server) == 0
+            && harness
+                .clients
+                .iter_mut()
+                .all(|client| count::<With<Projectile>>(client) == 0)
+    });
+
+    // Every entity ever spawned for a shot is still around, waiting in the pool.
+    let spawned = harness
+        .server
+        .world()
+        .resource::<ServerMetrics>()
+        .projectiles_spawned;
+    assert_eq!(
+        count::<With<Projectile>>(&mut harness.server),
+        spawned as usize
+    );
+}
+
+#[test]
+fn clients_without_the_join_token_are_refused() {
+    let port = free_port();
+    let mut server = server_app(port, &["--join-secret", "hunter2"]);
+    server.update();
+    let mut harness = Harness {
+        server,
+        clients: vec![
+            client_app(port, &["--token", "hunter2"]),
+            client_app(port, &["--token", "wrong"]),
+        ],
+    };
+
+    harness.update_until("the invited player", |harness| {
+        count::<With<LocalPlayer>>(&mut harness.clients[0]) == 1
+    });
+    harness.update_until("the uninvited client to be disconnected", |harness| {
+        count::<With<ConnectedClient>>(&mut harness.server) == 1
+    });
+    assert_eq!(count::<With<Player>>(&mut harness.server), 1);
+    assert_eq!(count::<With<LocalPlayer>>(&mut harness.clients[1]), 0);
+}
+
+#[test]
+fn bots_join_and_disconnect_when_done() {
+    let port = free_port();
+    let mut server = server_app(port, &[]);
+    server.update();
+    let bots = run_bots(port, 3, 2.0);
+    let mut harness = Harness {
+        server,
+        clients: Vec::new(),
+    };
+
+    harness.update_until("every bot to join", |harness| {
+        count::<With<Player>>(&mut harness.server) == 3
+    });
+    harness.update_until("the bots to finish", |_| bots.is_finished());
+    assert_eq!(bots.join().unwrap(), AppExit::Success);
+    harness.update_until("the bots to disconnect", |harness| {
+        count::<With<ConnectedClient>>(&mut harness.server) == 0
+    });
+}
+
+#[test]
+fn diagnose_exits_after_measuring() {
+    let port = free_port();
+    let mut server = server_app(port, &[]);
+    server.update();
+    let mut harness = Harness {
+        server,
+        clients: vec![diagnose_app(port, 0.5)],
+    };
+
+    harness.update_until("the diagnosis to finish", |harness| {
+        harness.clients[0].should_exit().is_some()
+    });
+    assert_eq!(harness.clients[0].should_exit(), Some(AppExit::Success));
+    // Diagnosing doesn't join the game.
+    assert_eq!(count::<With<Player>>(&mut harness.server), 0);
+}
+
+#[test]
+fn diagnose_fails_without_a_server() {
+    let mut client = diagnose_app(free_port(), 0.5);
+    let start = std::time::Instant::now();
+    while client.should_exit().is_none() {
+        assert!(
+            start.elapsed().as_secs() < 10,
+            "timed out waiting for the diagnosis to fail"
+        );
+        client.update();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+    assert!(client.should_exit().unwrap().is_error());
+}
+
+#[test]
+fn bound_port_is_reported_without_panicking() {
+    let port = free_port();
+    let _socket = UdpSocket::bind((Ipv6Addr::LOCALHOST, port)).unwrap();
+
+    let mut server = server_app(port, &[]);
+    server.update();
+
+    assert!(server.world().contains_resource::<NetworkError>());
+    assert!(server.should_exit().is_some());
+}
+
+#[test]
+fn taken_port_falls_back_to_the_next_one() {
+    let port = free_port();
+    let _socket = UdpSocket::bind((Ipv6Addr::LOCALHOST, port)).unwrap();
+
+    let mut server = server_app(port, &["--port-range", "8"]);
+    server.update();
+
+    assert!(!server.world().contains_resource::<NetworkError>());
+    let bound = server.world().resource::<BoundPort>().0;
+    assert!(bound > port && bound <= port + 8);
+}
+
+#[test]
+fn unbindable_address_falls_back_to_the_next_one() {
+    let port = free_port();
+    let _socket = UdpSocket::bind((Ipv6Addr::LOCALHOST, port)).unwrap();
+
+    let mut server = server_app(port, &["--ip", "::1", "--ip", "127.0.0.1"]);
+    server.update();
+
+    assert!(!server.world().contains_resource::<NetworkError>());
+    assert_eq!(server.world().resource::<BoundPort>().0, port);
+}
+
+/// Replication updates the first client gets for the other player while both stand still
+/// during a game
+fn idle_player_updates(server_args: &[&str]) -> usize {
+    let port = free_port();
+    let mut server = server_app(port, server_args);
+    server.update();
+    let mut harness = Harness {
+        server,
+        clients: vec![client_app(port, &[]), client_app(port, &[])],
+    };
+
+    harness.update_until("both players on both clients", |harness| {
+        harness.clients.iter_mut().all(|client| {
+            count::<With<Player>>(client) == 2 && count::<With<LocalPlayer>>(client) == 1
+        })
+    });
+    harness.start_game();
+    // Let the game start settle before counting.
+    for _ in 0..10 {
+        harness.update();
+    }
+
+    let client = &mut harness.clients[0];
+    let remote = client
+        .world_mut()
+        .query_filtered::<Entity, (With<Player>, Without<LocalPlayer>)>()
+        .single(client.world())
+        .unwrap();
+    let mut updates = 0;
+    for _ in 0..20 {
+        harness.update();
+        updates += harness.clients[0]
+            .world()
+            .resource::<Messages<EntityReplicated>>()
+            .iter_current_update_messages()
+            .filter(|update| update.entity == remote)
+            .count();
+    }
+    updates
+}
+
+/// Whether each sprite drawn for the remote players on `client` is opaque
+fn remote_player_alphas(client: &mut App) -> Vec<bool> {
+    let players: Vec<Entity> = client
+        .world_mut()
+        .query_filtered::<Entity, (With<Player>, Without<LocalPlayer>)>()
+        .iter(client.world())
+        .collect();
+    client
+        .world_mut()
+        .query::<(&Sprite, &ChildOf)>()
+        .iter(client.world())
+        .filter(|(_, child_of)| players.contains(&child_of.parent()))
+        .map(|(sprite, _)| sprite.color.alpha() == 1.0)
+        .collect()
+}
+
+/// Counts the sprites of players and of their children, such as health bars
+fn player_sprites(client: &mut App) -> usize {
+    let players: Vec<Entity> = client
+        .world_mut()
+        .query_filtered::<Entity, With<Player>>()
+        .iter(client.world())
+        .collect();
+    client
+        .world_mut()
+        .query_filtered::<(Entity, Option<&ChildOf>), With<Sprite>>()
+        .iter(client.world())
+        .filter(|(entity, child_of)| {
+            players.contains(entity)
+                || child_of.is_some_and(|child_of| players.contains(&child_of.parent()))
+        })
+        .count()
+}
+
+/// Keys bound to the local player's actions
+fn local_player_keys(client: &mut App) -> Vec<KeyCode> {
+    let actions: Vec<Entity> = client
+        .world_mut()
+        .query_filtered::<Entity, With<ActionOf<LocalPlayer>>>()
+        .iter(client.world())
+        .collect();
+    client
+        .world_mut()
+        .query::<(&Binding, &BindingOf)>()
+        .iter(client.world())
+        .filter(|(_, binding_of)| actions.contains(&binding_of.0))
+        .filter_map(|(binding, _)| match binding {
+            Binding::Keyboard { key, .. } => Some(*key),
+            _ => None,
+        })
+        .collect()
+}
+
+fn local_network_id(client: &mut App) -> u64 {
+    client
+        .world_mut()
+        .query_filtered::<&Player, With<LocalPlayer>>()
+        .single(client.world())
+        .unwrap()
+        .network_id
+}
+
+fn server_health(server: &mut App, network_id: u64) -> Health {
+    server
+        .world_mut()
+        .query::<(&Player, &Health)>()
+        .iter(server.world())
+        .find(|(player, _)| player.network_id == network_id)
+        .map(|(_, health)| *health)
+        .expect("player is not on the server")
+}
+
+fn server_velocity(server: &mut App, network_id: u64) -> Vec2 {
+    server
+        .world_mut()
+        .query::<(&Player, &Velocity)>()
+        .iter(server.world())
+        .find(|(player, _)| player.network_id == network_id)
+        .map(|(_, velocity)| velocity.0)
+        .expect("player is not on the server")
+}
+
+fn set_server_position(server: &mut App, network_id: u64, position: Vec2) {
+    let mut players = server.world_mut().query::<(&Player, &mut Transform)>();
+    let (_, mut transform) = players
+        .iter_mut(server.world_mut())
+        .find(|(player, _)| player.network_id == network_id)
+        .expect("player is not on the server");
+    transform.translation = position.extend(transform.translation.z);
+}
+
+fn server_position(server: &mut App, network_id: u64) -> Vec2 {
+    server
+        .world_mut()
+        .query::<(&Player, &Transform)>()
+        .iter(server.world())
+        .find(|(player, _)| player.network_id == network_id)
+        .map(|(_, transform)| transform.translation.xy())
+        .expect("player is not on the server")
+}
@@ -0,0 +1,115 @@
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgMatches, Command, Parser};
+use serde_json::{Map, Value};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Parses the process arguments, taking any option missing from the command line from the JSON
+/// file given with `--config`
+pub fn parse_args<T: Parser>() -> T {
+    parse_args_from(std::env::args_os())
+}
+
+/// Like [`parse_args`] with explicit arguments, the first one being the binary name.
+///
+/// The file holds an object keyed by option name, in either `snake_case` or `kebab-case`, e.g.
+/// `{ "port": 5000, "max_players": 8, "ip": ["::1", "127.0.0.1"] }`. Flags take booleans and
+/// repeatable options take arrays. Options given on the command line win over the file, along
+/// with those they conflict with. Errors exit the process like any other invalid argument.
+pub fn parse_args_from<T: Parser>(args: impl IntoIterator<Item = impl Into<OsString>>) -> T {
+    let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    let command = T::command();
+    let matches = command.clone().get_matches_from(&args);
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        return T::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    };
+
+    let file_args = match config_file_args(path, &command, &matches) {
+        Ok(file_args) => file_args,
+        Err(e) => command.clone().error(ErrorKind::InvalidValue, e).exit(),
+    };
+    let mut args = args.into_iter();
+    let binary = args.next();
+    T::parse_from(binary.into_iter().chain(file_args).chain(args))
+}
+
+/// Turns the options in the config file at `path` into command-line arguments, leaving out those
+/// `matches` already got from the command line
+fn config_file_args(
+    path: &Path,
+    command: &Command,
+    matches: &ArgMatches,
+) -> Result<Vec<OsString>, String> {
+    let json = fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read config file {}: {e}", path.display()))?;
+    let options: Map<String, Value> = serde_json::from_str(&json).map_err(|e| {
+        // Point at the offending line, serde only reports its position.
+        let line = json
+            .lines()
+            .nth(e.line().saturating_sub(1))
+            .unwrap_or_default();
+        format!(
+            "Invalid config file {}:{}:{}: {e}\n  | {line}\n  | {:>width$}",
+            path.display(),
+            e.line(),
+            e.column(),
+            "^",
+            width = e.column().max(1)
+        )
+    })?;
+
+    let on_command_line =
+        |arg: &Arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine);
+    let mut args = Vec::new();
+    for (key, value) in options {
+        let long = key.replace('_', "-");
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long.as_str()) && long != "config")
+        else {
+            return Err(format!(
+                "Unknown option {key:?} in config file {}",
+                path.display()
+            ));
+        };
+        if on_command_line(arg)
+            || command
+                .get_arg_conflicts_with(arg)
+                .into_iter()
+                .any(on_command_line)
+        {
+            continue;
+        }
+
+        let flag = OsString::from(format!("--{long}"));
+        let takes_values = arg.get_action().takes_values();
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::Bool(set) if !takes_values => {
+                    if set {
+                        args.push(flag.clone());
+                    }
+                    continue;
+                }
+                Value::Null => continue,
+                Value::String(value) => value,
+                Value::Bool(_) | Value::Number(_) => value.to_string(),
+                Value::Array(_) | Value::Object(_) => {
+                    return Err(format!(
+                        "Option {key:?} in config file {} must be a string, number or boolean",
+                        path.display()
+                    ));
+                }
+            };
+            args.push(flag.clone());
+            args.push(value.into());
+        }
+    }
+    Ok(args)
+}
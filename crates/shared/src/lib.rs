@@ -1,10 +1,370 @@
+pub mod config;
+pub mod networking;
+pub mod wire;
+
 use bevy::prelude::*;
+use bevy_quinnet::shared::channels::{DEFAULT_MAX_RELIABLE_FRAME_LEN, SendChannelsConfiguration};
 use bevy_replicon::prelude::*;
-use serde::{Deserialize, Serialize};
+use bevy_replicon_quinnet::ChannelsConfigurationExt;
+use networking::{ClientDisconnect, ServerDisconnect};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "dev")]
+use std::collections::VecDeque;
+use std::time::Duration;
+use wire::VersionedAppExt;
+
+/// Registers the events and components shared by client and server.
+///
+/// Replicon requires both sides to register them in the same order, so they only live here.
+/// Payloads are tagged with [`wire::WIRE_VERSION`], which also goes into the protocol hash.
+/// Must be added after the replicon plugins, and after inserting a [`NetworkBudget`] to change it.
+pub struct GameSharedPlugin;
+
+impl Plugin for GameSharedPlugin {
+    fn build(&self, app: &mut App) {
+        let budget = *app
+            .world_mut()
+            .get_resource_or_init::<NetworkBudget>()
+            .into_inner();
+        app.world_mut()
+            .resource_mut::<ProtocolHasher>()
+            .add_custom(wire::WIRE_VERSION);
+
+        app.add_versioned_client_event::<ClientMovementIntent>(budget.movement_channel)
+            .add_versioned_client_event::<SetPlayerName>(Channel::Ordered)
+            .add_versioned_client_event::<ChatMessage>(Channel::Ordered)
+            .add_versioned_client_event::<ToggleReady>(Channel::Ordered)
+            .add_versioned_client_event::<Ping>(Channel::Unreliable)
+            .add_versioned_client_event::<ConnectIntent>(Channel::Ordered)
+            .add_versioned_client_event::<Whisper>(Channel::Ordered)
+            .add_versioned_client_event::<ClientDisconnect>(Channel::Ordered)
+            .add_versioned_client_event::<JoinRoom>(Channel::Ordered)
+            .add_versioned_client_event::<FireProjectile>(Channel::Ordered)
+            .add_versioned_client_event::<DashIntent>(Channel::Ordered)
+            .add_versioned_server_event::<GameConfig>(Channel::Ordered)
+            .add_versioned_server_event::<BroadcastChat>(Channel::Ordered)
+            .add_versioned_server_event::<ConnectionRejected>(Channel::Ordered)
+            .add_versioned_server_event::<ServerShutdown>(Channel::Ordered)
+            .add_versioned_server_event::<GameStart>(Channel::Ordered)
+            .add_versioned_server_event::<GamePaused>(Channel::Ordered)
+            .add_versioned_server_event::<PlayerDied>(Channel::Ordered)
+            .add_versioned_server_event::<PlayerRespawned>(Channel::Ordered)
+            .add_versioned_server_event::<RosterUpdate>(Channel::Ordered)
+            .add_versioned_server_event::<Pong>(Channel::Unreliable)
+            .add_versioned_server_event::<Kicked>(Channel::Ordered)
+            .add_versioned_server_event::<IdleKick>(Channel::Ordered)
+            .add_versioned_server_event::<InitialSnapshot>(Channel::Ordered)
+            .add_versioned_server_event::<CollisionHit>(Channel::Unreliable)
+            .add_versioned_server_event::<WhisperDelivery>(Channel::Ordered)
+            .add_versioned_server_event::<WhisperFailed>(Channel::Ordered)
+            .add_versioned_server_event::<ServerDisconnect>(Channel::Ordered)
+            .add_versioned_server_event::<PlayerJoined>(Channel::Ordered)
+            .add_versioned_server_event::<PlayerLeft>(Channel::Ordered)
+            .add_versioned_server_event::<ForcePosition>(Channel::Ordered)
+            .replicate_versioned_filtered::<Transform, Without<NetPosition>>()
+            .replicate_versioned::<Player>()
+            .replicate_versioned::<PlayerName>()
+            .replicate_versioned::<LastProcessedInput>()
+            .replicate_versioned::<PlayerReady>()
+            .replicate_versioned::<Health>()
+            .replicate_versioned::<PlayerColor>()
+            .replicate_versioned::<NetPosition>()
+            .replicate_versioned::<Ball>()
+            .replicate_versioned::<Projectile>()
+            .replicate_versioned::<Velocity>()
+            .replicate_versioned::<RoomId>()
+            .replicate_versioned::<PlayerShape>()
+            .replicate_versioned::<Facing>()
+            .replicate_versioned::<DashCooldown>();
+
+        #[cfg(feature = "dev")]
+        app.add_versioned_client_event::<SetNoclip>(Channel::Ordered)
+            .replicate_versioned::<Noclip>();
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+/// Limits on what the game sends over the network, fixed once [`GameSharedPlugin`] is added.
+///
+/// Replicon gives every message its own quinnet channel, and how reliable a channel is only
+/// matters to the side sending on it. Client and server don't have to agree on
+/// [`Self::movement_channel`], it only takes effect on clients. Quinnet sends all channels over
+/// one connection without priorities, so choosing the channel is the only way to favour one kind
+/// of traffic over another.
+pub struct NetworkBudget {
+    /// Channel movement intents are sent on. `Unreliable` drops lost intents since newer ones
+    /// replace them anyway, `Ordered` resends them and holds back later ones until they arrive.
+    pub movement_channel: Channel,
+    /// Largest message in bytes on reliable channels, the `max_frame_size` of quinnet's
+    /// `OrderedReliable` and `UnorderedReliable` channels
+    pub max_reliable_frame_len: usize,
+    /// Most movement intents a client sends per second, unlimited if zero. Releasing all input
+    /// is always sent right away, so a player never keeps moving on a dropped stop.
+    pub max_intent_rate: f32,
+}
+
+impl NetworkBudget {
+    /// Quinnet channels for the server endpoint
+    pub fn server_configs(&self, channels: &RepliconChannels) -> SendChannelsConfiguration {
+        channels.server_configs_custom(self.max_reliable_frame_len)
+    }
+
+    /// Quinnet channels for a client connection
+    pub fn client_configs(&self, channels: &RepliconChannels) -> SendChannelsConfiguration {
+        channels.client_configs_custom(self.max_reliable_frame_len)
+    }
+}
+
+impl Default for NetworkBudget {
+    fn default() -> Self {
+        Self {
+            movement_channel: Channel::Unreliable,
+            max_reliable_frame_len: DEFAULT_MAX_RELIABLE_FRAME_LEN,
+            max_intent_rate: 0.0,
+        }
+    }
+}
+
+/// Samples [`PerfStats`] takes the minimum and maximum over
+#[cfg(feature = "dev")]
+const PERF_WINDOW: usize = 120;
+
+/// Weight of the newest sample in [`PerfStats::average`]
+#[cfg(feature = "dev")]
+const PERF_SMOOTHING: f64 = 0.05;
+
+#[cfg(feature = "dev")]
+#[derive(Resource, Debug, Default)]
+/// Time taken by a client frame or a server tick, smoothed so a displayed number doesn't jitter
+pub struct PerfStats {
+    /// Exponential moving average of the samples
+    pub average: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    window: VecDeque<Duration>,
+}
+
+#[cfg(feature = "dev")]
+impl PerfStats {
+    pub fn record(&mut self, sample: Duration) {
+        self.average = if self.window.is_empty() {
+            sample
+        } else {
+            self.average
+                .mul_f64(1.0 - PERF_SMOOTHING)
+                .saturating_add(sample.mul_f64(PERF_SMOOTHING))
+        };
+
+        if self.window.len() >= PERF_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(sample);
+        self.min = self.window.iter().copied().min().unwrap_or_default();
+        self.max = self.window.iter().copied().max().unwrap_or_default();
+    }
+}
+
+/// Default player movement speed in units per second
+pub const PLAYER_SPEED: f32 = 100.0;
+
+/// Default server simulation rate in Hz
+pub const DEFAULT_TICK_RATE: f64 = 64.0;
+
+/// Default side length of the square players are drawn as
+pub const PLAYER_SIZE: f32 = 50.0;
+
+#[derive(Component, Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[require(Replicated)]
+/// Direction a player last moved in, in radians counterclockwise from +x
+pub struct Facing(pub f32);
+
+#[derive(Serialize, Deserialize, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// Outline players are drawn with
+pub enum ShapeKind {
+    #[default]
+    Square,
+    Circle,
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[require(Replicated)]
+/// How big a player is and how it's drawn, set by the server so visuals and collisions agree
+pub struct PlayerShape {
+    pub size: Vec2,
+    pub kind: ShapeKind,
+}
+
+impl PlayerShape {
+    /// Radius of the circle the player collides as, inscribed in its shape
+    pub fn collision_radius(&self) -> f32 {
+        self.size.min_element() / 2.0
+    }
+}
+
+impl Default for PlayerShape {
+    fn default() -> Self {
+        Self {
+            size: Vec2::splat(PLAYER_SIZE),
+            kind: ShapeKind::default(),
+        }
+    }
+}
+
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Copy)]
+/// Movement tuning, shared so client prediction matches the server
+pub struct MovementConfig {
+    /// Player movement speed in units per second, [`PLAYER_SPEED`] by default
+    pub speed: f32,
+}
+
+impl Default for MovementConfig {
+    fn default() -> Self {
+        Self {
+            speed: PLAYER_SPEED,
+        }
+    }
+}
+
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Copy)]
+/// How quickly players speed up and slow down, shared so client prediction matches the server
+pub struct AccelConfig {
+    /// Units per second squared gained towards the input direction while there's input
+    pub acceleration: f32,
+    /// Units per second squared lost once the input is released
+    pub deceleration: f32,
+}
+
+impl Default for AccelConfig {
+    fn default() -> Self {
+        Self {
+            acceleration: PLAYER_SPEED * 8.0,
+            deceleration: PLAYER_SPEED * 6.0,
+        }
+    }
+}
+
+#[derive(Resource, Event, Serialize, Deserialize, Debug, Clone, Copy)]
+/// Server -> Client event sent on connect with the rules of the world the server simulates
+pub struct GameConfig {
+    pub movement: MovementConfig,
+    pub accel: AccelConfig,
+    pub bounds: WorldBounds,
+    /// Server simulation rate in Hz, [`DEFAULT_TICK_RATE`] by default
+    pub tick_rate: f64,
+    /// Rate in Hz the server sends replication updates at, at most `tick_rate`
+    pub replication_rate: f64,
+    /// How long dead players wait before respawning
+    pub respawn_delay: Duration,
+}
+
+/// Velocity of a player moving at `velocity` after accelerating towards `direction` for
+/// `delta_secs`, decelerating instead when there's no input.
+///
+/// The direction is clamped to unit length and the target speed never exceeds `movement.speed`,
+/// so the server simulation and the client prediction agree even for unnormalized input such as
+/// diagonal key presses.
+pub fn accelerate(
+    velocity: Vec2,
+    direction: Vec2,
+    movement: &MovementConfig,
+    accel: &AccelConfig,
+    delta_secs: f32,
+) -> Vec2 {
+    let target = direction.clamp_length_max(1.0) * movement.speed;
+    let rate = if target == Vec2::ZERO {
+        accel.deceleration
+    } else {
+        accel.acceleration
+    };
+    velocity.move_towards(target, rate * delta_secs)
+}
+
+/// Fraction of a knockback's velocity left after one second, so it fades out within a few ticks
+pub const KNOCKBACK_DAMPING: f32 = 0.0001;
+
+/// Knockback slower than this in units per second stops outright
+pub const KNOCKBACK_REST_SPEED: f32 = 5.0;
+
+#[derive(Component, Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+/// Knockback a player slides with on top of its own movement, in units per second
+pub struct Velocity(pub Vec2);
+
+/// Damps `velocity` over `delta_secs`, returning the displacement and the velocity left.
+///
+/// Shared so the client prediction fades knockback out at the same rate as the server.
+pub fn knockback_step(velocity: Vec2, delta_secs: f32) -> (Vec2, Vec2) {
+    let damped = velocity * KNOCKBACK_DAMPING.powf(delta_secs);
+    if damped.length() < KNOCKBACK_REST_SPEED {
+        return (Vec2::ZERO, Vec2::ZERO);
+    }
+    (damped * delta_secs, damped)
+}
+
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Copy)]
+/// Axis-aligned area that players are confined to
+pub struct WorldBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl WorldBounds {
+    /// Creates bounds of the given size centered on the origin
+    pub fn from_size(size: Vec2) -> Self {
+        Self {
+            min: -size / 2.0,
+            max: size / 2.0,
+        }
+    }
+
+    /// Returns the closest point inside the bounds
+    pub fn clamp(&self, point: Vec2) -> Vec2 {
+        point.clamp(self.min, self.max)
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Event)]
 /// Client -> Server event telling server about the client's new position
-pub struct ClientMovementIntent(pub Vec2);
+pub struct ClientMovementIntent {
+    /// Increases with every intent sent, so stale or duplicated packets can be discarded
+    pub seq: u32,
+    pub direction: Vec2,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event sent right before the server disconnects a client that was playing
+pub struct Kicked {
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event sent right before the server disconnects a player for being idle
+pub struct IdleKick {
+    /// How long the player went without sending any input
+    pub timeout: Duration,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event sent in the tick a client joins.
+///
+/// Replicon applies server events only after the replication of the tick they were sent in, so
+/// by the time this arrives the client has the whole world as of its join.
+pub struct InitialSnapshot;
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event sent right before the server refuses a client
+pub struct ConnectionRejected {
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event broadcast shortly before the server stops its endpoint
+pub struct ServerShutdown {
+    pub reason: String,
+}
+
+#[derive(Resource, Debug, Clone)]
+/// Last failure to open the server endpoint or the client connection, logged instead of panicking
+pub struct NetworkError(pub String);
 
 #[derive(Component)]
 /// Marker component for the locally controlled player
@@ -16,3 +376,312 @@ pub struct LocalPlayer;
 pub struct Player {
     pub network_id: u64,
 }
+
+/// Radius of the ball players push around
+pub const BALL_RADIUS: f32 = 20.0;
+
+#[derive(Component, Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[require(Replicated, Transform)]
+/// The ball players push around, simulated by the server
+pub struct Ball {
+    /// Units per second
+    pub velocity: Vec2,
+}
+
+/// Radius of the projectiles players fire
+pub const PROJECTILE_RADIUS: f32 = 5.0;
+
+/// Projectile speed in units per second
+pub const PROJECTILE_SPEED: f32 = 600.0;
+
+#[derive(Component, Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[require(Replicated, Transform)]
+/// A shot fired by a player, simulated by the server until it hits someone or leaves the arena
+pub struct Projectile {
+    /// Units per second
+    pub velocity: Vec2,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Client -> Server event firing a projectile, ignored while the shooter's previous shot is
+/// still cooling down
+pub struct FireProjectile {
+    pub direction: Vec2,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Client -> Server event bursting the player forward, ignored until its [`DashCooldown`] is over
+pub struct DashIntent {
+    pub direction: Vec2,
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Time until a player may dash again, added on its first dash.
+///
+/// The server only replicates it when the player dashes, clients tick their copy themselves.
+pub struct DashCooldown(pub Timer);
+
+/// World units per fixed-point step of a [`NetPosition`]
+pub const NET_POSITION_PRECISION: f32 = 0.01;
+
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq)]
+#[require(Transform)]
+/// Compact stand-in for `Transform`, replicated instead of it when the server opts in.
+///
+/// Sent as two fixed-point integers in steps of [`NET_POSITION_PRECISION`], which postcard encodes
+/// as varints. A position within 10000 units of the origin takes at most 6 bytes, against 40 for
+/// the translation, rotation and scale of a full `Transform`.
+pub struct NetPosition(Vec2);
+
+impl NetPosition {
+    /// Snaps `position` to the fixed-point grid, so equal encodings compare equal
+    pub fn new(position: Vec2) -> Self {
+        Self::from_steps(Self::steps(position))
+    }
+
+    pub fn get(self) -> Vec2 {
+        self.0
+    }
+
+    fn steps(position: Vec2) -> (i32, i32) {
+        let steps = (position / NET_POSITION_PRECISION).round();
+        (steps.x as i32, steps.y as i32)
+    }
+
+    fn from_steps((x, y): (i32, i32)) -> Self {
+        Self(Vec2::new(x as f32, y as f32) * NET_POSITION_PRECISION)
+    }
+}
+
+impl Serialize for NetPosition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Self::steps(self.0).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for NetPosition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <(i32, i32)>::deserialize(deserializer).map(Self::from_steps)
+    }
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[require(Replicated)]
+/// Sequence number of the last movement intent the server applied for a player
+pub struct LastProcessedInput(pub u32);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Event)]
+/// Server -> Client event snapping the local player to `pos` after the server overrode its
+/// movement, with every intent up to `seq` already accounted for
+pub struct ForcePosition {
+    pub seq: u32,
+    pub pos: Vec2,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Client -> Server event asking the server to echo a [`Pong`], used to measure round-trip time
+pub struct Ping {
+    pub id: u32,
+    /// Client clock when the ping was sent, in milliseconds
+    pub client_time_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event echoing a [`Ping`] back unchanged
+pub struct Pong {
+    pub id: u32,
+    pub client_time_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event sent to a player that collision resolution pushed this tick
+pub struct CollisionHit {
+    /// How far the player was pushed, in world units
+    pub push: Vec2,
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy)]
+#[require(Replicated)]
+/// Hit points of a player, which is dead while `current` is zero
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    /// Full health with the given maximum
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event broadcast when a player's health reaches zero
+pub struct PlayerDied {
+    pub network_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event broadcast once a new player has a name
+pub struct PlayerJoined {
+    pub network_id: u64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event broadcast to the remaining players when one disconnects
+pub struct PlayerLeft {
+    pub network_id: u64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event broadcast when a dead player is back in the game
+pub struct PlayerRespawned {
+    pub network_id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event listing every named player, sent after joins, leaves and renames
+pub struct RosterUpdate {
+    /// Network id and name of each player, ordered by network id
+    pub players: Vec<(u64, String)>,
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy)]
+#[require(Replicated)]
+/// Color a player is drawn with, unique among connected players while the palette lasts
+pub struct PlayerColor(pub Color);
+
+/// Maximum number of characters kept from a player's chosen name
+pub const MAX_PLAYER_NAME_LEN: usize = 16;
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Client -> Server event choosing the client's display name
+pub struct SetPlayerName(pub String);
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+#[require(Replicated)]
+/// Display name of a player, replicated to all clients
+pub struct PlayerName(pub String);
+
+/// Strips control characters, trims whitespace and truncates a requested name to
+/// [`MAX_PLAYER_NAME_LEN`], returning `None` if nothing usable is left
+pub fn sanitize_player_name(name: &str) -> Option<String> {
+    sanitize_text(name, MAX_PLAYER_NAME_LEN)
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[require(Replicated)]
+/// Whether a player is ready for the game to start, replicated to all clients
+pub struct PlayerReady(pub bool);
+
+#[derive(Component, Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+/// Room a player or the ball is in. Clients only see and collide with what shares their room, and
+/// everyone starts in room 0.
+pub struct RoomId(pub u32);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Event)]
+/// Client -> Server event moving the sender to another room, created on demand
+pub struct JoinRoom {
+    pub room_id: u32,
+}
+
+/// Factor noclip players move faster by than [`MovementConfig::speed`]
+#[cfg(feature = "dev")]
+pub const NOCLIP_SPEED_FACTOR: f32 = 3.0;
+
+#[cfg(feature = "dev")]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Event)]
+/// Client -> Server event turning noclip on or off, only honored for admins
+pub struct SetNoclip(pub bool);
+
+#[cfg(feature = "dev")]
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy)]
+/// Marker for a player moving through others and out of the world bounds at
+/// [`NOCLIP_SPEED_FACTOR`] times the speed, for testing
+pub struct Noclip;
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Client -> Server event sent right after connecting, the server only spawns a player once it has it
+pub struct ConnectIntent {
+    /// Watch the game without a player, not counting against the player limit
+    pub spectator: bool,
+    /// Proves the client may join servers that require a token
+    pub token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Client -> Server event flipping the client's ready state while in the lobby
+pub struct ToggleReady;
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event sent when the lobby ends and players can move
+pub struct GameStart;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Event)]
+/// Server -> Client event sent when the operator pauses (`true`) or resumes (`false`) the game
+pub struct GamePaused(pub bool);
+
+/// Maximum number of characters kept from a chat message
+pub const MAX_CHAT_MESSAGE_LEN: usize = 200;
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Client -> Server event sending a chat message
+pub struct ChatMessage {
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event relaying a chat message to everyone
+pub struct BroadcastChat {
+    /// Network id of the player who sent the message
+    pub sender: u64,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Client -> Server event sending a chat message to a single player
+pub struct Whisper {
+    /// Display name of the recipient
+    pub target_name: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event delivering a [`Whisper`] to its recipient, and echoing it to its sender
+pub struct WhisperDelivery {
+    pub sender: u64,
+    pub target: u64,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Event)]
+/// Server -> Client event telling the sender of a [`Whisper`] that nobody has that name
+pub struct WhisperFailed {
+    pub target_name: String,
+}
+
+/// Strips control characters, trims whitespace and truncates a chat message to
+/// [`MAX_CHAT_MESSAGE_LEN`], returning `None` if nothing usable is left
+pub fn sanitize_chat_message(text: &str) -> Option<String> {
+    sanitize_text(text, MAX_CHAT_MESSAGE_LEN)
+}
+
+fn sanitize_text(text: &str, max_len: usize) -> Option<String> {
+    let text: String = text
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>()
+        .trim()
+        .chars()
+        .take(max_len)
+        .collect();
+    let text = text.trim_end();
+
+    (!text.is_empty()).then(|| text.to_string())
+}
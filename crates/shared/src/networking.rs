@@ -0,0 +1,56 @@
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+/// Why one side closed the connection, sent to the other side right before it does
+pub enum DisconnectReason {
+    /// The player left or closed the game
+    UserRequested,
+    /// Removed by the server operator or for breaking the rules
+    Kicked,
+    ServerShutdown,
+    /// The server is restarting and accepts connections again shortly
+    Restarting,
+    /// No input or no answer for too long
+    Timeout,
+    /// Refused for a missing or wrong join token
+    Unauthorized,
+    /// The connection couldn't continue, the side closing it logs the details
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Event)]
+/// Server -> Client event sent right before the server disconnects the client
+pub struct ServerDisconnect {
+    pub reason: DisconnectReason,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Event)]
+/// Client -> Server event sent right before the client closes its connection
+pub struct ClientDisconnect {
+    pub reason: DisconnectReason,
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+/// Set by [`close_connection`], the client closes its connections at the end of the frame
+pub struct PendingClose(pub DisconnectReason);
+
+/// Tells `client` why and disconnects it.
+///
+/// The backend only disconnects after pending messages are sent, so the reason still arrives.
+pub fn disconnect_client(commands: &mut Commands, client: Entity, reason: DisconnectReason) {
+    commands.server_trigger(ToClients {
+        mode: SendMode::Direct(ClientId::Client(client)),
+        message: ServerDisconnect { reason },
+    });
+    commands.write_message(DisconnectRequest { client });
+}
+
+/// Tells the server why and has the client close its connections at the end of the frame.
+///
+/// Client events only go out in `PostUpdate`, closing right away would drop the reason.
+pub fn close_connection(commands: &mut Commands, reason: DisconnectReason) {
+    commands.client_trigger(ClientDisconnect { reason });
+    commands.insert_resource(PendingClose(reason));
+}
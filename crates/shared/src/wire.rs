@@ -0,0 +1,190 @@
+use bevy::prelude::*;
+use bevy_replicon::bytes::{Buf, Bytes};
+use bevy_replicon::postcard_utils;
+use bevy_replicon::prelude::*;
+use bevy_replicon::shared::message::ctx::{
+    ClientReceiveCtx, ClientSendCtx, ServerReceiveCtx, ServerSendCtx,
+};
+use bevy_replicon::shared::replication::registry::command_fns::MutWrite;
+use bevy_replicon::shared::replication::registry::ctx::{SerializeCtx, WriteCtx};
+use bevy_replicon::shared::replication::rules::filter::FilterRules;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::fmt;
+
+/// Version of the wire format of the shared events and components, bump it whenever one of them
+/// changes shape.
+///
+/// Replicon's protocol hash only covers which types are registered in which order, so builds
+/// that agree on it may still lay out a type's fields differently and misread each other's bytes.
+/// Every payload starts with this byte, and one tagged with another version is refused.
+pub const WIRE_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A payload tagged with another wire version than the decoder's
+pub struct WireVersionMismatch {
+    pub expected: u8,
+    /// `None` for an empty payload
+    pub found: Option<u8>,
+}
+
+impl fmt::Display for WireVersionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.found {
+            Some(found) => write!(
+                f,
+                "payload has wire version {found}, expected {}",
+                self.expected
+            ),
+            None => write!(
+                f,
+                "payload is empty, expected wire version {}",
+                self.expected
+            ),
+        }
+    }
+}
+
+impl Error for WireVersionMismatch {}
+
+/// Writes `value` after our [`WIRE_VERSION`]
+pub fn encode<T: Serialize>(value: &T, bytes: &mut Vec<u8>) -> Result<()> {
+    encode_as(WIRE_VERSION, value, bytes)
+}
+
+/// Like [`encode`], but tagged with `version`
+pub fn encode_as<T: Serialize>(version: u8, value: &T, bytes: &mut Vec<u8>) -> Result<()> {
+    bytes.push(version);
+    postcard_utils::to_extend_mut(value, bytes)?;
+    Ok(())
+}
+
+/// Reads a value written by [`encode`], refusing one tagged with another version
+pub fn decode<T: DeserializeOwned>(bytes: &mut Bytes) -> Result<T> {
+    decode_as(WIRE_VERSION, bytes)
+}
+
+/// Like [`decode`], but only accepting `version`
+pub fn decode_as<T: DeserializeOwned>(version: u8, bytes: &mut Bytes) -> Result<T> {
+    let found = bytes.try_get_u8().ok();
+    if found != Some(version) {
+        let mismatch = WireVersionMismatch {
+            expected: version,
+            found,
+        };
+        // Replicon only logs refused client events at debug level, and a mismatched peer sends
+        // nothing else.
+        warn_once!(
+            "Refusing payloads from another build, first one was a {}: {mismatch}",
+            ShortName::of::<T>()
+        );
+        return Err(mismatch.into());
+    }
+    Ok(postcard_utils::from_buf(bytes)?)
+}
+
+/// Registers events and components with their payloads tagged by [`WIRE_VERSION`]
+pub trait VersionedAppExt {
+    /// Like [`ClientEventAppExt::add_client_event`]
+    fn add_versioned_client_event<E: Event + Serialize + DeserializeOwned>(
+        &mut self,
+        channel: Channel,
+    ) -> &mut Self;
+
+    /// Like [`ServerEventAppExt::add_server_event`]
+    fn add_versioned_server_event<'a, E>(&mut self, channel: Channel) -> &mut Self
+    where
+        E: Event<Trigger<'a>: Default> + Serialize + DeserializeOwned;
+
+    /// Like [`AppRuleExt::replicate`]
+    fn replicate_versioned<C>(&mut self) -> &mut Self
+    where
+        C: Component<Mutability: MutWrite<C>> + Serialize + DeserializeOwned,
+    {
+        self.replicate_versioned_filtered::<C, ()>()
+    }
+
+    /// Like [`AppRuleExt::replicate_filtered`]
+    fn replicate_versioned_filtered<C, F>(&mut self) -> &mut Self
+    where
+        C: Component<Mutability: MutWrite<C>> + Serialize + DeserializeOwned,
+        F: FilterRules;
+}
+
+impl VersionedAppExt for App {
+    fn add_versioned_client_event<E: Event + Serialize + DeserializeOwned>(
+        &mut self,
+        channel: Channel,
+    ) -> &mut Self {
+        self.add_client_event_with(
+            channel,
+            serialize_client_event,
+            deserialize_client_event::<E>,
+        )
+    }
+
+    fn add_versioned_server_event<'a, E>(&mut self, channel: Channel) -> &mut Self
+    where
+        E: Event<Trigger<'a>: Default> + Serialize + DeserializeOwned,
+    {
+        self.add_server_event_with(
+            channel,
+            serialize_server_event,
+            deserialize_server_event::<E>,
+        )
+    }
+
+    fn replicate_versioned_filtered<C, F>(&mut self) -> &mut Self
+    where
+        C: Component<Mutability: MutWrite<C>> + Serialize + DeserializeOwned,
+        F: FilterRules,
+    {
+        self.replicate_with_filtered::<_, F>(RuleFns::new(
+            serialize_component::<C>,
+            deserialize_component::<C>,
+        ))
+    }
+}
+
+fn serialize_client_event<E: Serialize>(
+    _ctx: &mut ClientSendCtx,
+    event: &E,
+    bytes: &mut Vec<u8>,
+) -> Result<()> {
+    encode(event, bytes)
+}
+
+fn deserialize_client_event<E: DeserializeOwned>(
+    _ctx: &mut ServerReceiveCtx,
+    bytes: &mut Bytes,
+) -> Result<E> {
+    decode(bytes)
+}
+
+fn serialize_server_event<E: Serialize>(
+    _ctx: &mut ServerSendCtx,
+    event: &E,
+    bytes: &mut Vec<u8>,
+) -> Result<()> {
+    encode(event, bytes)
+}
+
+fn deserialize_server_event<E: DeserializeOwned>(
+    _ctx: &mut ClientReceiveCtx,
+    bytes: &mut Bytes,
+) -> Result<E> {
+    decode(bytes)
+}
+
+fn serialize_component<C: Serialize>(
+    _ctx: &SerializeCtx,
+    component: &C,
+    bytes: &mut Vec<u8>,
+) -> Result<()> {
+    encode(component, bytes)
+}
+
+fn deserialize_component<C: DeserializeOwned>(_ctx: &mut WriteCtx, bytes: &mut Bytes) -> Result<C> {
+    decode(bytes)
+}
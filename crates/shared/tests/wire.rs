@@ -0,0 +1,70 @@
+use bevy::prelude::*;
+use bevy_replicon::bytes::Bytes;
+use shared::wire::{WIRE_VERSION, WireVersionMismatch, decode, decode_as, encode, encode_as};
+use shared::{ClientMovementIntent, Player};
+
+#[test]
+fn payloads_round_trip_at_our_version() {
+    let mut bytes = Vec::new();
+    encode(
+        &ClientMovementIntent {
+            seq: 7,
+            direction: Vec2::X,
+        },
+        &mut bytes,
+    )
+    .unwrap();
+    assert_eq!(bytes[0], WIRE_VERSION);
+
+    let intent: ClientMovementIntent = decode(&mut Bytes::from(bytes)).unwrap();
+    assert_eq!(intent.seq, 7);
+    assert_eq!(intent.direction, Vec2::X);
+}
+
+#[test]
+fn older_events_are_refused_by_a_newer_decoder() {
+    let mut bytes = Vec::new();
+    encode_as(
+        1,
+        &ClientMovementIntent {
+            seq: 7,
+            direction: Vec2::X,
+        },
+        &mut bytes,
+    )
+    .unwrap();
+
+    let error = decode_as::<ClientMovementIntent>(2, &mut Bytes::from(bytes)).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<WireVersionMismatch>(),
+        Some(&WireVersionMismatch {
+            expected: 2,
+            found: Some(1),
+        })
+    );
+}
+
+#[test]
+fn older_components_are_refused_by_a_newer_decoder() {
+    let mut bytes = Vec::new();
+    encode_as(1, &Player { network_id: 42 }, &mut bytes).unwrap();
+
+    let Err(error) = decode_as::<Player>(2, &mut Bytes::from(bytes)) else {
+        panic!("a version 1 player was accepted by version 2");
+    };
+    assert!(error.downcast_ref::<WireVersionMismatch>().is_some());
+}
+
+#[test]
+fn empty_payloads_are_refused() {
+    let Err(error) = decode::<Player>(&mut Bytes::new()) else {
+        panic!("an empty player was accepted");
+    };
+    assert_eq!(
+        error.downcast_ref::<WireVersionMismatch>(),
+        Some(&WireVersionMismatch {
+            expected: WIRE_VERSION,
+            found: None,
+        })
+    );
+}